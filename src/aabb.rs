@@ -0,0 +1,94 @@
+use nalgebra::{Point3, Similarity3, Vector3};
+
+use crate::ray::Ray;
+
+/// Thickness given to flat boxes so slab tests against them stay well-defined.
+const MIN_THICKNESS: f32 = 1e-4;
+
+/// Axis-aligned bounding box.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Aabb { min, max }
+    }
+
+    /// Box reaching `radius` beyond the point along every axis.
+    pub fn around(center: Point3<f32>, radius: f32) -> Self {
+        let extent = Vector3::repeat(radius);
+        Aabb::new(center - extent, center + extent)
+    }
+
+    /// Smallest box containing both boxes.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(self.min.inf(&other.min), self.max.sup(&other.max))
+    }
+
+    /// Box grown along any axis it is flat in, so it has at least `MIN_THICKNESS` in every
+    /// dimension.
+    pub fn padded(&self) -> Aabb {
+        let size = self.size();
+        let padding = Vector3::from_fn(|axis, _| if size[axis] < MIN_THICKNESS { MIN_THICKNESS / 2.0 } else { 0.0 });
+        Aabb::new(self.min - padding, self.max + padding)
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    /// Whether the ray passes through the box for some `t` between `t_min` and `t_max`.
+    pub fn hit(&self, ray: &Ray, mut t_min: f32, mut t_max: f32) -> bool {
+        for axis in 0..3 {
+            let inverse_direction = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inverse_direction;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inverse_direction;
+            if inverse_direction < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Index of the axis along which the box is largest.
+    pub fn longest_axis(&self) -> usize {
+        self.size().imax()
+    }
+
+    pub fn contains(&self, point: &Point3<f32>) -> bool {
+        (0..3).all(|axis| self.min[axis] <= point[axis] && point[axis] <= self.max[axis])
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Point3::new(min.x, min.y, min.z),
+            Point3::new(max.x, min.y, min.z),
+            Point3::new(min.x, max.y, min.z),
+            Point3::new(max.x, max.y, min.z),
+            Point3::new(min.x, min.y, max.z),
+            Point3::new(max.x, min.y, max.z),
+            Point3::new(min.x, max.y, max.z),
+            Point3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    /// Box containing this one after it is transformed.
+    pub fn transform(&self, transform: &Similarity3<f32>) -> Aabb {
+        let corners = self.corners().map(|corner| transform * corner);
+        corners[1..].iter()
+            .fold(Aabb::new(corners[0], corners[0]), |aabb, corner| aabb.union(&Aabb::new(*corner, *corner)))
+    }
+}
@@ -0,0 +1,364 @@
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+use nalgebra::{Rotation3, UnitVector3, vector, Vector3};
+
+use crate::picture::{Color, Picture};
+use crate::render::Rng;
+
+const SKY_BOTTOM: Color = Color::WHITE;
+const SKY_TOP: Color = Color::new(0.5, 0.6, 1.0, 1.0);
+
+/// Light arriving from directions in which a ray hits no geometry.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Background {
+    /// The same color in every direction, such as black for scenes lit only by their own lights.
+    SolidColor(Color),
+    /// Blends from `bottom` straight down to `top` straight up.
+    Gradient { bottom: Color, top: Color },
+    /// A gradient sky with a bright disk of light around the sun's direction.
+    Sun {
+        bottom: Color,
+        top: Color,
+        direction: UnitVector3<f32>,
+        /// Angle, in radians, from the center of the disk to its edge.
+        angular_radius: f32,
+        intensity: Color,
+    },
+    /// Turns another background, so that it appears in direction `rotation * d` where it would
+    /// otherwise appear in direction `d`.
+    Rotated { rotation: Rotation3<f32>, background: Box<Background> },
+    /// Linear high dynamic range latitude-longitude image around the scene, with its first row
+    /// straight up.
+    Environment(Arc<Picture<Vec<Color>>>),
+}
+
+impl Background {
+    pub fn sun(direction: Vector3<f32>, angular_radius: f32, intensity: Color) -> Self {
+        Background::Sun {
+            bottom: SKY_BOTTOM,
+            top: SKY_TOP,
+            direction: UnitVector3::new_normalize(direction),
+            angular_radius,
+            intensity,
+        }
+    }
+
+    /// Loads an environment image, such as a Radiance `.hdr` file.
+    pub fn environment_from_path(path: impl AsRef<Path>) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.into_rgb32f();
+        let size = image.dimensions();
+        let pixels = image.pixels()
+            .map(|pixel| Color::new(pixel[0], pixel[1], pixel[2], 1.0))
+            .collect();
+        Ok(Background::Environment(Arc::new(Picture::new(pixels, size))))
+    }
+
+    pub fn rotated(self, rotation: Rotation3<f32>) -> Self {
+        Background::Rotated { rotation, background: Box::new(self) }
+    }
+
+    pub fn sample(&self, direction: &Vector3<f32>) -> Color {
+        let unit_direction = direction.normalize();
+        match self {
+            Background::SolidColor(color) => *color,
+            Background::Gradient { bottom, top } => gradient(&unit_direction, *bottom, *top),
+            Background::Sun { bottom, top, direction, angular_radius, intensity } => {
+                if unit_direction.dot(direction) >= angular_radius.cos() {
+                    *intensity
+                } else {
+                    gradient(&unit_direction, *bottom, *top)
+                }
+            }
+            Background::Rotated { rotation, background } => background.sample(&rotation.inverse_transform_vector(&unit_direction)),
+            Background::Environment(picture) => {
+                let picture = picture.view();
+                let u = 0.5 + unit_direction.z.atan2(unit_direction.x) / (2.0 * PI);
+                let v = unit_direction.y.clamp(-1.0, 1.0).acos() / PI;
+                let column = ((u * picture.width() as f32) as u32).min(picture.width() - 1);
+                let row = ((v * picture.height() as f32) as u32).min(picture.height() - 1);
+                *picture.pixel(column, row)
+            }
+        }
+    }
+}
+
+fn gradient(unit_direction: &Vector3<f32>, bottom: Color, top: Color) -> Color {
+    let t = 0.5 * (unit_direction.y + 1.0);
+    (1.0 - t) * bottom + t * top
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Gradient {
+            bottom: SKY_BOTTOM,
+            top: SKY_TOP,
+        }
+    }
+}
+
+/// Resolution, in longitude and latitude cells, at which a background is integrated over.
+const CONVOLUTION_CELLS: (u32, u32) = (64, 32);
+
+/// A background convolved with a cosine lobe: the light a diffuse surface facing each direction
+/// receives from the whole background, divided by π so a uniform background maps to itself. Stored
+/// as a latitude-longitude image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IrradianceMap {
+    width: u32,
+    height: u32,
+    texels: Vec<Color>,
+}
+
+impl IrradianceMap {
+    /// Precomputes the map at the given resolution. Features of the background smaller than the
+    /// integration cells, such as a small sun, are only partially accounted for.
+    pub fn new(background: &Background, width: u32, height: u32) -> Self {
+        let (columns, rows) = CONVOLUTION_CELLS;
+        let cell_area = (2.0 * PI / columns as f32) * (PI / rows as f32);
+        let incoming: Vec<_> = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let direction = lat_long_direction(column, row, columns, rows);
+                let solid_angle = cell_area * (1.0 - direction.y * direction.y).sqrt();
+                (direction, background.sample(&direction), solid_angle)
+            })
+            .collect();
+
+        let texels = (0..height)
+            .flat_map(|row| (0..width).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let normal = lat_long_direction(column, row, width, height);
+                let (r, g, b) = incoming.iter()
+                    .map(|(direction, color, solid_angle)| (normal.dot(direction).max(0.0) * solid_angle, color))
+                    .fold((0.0, 0.0, 0.0), |(r, g, b), (weight, color)| (r + weight * color.r, g + weight * color.g, b + weight * color.b));
+                Color::new(r / PI, g / PI, b / PI, 1.0)
+            })
+            .collect();
+
+        IrradianceMap { width, height, texels }
+    }
+
+    pub fn sample(&self, normal: &Vector3<f32>) -> Color {
+        let normal = normal.normalize();
+        let longitude = normal.z.atan2(normal.x).rem_euclid(2.0 * PI);
+        let polar = normal.y.clamp(-1.0, 1.0).acos();
+        let column = ((longitude / (2.0 * PI) * self.width as f32) as u32).min(self.width - 1);
+        let row = ((polar / PI * self.height as f32) as u32).min(self.height - 1);
+        self.texels[(row * self.width + column) as usize]
+    }
+}
+
+/// Direction through the center of a cell of a latitude-longitude image, with the first row
+/// straight up.
+fn lat_long_direction(column: u32, row: u32, columns: u32, rows: u32) -> Vector3<f32> {
+    let longitude = (column as f32 + 0.5) / columns as f32 * 2.0 * PI;
+    let polar = (row as f32 + 0.5) / rows as f32 * PI;
+    vector![polar.sin() * longitude.cos(), polar.cos(), polar.sin() * longitude.sin()]
+}
+
+/// Distribution of the light of an environment image over its texels, for drawing directions in
+/// proportion to the light arriving from them, so small bright features such as a sun are found
+/// without relying on diffuse rays happening to hit them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvironmentSampler {
+    width: u32,
+    height: u32,
+    /// Probability of picking each row, accumulated so the last row ends at 1.
+    rows: Vec<f32>,
+    /// Probability of picking each texel within its row, accumulated the same way per row.
+    columns: Vec<f32>,
+    /// Probability of picking each texel.
+    texels: Vec<f32>,
+    /// Turn of the image, as given by any `Background::Rotated` around it.
+    rotation: Rotation3<f32>,
+}
+
+impl EnvironmentSampler {
+    /// Builds the distribution of an environment background, which may be rotated, or `None` for
+    /// other backgrounds and images without any light in them.
+    pub fn new(background: &Background) -> Option<Self> {
+        let picture = match background {
+            Background::Environment(picture) => picture,
+            Background::Rotated { rotation, background } => {
+                let inner = EnvironmentSampler::new(background)?;
+                return Some(EnvironmentSampler { rotation: rotation * inner.rotation, ..inner });
+            }
+            _ => return None,
+        };
+        let picture = picture.view();
+        let (width, height) = (picture.width(), picture.height());
+        // rows near the poles cover less of the sphere.
+        let weights: Vec<f32> = (0..height)
+            .flat_map(|row| (0..width).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let polar = (row as f32 + 0.5) / height as f32 * PI;
+                picture.pixel(column, row).luminance().max(0.0) * polar.sin()
+            })
+            .collect();
+        let total: f32 = weights.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+
+        let texels: Vec<f32> = weights.iter().map(|weight| weight / total).collect();
+        let mut rows = Vec::with_capacity(height as usize);
+        let mut columns = Vec::with_capacity(texels.len());
+        let mut sum = 0.0;
+        for row in texels.chunks(width as usize) {
+            let row_total: f32 = row.iter().sum();
+            sum += row_total;
+            rows.push(sum);
+            let mut row_sum = 0.0;
+            for (column, texel) in row.iter().enumerate() {
+                // rows without light are never picked, but keep a valid distribution.
+                row_sum += if row_total > 0.0 { texel / row_total } else { 1.0 / width as f32 };
+                columns.push(if column + 1 == row.len() { 1.0 } else { row_sum });
+            }
+        }
+        *rows.last_mut().expect("non-empty image") = 1.0;
+
+        Some(EnvironmentSampler { width, height, rows, columns, texels, rotation: Rotation3::identity() })
+    }
+
+    /// Unit direction drawn in proportion to the light arriving from it, along with its probability
+    /// density over solid angle.
+    pub fn sample(&self, rng: &mut Rng) -> (Vector3<f32>, f32) {
+        let pick = |cumulative: &[f32], value: f32| cumulative.partition_point(|&sum| sum <= value).min(cumulative.len() - 1);
+        let row = pick(&self.rows, rng.f32());
+        let row_columns = &self.columns[row * self.width as usize..(row + 1) * self.width as usize];
+        let column = pick(row_columns, rng.f32());
+
+        let u = (column as f32 + rng.f32()) / self.width as f32;
+        let v = (row as f32 + rng.f32()) / self.height as f32;
+        let longitude = (u - 0.5) * 2.0 * PI;
+        let polar = v * PI;
+        let direction = self.rotation * vector![polar.sin() * longitude.cos(), polar.cos(), polar.sin() * longitude.sin()];
+        (direction, self.pdf(&direction))
+    }
+
+    /// Probability density over solid angle of `sample` drawing the direction.
+    pub fn pdf(&self, direction: &Vector3<f32>) -> f32 {
+        let direction = self.rotation.inverse_transform_vector(&direction.normalize());
+        let sin_polar = (1.0 - direction.y * direction.y).max(0.0).sqrt();
+        if sin_polar <= 0.0 {
+            return 0.0;
+        }
+        // the same texel lookup as `Background::sample`.
+        let u = 0.5 + direction.z.atan2(direction.x) / (2.0 * PI);
+        let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+        let column = ((u * self.width as f32) as u32).min(self.width - 1);
+        let row = ((v * self.height as f32) as u32).min(self.height - 1);
+        let texel = self.texels[(row * self.width + column) as usize];
+        texel * (self.width * self.height) as f32 / (2.0 * PI * PI * sin_polar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_samples_cluster_around_the_brightest_texel() {
+        let (width, height) = (16, 8);
+        let bright = (5, 3);
+        let pixels = (0..height)
+            .flat_map(|row| (0..width).map(move |column| (column, row)))
+            .map(|texel| if texel == bright { Color::new(50.0, 50.0, 50.0, 1.0) } else { Color::new(0.01, 0.01, 0.01, 1.0) })
+            .collect();
+        let background = Background::Environment(Arc::new(Picture::new(pixels, (width, height))));
+        let sampler = EnvironmentSampler::new(&background).unwrap();
+
+        let mut rng = Rng::with_seed(3);
+        let hits = (0..1000)
+            .map(|_| sampler.sample(&mut rng).0)
+            .filter(|direction| background.sample(direction).r > 1.0)
+            .count();
+        assert!(hits > 900, "{} of 1000 samples in the brightest texel", hits);
+    }
+
+    #[test]
+    fn environment_pdf_integrates_to_one() {
+        let pixels = (0..32).map(|i| Color::new(i as f32, 1.0, 0.5, 1.0)).collect();
+        let background = Background::Environment(Arc::new(Picture::new(pixels, (8, 4))));
+        let sampler = EnvironmentSampler::new(&background).unwrap();
+
+        let (columns, rows) = (256, 128);
+        let cell_area = (2.0 * PI / columns as f32) * (PI / rows as f32);
+        let integral: f32 = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let direction = lat_long_direction(column, row, columns, rows);
+                sampler.pdf(&direction) * cell_area * (1.0 - direction.y * direction.y).sqrt()
+            })
+            .sum();
+        assert!((integral - 1.0).abs() < 0.01, "{}", integral);
+    }
+
+    #[test]
+    fn sun_disk_gives_its_intensity_and_sky_around_it() {
+        let intensity = Color::new(50.0, 45.0, 40.0, 1.0);
+        let sun = Background::sun(vector![1.0, 1.0, 0.0], 0.02, intensity);
+        let sky = Background::default();
+
+        assert_eq!(sun.sample(&vector![1.0, 1.0, 0.0]), intensity);
+        let off = vector![1.0, 1.1, 0.0];
+        assert_eq!(sun.sample(&off), sky.sample(&off));
+    }
+
+    #[test]
+    fn rotating_environment_shifts_sampled_texel() {
+        // a quarter of the way around per column.
+        let pixels = (0..2).flat_map(|_| (0..4).map(|column| Color::new(column as f32, 0.0, 0.0, 1.0))).collect();
+        let environment = Background::Environment(Arc::new(Picture::new(pixels, (4, 2))));
+        let rotated = environment.clone().rotated(Rotation3::from_axis_angle(&Vector3::y_axis(), PI / 2.0));
+
+        let direction = Vector3::x();
+        assert_eq!(environment.sample(&direction).r, 2.0);
+        assert_eq!(rotated.sample(&direction).r, 3.0);
+    }
+
+    #[test]
+    fn irradiance_of_a_uniform_background_is_that_background() {
+        let sky = Color::new(0.2, 0.5, 0.9, 1.0);
+        let irradiance = IrradianceMap::new(&Background::SolidColor(sky), 8, 4);
+
+        for normal in [Vector3::y(), -Vector3::y(), vector![1.0, 0.3, -0.5]] {
+            let texel = irradiance.sample(&normal);
+            for (channel, expected) in [(texel.r, sky.r), (texel.g, sky.g), (texel.b, sky.b)] {
+                assert!((channel - expected).abs() < 0.01 * expected, "{:?} for {}", texel, normal);
+            }
+        }
+    }
+
+    #[test]
+    fn vertical_rays_sample_the_top_and_bottom_rows() {
+        // rows of environment images start from the top.
+        let rows = [Color::new(20.0, 18.0, 15.0, 1.0), Color::new(0.5, 0.6, 0.9, 1.0), Color::new(0.1, 0.1, 0.1, 1.0)];
+        let pixels = rows.iter().flat_map(|row| [*row; 4]).collect();
+        let background = Background::Environment(Arc::new(Picture::new(pixels, (4, 3))));
+
+        assert_eq!(background.sample(&Vector3::y()), rows[0]);
+        assert_eq!(background.sample(&-Vector3::y()), rows[2]);
+        assert_eq!(background.sample(&vector![1.0, 0.1, -0.5]), rows[1]);
+    }
+
+    #[test]
+    fn rotated_environment_samples_follow_the_rotation() {
+        let (width, height) = (16, 8);
+        let pixels = (0..height)
+            .flat_map(|row| (0..width).map(move |column| (column, row)))
+            .map(|texel| if texel == (5, 3) { Color::new(50.0, 50.0, 50.0, 1.0) } else { Color::new(0.01, 0.01, 0.01, 1.0) })
+            .collect();
+        let background = Background::Environment(Arc::new(Picture::new(pixels, (width, height))))
+            .rotated(Rotation3::from_axis_angle(&Vector3::x_axis(), 1.0));
+        let sampler = EnvironmentSampler::new(&background).expect("rotated environment sampler");
+
+        let mut rng = Rng::with_seed(3);
+        let samples: Vec<_> = (0..1000).map(|_| sampler.sample(&mut rng)).collect();
+        let hits = samples.iter().filter(|(direction, _)| background.sample(direction).r > 1.0).count();
+        assert!(hits > 900, "{} of 1000 samples in the brightest texel", hits);
+        assert!(samples.iter().all(|(direction, pdf)| (sampler.pdf(direction) - pdf).abs() <= 1e-3 * pdf));
+    }
+}
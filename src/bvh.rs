@@ -0,0 +1,283 @@
+use std::fs;
+use std::io;
+use std::iter::once;
+use std::path::Path;
+
+use float_ord::FloatOrd;
+use nalgebra::Point3;
+
+use crate::aabb::Aabb;
+use crate::object::Object;
+
+/// Identifies files written by `Bvh::save`.
+const MAGIC: &[u8; 8] = b"RTBVH\0\0\x01";
+
+/// Node of a `Bvh`, referring to other nodes and to primitives by index.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BvhNode {
+    Leaf { primitive: usize },
+    Branch { bounds: Aabb, left: usize, right: usize },
+}
+
+/// Bounding volume hierarchy over a list of primitives, kept apart from the primitives themselves
+/// so an expensive build can be saved to disk and reused for the same list.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bvh {
+    /// Nodes of the tree, with the root first and every child after its parent.
+    nodes: Vec<BvhNode>,
+    /// Primitives without bounds, which are kept next to the tree.
+    unbounded: Vec<usize>,
+    primitive_count: usize,
+}
+
+impl Bvh {
+    /// Splits the bounded primitives in half along the longest axis of their bounds until each node
+    /// holds one.
+    pub fn build(objects: &[Object]) -> Self {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for (index, object) in objects.iter().enumerate() {
+            match object.bounding_box() {
+                Some(bounds) => bounded.push((bounds, index)),
+                None => unbounded.push(index),
+            }
+        }
+
+        let mut nodes = Vec::new();
+        if !bounded.is_empty() {
+            build_node(&mut nodes, bounded);
+        }
+        Bvh { nodes, unbounded, primitive_count: objects.len() }
+    }
+
+    pub fn nodes(&self) -> &[BvhNode] {
+        &self.nodes
+    }
+
+    pub fn primitive_count(&self) -> usize {
+        self.primitive_count
+    }
+
+    /// Arranges the objects the hierarchy was built for into its tree.
+    ///
+    /// # Panics
+    /// If the number of objects differs from the number the hierarchy was built for.
+    pub fn object(&self, objects: Vec<Object>) -> Object {
+        assert_eq!(objects.len(), self.primitive_count, "objects do not match the hierarchy");
+        let mut objects: Vec<_> = objects.into_iter().map(Some).collect();
+        let unbounded: Vec<_> = self.unbounded.iter()
+            .map(|&index| objects[index].take().expect("primitive referenced once"))
+            .collect();
+
+        match (self.nodes.is_empty(), unbounded.is_empty()) {
+            (true, _) => Object::list(unbounded),
+            (false, true) => self.node_object(0, &mut objects),
+            (false, false) => Object::list(once(self.node_object(0, &mut objects)).chain(unbounded).collect()),
+        }
+    }
+
+    fn node_object(&self, index: usize, objects: &mut [Option<Object>]) -> Object {
+        match self.nodes[index] {
+            BvhNode::Leaf { primitive } => objects[primitive].take().expect("primitive referenced once"),
+            BvhNode::Branch { bounds, left, right } => Object::Bvh {
+                bounds,
+                left: Box::new(self.node_object(left, objects)),
+                right: Box::new(self.node_object(right, objects)),
+            },
+        }
+    }
+
+    /// Writes the hierarchy in a compact binary format.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut bytes = MAGIC.to_vec();
+        let mut push = |value: usize| bytes.extend((value as u64).to_le_bytes());
+        push(self.primitive_count);
+        push(self.unbounded.len());
+        self.unbounded.iter().for_each(|&index| push(index));
+        push(self.nodes.len());
+        for node in &self.nodes {
+            match *node {
+                BvhNode::Leaf { primitive } => {
+                    bytes.push(0);
+                    bytes.extend((primitive as u64).to_le_bytes());
+                }
+                BvhNode::Branch { bounds, left, right } => {
+                    bytes.push(1);
+                    for value in bounds.min.iter().chain(bounds.max.iter()) {
+                        bytes.extend(value.to_le_bytes());
+                    }
+                    bytes.extend((left as u64).to_le_bytes());
+                    bytes.extend((right as u64).to_le_bytes());
+                }
+            }
+        }
+        fs::write(path, bytes)
+    }
+
+    /// Reads a hierarchy written by `save`, failing unless it is well-formed and was built for
+    /// `primitive_count` primitives.
+    pub fn load(path: impl AsRef<Path>, primitive_count: usize) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let bvh = Reader { bytes: &bytes }.bvh().ok_or_else(|| invalid("malformed hierarchy"))?;
+        if bvh.primitive_count != primitive_count {
+            return Err(invalid(&format!("hierarchy is for {} primitives, not {}", bvh.primitive_count, primitive_count)));
+        }
+        bvh.validate().then_some(bvh).ok_or_else(|| invalid("inconsistent hierarchy"))
+    }
+
+    /// Whether walking the tree from its root reaches every node exactly once, and together with
+    /// the unbounded primitives references every primitive exactly once.
+    fn validate(&self) -> bool {
+        let mut referenced = vec![false; self.primitive_count];
+        let mut reference = |index: usize| index < self.primitive_count && !std::mem::replace(&mut referenced[index], true);
+        let mut reached = vec![false; self.nodes.len()];
+        let mut pending = if self.nodes.is_empty() { vec![] } else { vec![0] };
+        while let Some(index) = pending.pop() {
+            if index >= self.nodes.len() || std::mem::replace(&mut reached[index], true) {
+                return false;
+            }
+            match self.nodes[index] {
+                BvhNode::Leaf { primitive } => {
+                    if !reference(primitive) {
+                        return false;
+                    }
+                }
+                BvhNode::Branch { left, right, .. } => pending.extend([left, right]),
+            }
+        }
+        reached.iter().all(|&reached| reached)
+            && self.unbounded.iter().all(|&index| reference(index))
+            && referenced.iter().all(|&referenced| referenced)
+    }
+}
+
+fn build_node(nodes: &mut Vec<BvhNode>, mut primitives: Vec<(Aabb, usize)>) -> usize {
+    let index = nodes.len();
+    if primitives.len() == 1 {
+        nodes.push(BvhNode::Leaf { primitive: primitives[0].1 });
+        return index;
+    }
+
+    let bounds = primitives.iter()
+        .map(|(bounds, _)| *bounds)
+        .reduce(|a, b| a.union(&b))
+        .expect("bounded primitives");
+    let axis = bounds.longest_axis();
+    primitives.sort_by_key(|(bounds, _)| FloatOrd(bounds.center()[axis]));
+    let right = primitives.split_off(primitives.len() / 2);
+    // replaced once the children's indices are known.
+    nodes.push(BvhNode::Leaf { primitive: 0 });
+    let left = build_node(nodes, primitives);
+    let right = build_node(nodes, right);
+    nodes[index] = BvhNode::Branch { bounds, left, right };
+    index
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, tail) = self.bytes.split_first_chunk::<N>()?;
+        self.bytes = tail;
+        Some(*head)
+    }
+
+    fn index(&mut self) -> Option<usize> {
+        usize::try_from(u64::from_le_bytes(self.take()?)).ok()
+    }
+
+    fn point(&mut self) -> Option<Point3<f32>> {
+        let mut point = Point3::origin();
+        for value in point.iter_mut() {
+            *value = f32::from_le_bytes(self.take()?);
+        }
+        Some(point)
+    }
+
+    fn bvh(mut self) -> Option<Bvh> {
+        if &self.take::<8>()? != MAGIC {
+            return None;
+        }
+        let primitive_count = self.index()?;
+        let unbounded = (0..self.index()?).map(|_| self.index()).collect::<Option<Vec<_>>>()?;
+        let nodes = (0..self.index()?)
+            .map(|_| match self.take::<1>()? {
+                [0] => Some(BvhNode::Leaf { primitive: self.index()? }),
+                [1] => Some(BvhNode::Branch {
+                    bounds: Aabb::new(self.point()?, self.point()?),
+                    left: self.index()?,
+                    right: self.index()?,
+                }),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        self.bytes.is_empty().then_some(Bvh { nodes, unbounded, primitive_count })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{point, Vector3};
+
+    use crate::material::Material;
+    use crate::picture::Color;
+    use crate::ray::Ray;
+    use crate::render::Rng;
+
+    use super::*;
+
+    #[test]
+    fn loaded_hierarchy_gives_the_same_hits() {
+        let mut rng = Rng::with_seed(14);
+        let mut objects: Vec<_> = (0..50)
+            .map(|_| Object::sphere(point![rng.f32() * 10.0, rng.f32(), rng.f32() * 10.0], 0.3, Material::lambert(Color::WHITE)))
+            .collect();
+        objects.push(Object::plane(Point3::origin(), Vector3::y_axis(), Material::lambert(Color::WHITE)));
+        let built = Bvh::build(&objects);
+
+        let path = std::env::temp_dir().join(format!("ray-tracing-{}-bvh", std::process::id()));
+        built.save(&path).unwrap();
+        let loaded = Bvh::load(&path, objects.len());
+        let wrong_count = Bvh::load(&path, objects.len() - 1);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, built);
+        assert_eq!(wrong_count.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let (built, loaded) = (built.object(objects.clone()), loaded.object(objects));
+        for _ in 0..200 {
+            let ray = Ray::new(point![rng.f32() * 10.0, 5.0, rng.f32() * 10.0], Vector3::new(rng.f32() - 0.5, -1.0, rng.f32() - 0.5));
+            assert_eq!(built.hit(&ray, 0.0..).map(|hit| hit.point), loaded.hit(&ray, 0.0..).map(|hit| hit.point));
+        }
+    }
+
+
+    #[test]
+    fn hierarchies_not_reaching_every_node_once_fail_to_load() {
+        let bounds = Aabb::new(Point3::origin(), point![1.0, 1.0, 1.0]);
+        let shared_child = Bvh {
+            nodes: vec![BvhNode::Branch { bounds, left: 1, right: 1 }, BvhNode::Leaf { primitive: 0 }],
+            unbounded: vec![],
+            primitive_count: 1,
+        };
+        let unreachable_leaf = Bvh {
+            nodes: vec![BvhNode::Leaf { primitive: 0 }, BvhNode::Leaf { primitive: 1 }],
+            unbounded: vec![],
+            primitive_count: 2,
+        };
+
+        for (name, bvh) in [("shared", shared_child), ("unreachable", unreachable_leaf)] {
+            let path = std::env::temp_dir().join(format!("ray-tracing-{}-bvh-{}", std::process::id(), name));
+            bvh.save(&path).unwrap();
+            let loaded = Bvh::load(&path, bvh.primitive_count);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.unwrap_err().kind(), io::ErrorKind::InvalidData, "{}", name);
+        }
+    }
+}
@@ -1,6 +1,11 @@
-use nalgebra::{Point2, Point3, RealField, Rotation3, UnitVector3, vector, Vector3};
-use crate::ray::Ray;
-use crate::render::random_vec_in_unit_disk;
+use std::f32::consts::PI;
+use std::iter::repeat_with;
+
+use nalgebra::{Point2, Point3, RealField, Rotation3, UnitVector3, vector, Vector2, Vector3};
+use crate::aabb::Aabb;
+use crate::object::Object;
+use crate::ray::{Ray, RayKind};
+use crate::render::{random_vec_in_unit_disk, Rng};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum CameraDirection {
@@ -43,13 +48,77 @@ impl<F: RealField> From<RollPitchYaw<F>> for Rotation3<F> {
     }
 }
 
+/// Shape of the lens opening, which determines the shape of out-of-focus highlights.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ApertureShape {
+    Circle,
+    Polygon { blades: u32, rotation: f32 },
+}
+
+impl ApertureShape {
+    /// Whether the offset lies within the aperture, scaled to a unit circumradius.
+    pub fn contains(&self, offset: &Vector2<f32>) -> bool {
+        let distance = offset.magnitude();
+        match *self {
+            ApertureShape::Circle => distance <= 1.0,
+            ApertureShape::Polygon { blades, rotation } => {
+                let sector = 2.0 * PI / blades as f32;
+                let angle = (offset.y.atan2(offset.x) - rotation).rem_euclid(sector);
+                distance * (angle - sector / 2.0).cos() <= (sector / 2.0).cos()
+            }
+        }
+    }
+
+    /// Random point on the lens, within a unit circumradius in the XY plane.
+    pub fn sample(&self, rng: &mut Rng) -> Vector3<f32> {
+        repeat_with(|| random_vec_in_unit_disk(rng))
+            .find(|vec| self.contains(&vec.xy()))
+            .expect("infinite iterator")
+    }
+}
+
+/// How the scene is projected onto the image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    /// Rays fan out from the camera position according to the field of view.
+    Perspective,
+    /// Parallel rays leave a view plane of the given height, ignoring field of view and aperture.
+    Orthographic { height: f32 },
+}
+
+/// Photographic lens the aperture is sized from, as `focal_length / f_stop`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Lens {
+    pub focal_length: f32,
+    pub f_stop: f32,
+}
+
+impl Lens {
+    /// Diameter of the aperture.
+    pub fn aperture(&self) -> f32 {
+        self.focal_length / self.f_stop
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Camera {
     pub position: Point3<f32>,
     pub direction: CameraDirection,
+    pub projection: Projection,
     pub fov_deg: f32,
     pub aperture: f32,
+    /// Lens the aperture is derived from instead of `aperture`, when set, so it follows later
+    /// changes of the f-number or focal length.
+    pub lens: Option<Lens>,
+    pub aperture_shape: ApertureShape,
     pub focus_distance: f32,
+    /// Times the shutter opens and closes at. The samples of a pixel are cast at times stratified
+    /// over the interval, each at a random moment within its own equal part of it.
+    pub time0: f32,
+    pub time1: f32,
+    /// Distance in front of the camera at which rays start, so the camera can see out of objects
+    /// it is inside of.
+    pub near: f32,
 }
 
 impl Camera {
@@ -63,35 +132,103 @@ impl Camera {
         Camera {
             position,
             direction,
+            projection: Projection::Perspective,
             fov_deg,
             aperture,
+            lens: None,
+            aperture_shape: ApertureShape::Circle,
             focus_distance,
+            time0: 0.0,
+            time1: 0.0,
+            near: 0.0,
+        }
+    }
+
+    /// Camera looking at the whole of the object from above and to the side, with its bounds
+    /// filling as much of an image of the given aspect ratio as possible.
+    pub fn frame_scene(object: &Object, aspect_ratio: f32) -> Camera {
+        let bounds = object.bounding_box().unwrap_or(Aabb::around(Point3::origin(), 1.0));
+        let center = bounds.center();
+        let radius = (bounds.size().magnitude() / 2.0).max(f32::EPSILON);
+
+        let half_vertical = DEFAULT_FOV_DEG.to_radians() / 2.0;
+        let half_horizontal = (half_vertical.tan() * aspect_ratio).atan();
+        // distance at which a sphere around the bounds fits the narrower dimension of the view.
+        let distance = radius / half_vertical.min(half_horizontal).sin();
+
+        let position = center + vector![1.0, 0.5, 1.0].normalize() * distance;
+        CameraBuilder::new(position)
+            .look_at(center)
+            .build()
+    }
+
+    pub fn builder(position: Point3<f32>) -> CameraBuilder {
+        CameraBuilder::new(position)
+    }
+
+    /// Focuses on the point the camera looks at, if it looks at one.
+    pub fn focus_on_lookat(&mut self) {
+        if let CameraDirection::LookAt { look_at, .. } = &self.direction {
+            self.focus_distance = (self.position - look_at).magnitude();
         }
     }
 
+    /// Sizes the aperture from a focal length and f-number, as on a photographic lens.
+    pub fn set_f_stop(&mut self, focal_length: f32, f_stop: f32) {
+        self.lens = Some(Lens { focal_length, f_stop });
+    }
+
+    /// Diameter of the aperture, from the lens if there is one.
+    pub fn aperture_diameter(&self) -> f32 {
+        self.lens.map_or(self.aperture, |lens| lens.aperture())
+    }
+
+    /// Radius, in pixels, of the blur disk a point at the given distance from the camera is spread
+    /// over when rendered at the given image height.
+    pub fn circle_of_confusion(&self, depth: f32, image_height: f32) -> f32 {
+        let lens_radius = self.aperture_diameter() / 2.0;
+        let h = (self.fov_deg.to_radians() / 2.0).tan();
+        let pixels_per_unit = image_height / (2.0 * h * self.focus_distance);
+        lens_radius * (depth - self.focus_distance).abs() / depth * pixels_per_unit
+    }
+
     pub fn viewport(&self, width: u32, height: u32) -> Viewport {
         let image_width = width as f32;
         let image_height = height as f32;
 
-        let theta = self.fov_deg.to_radians();
-        let h = (theta / 2.0).tan();
-
         let aspect_ratio = image_width / image_height;
-        let vertical = 2.0 * h;
-        let horizontal = vertical * aspect_ratio;
-
         let rotation = self.direction.direction(&self.position);
-
-        let vertical = rotation * vector![0.0, vertical, 0.0] * self.focus_distance;
-        let horizontal = rotation * vector![horizontal, 0.0, 0.0] * self.focus_distance;
-        let depth = rotation * vector![0.0, 0.0, self.focus_distance];
         let lens_u = rotation * Vector3::x();
         let lens_v = rotation * Vector3::y();
+        let forward = rotation * -Vector3::z();
+
+        let (vertical, horizontal, lower_left_corner, lens_radius) = match self.projection {
+            Projection::Perspective => {
+                let theta = self.fov_deg.to_radians();
+                let h = (theta / 2.0).tan();
 
-        let lower_left_corner = self.position - vertical / 2.0 - horizontal / 2.0 - depth;
-        let lens_radius = self.aperture / 2.0;
+                let vertical = 2.0 * h;
+                let horizontal = vertical * aspect_ratio;
+
+                let vertical = rotation * vector![0.0, vertical, 0.0] * self.focus_distance;
+                let horizontal = rotation * vector![horizontal, 0.0, 0.0] * self.focus_distance;
+                let depth = rotation * vector![0.0, 0.0, self.focus_distance];
+
+                let lower_left_corner = self.position - vertical / 2.0 - horizontal / 2.0 - depth;
+                (vertical, horizontal, lower_left_corner, self.aperture_diameter() / 2.0)
+            }
+            Projection::Orthographic { height } => {
+                let vertical = rotation * vector![0.0, height, 0.0];
+                let horizontal = rotation * vector![height * aspect_ratio, 0.0, 0.0];
+                let lower_left_corner = self.position - vertical / 2.0 - horizontal / 2.0;
+                (vertical, horizontal, lower_left_corner, 0.0)
+            }
+        };
 
         Viewport {
+            projection: self.projection,
+            rotation,
+            forward,
             origin: self.position,
             image_width,
             image_height,
@@ -101,11 +238,131 @@ impl Camera {
             lens_u,
             lens_v,
             lens_radius,
+            focus_distance: self.focus_distance,
+            aperture_shape: self.aperture_shape,
+            time0: self.time0,
+            time1: self.time1,
+            near: self.near,
+        }
+    }
+}
+
+/// Camera circling `center` around the vertical axis through it, `radius` away and `height` above
+/// it, always looking at it. An angle of zero places the camera on the positive z side.
+pub fn turntable_camera(center: Point3<f32>, radius: f32, height: f32, angle_deg: f32) -> Camera {
+    let (sin, cos) = angle_deg.to_radians().sin_cos();
+    CameraBuilder::new(center + vector![radius * sin, height, radius * cos])
+        .look_at(center)
+        .build()
+}
+
+/// Vertical field of view of cameras made by `CameraBuilder` unless set otherwise.
+pub const DEFAULT_FOV_DEG: f32 = 45.0;
+
+/// Builds a `Camera` from named settings. Unless told otherwise it is a pinhole camera looking at
+/// the world origin, focused on whatever it looks at.
+#[derive(Clone, Debug)]
+pub struct CameraBuilder {
+    position: Point3<f32>,
+    direction: CameraDirection,
+    projection: Projection,
+    fov_deg: f32,
+    aperture: f32,
+    lens: Option<Lens>,
+    aperture_shape: ApertureShape,
+    focus_distance: Option<f32>,
+    time0: f32,
+    time1: f32,
+    near: f32,
+}
+
+impl CameraBuilder {
+    pub fn new(position: Point3<f32>) -> Self {
+        CameraBuilder {
+            position,
+            direction: CameraDirection::LookAt { look_at: Point3::origin(), up: Vector3::y_axis() },
+            projection: Projection::Perspective,
+            fov_deg: DEFAULT_FOV_DEG,
+            aperture: 0.0,
+            lens: None,
+            aperture_shape: ApertureShape::Circle,
+            focus_distance: None,
+            time0: 0.0,
+            time1: 0.0,
+            near: 0.0,
+        }
+    }
+
+    pub fn look_at(self, look_at: Point3<f32>) -> Self {
+        self.direction(CameraDirection::LookAt { look_at, up: Vector3::y_axis() })
+    }
+
+    pub fn direction(self, direction: CameraDirection) -> Self {
+        CameraBuilder { direction, ..self }
+    }
+
+    pub fn projection(self, projection: Projection) -> Self {
+        CameraBuilder { projection, ..self }
+    }
+
+    pub fn fov_deg(self, fov_deg: f32) -> Self {
+        CameraBuilder { fov_deg, ..self }
+    }
+
+    pub fn aperture(self, aperture: f32) -> Self {
+        CameraBuilder { aperture, ..self }
+    }
+
+    /// Sizes the aperture from a focal length and f-number. See `Camera::set_f_stop`.
+    pub fn f_stop(self, focal_length: f32, f_stop: f32) -> Self {
+        CameraBuilder { lens: Some(Lens { focal_length, f_stop }), ..self }
+    }
+
+    pub fn aperture_shape(self, aperture_shape: ApertureShape) -> Self {
+        CameraBuilder { aperture_shape, ..self }
+    }
+
+    pub fn focus_distance(self, focus_distance: f32) -> Self {
+        CameraBuilder { focus_distance: Some(focus_distance), ..self }
+    }
+
+    /// Keeps the shutter open from `time0` to `time1`, blurring geometry moving in between.
+    pub fn shutter(self, time0: f32, time1: f32) -> Self {
+        CameraBuilder { time0, time1, ..self }
+    }
+
+    pub fn near(self, near: f32) -> Self {
+        CameraBuilder { near, ..self }
+    }
+
+    pub fn build(self) -> Camera {
+        // without a target to focus on, the focus distance only matters once an aperture is set.
+        let focus_distance = self.focus_distance.unwrap_or(match &self.direction {
+            CameraDirection::LookAt { look_at, .. } => (look_at - self.position).magnitude(),
+            CameraDirection::Rotation(_) => 1.0,
+        });
+        Camera {
+            position: self.position,
+            direction: self.direction,
+            projection: self.projection,
+            fov_deg: self.fov_deg,
+            aperture: self.aperture,
+            lens: self.lens,
+            aperture_shape: self.aperture_shape,
+            focus_distance,
+            time0: self.time0,
+            time1: self.time1,
+            near: self.near,
         }
     }
 }
 
 pub struct Viewport {
+    pub projection: Projection,
+    /// Orientation of the camera, taking its local axes to world space.
+    pub rotation: Rotation3<f32>,
+    /// Direction the camera is looking in.
+    pub forward: Vector3<f32>,
     pub origin: Point3<f32>,
     pub image_width: f32,
     pub image_height: f32,
@@ -115,18 +372,177 @@ pub struct Viewport {
     pub lens_u: Vector3<f32>,
     pub lens_v: Vector3<f32>,
     pub lens_radius: f32,
+    pub focus_distance: f32,
+    pub aperture_shape: ApertureShape,
+    pub time0: f32,
+    pub time1: f32,
+    /// Depth along `forward` at which rays start. Distances along rays are measured from there.
+    pub near: f32,
 }
 
 impl Viewport {
-    pub fn emit_ray(&self, p: &Point2<f32>) -> Ray {
-        let rd = self.lens_radius * random_vec_in_unit_disk();
+    /// Radius, in pixels, of the blur disk a point at the given distance from the camera is spread
+    /// over. See `Camera::circle_of_confusion`.
+    pub fn circle_of_confusion(&self, depth: f32) -> f32 {
+        let pixels_per_unit = self.image_height / self.vertical.magnitude();
+        self.lens_radius * (depth - self.focus_distance).abs() / depth * pixels_per_unit
+    }
+
+    /// Ray through the point on the image, with the lens position and time drawn from `rng`. The
+    /// time is drawn from the `sample`th of `samples` equal parts of the shutter interval, so the
+    /// samples taken in a pixel spread evenly over it.
+    pub fn emit_ray(&self, p: &Point2<f32>, sample: usize, samples: usize, rng: &mut Rng) -> Ray {
+        let target = self.lower_left_corner + p.x * self.horizontal + p.y * self.vertical;
+        let time = if self.time1 > self.time0 {
+            let fraction = ((sample as f32 + rng.f32()) / samples as f32).min(1.0);
+            self.time0 + fraction * (self.time1 - self.time0)
+        } else {
+            self.time0
+        };
+        if let Projection::Orthographic { .. } = self.projection {
+            return Ray::new(target + self.forward * self.near, self.forward).with_kind(RayKind::Camera).with_time(time);
+        }
+
+        let rd = self.lens_radius * self.aperture_shape.sample(rng);
         let offset = self.lens_u * rd.x + self.lens_v * rd.y;
 
-        Ray::new(
-            self.origin + offset,
-            self.lower_left_corner +
-                p.x * self.horizontal +
-                p.y * self.vertical - self.origin - offset,
-        )
+        let origin = self.origin + offset;
+        let direction = target - origin;
+        // start the ray where it crosses the near plane.
+        let origin = origin + direction * (self.near / direction.dot(&self.forward));
+        Ray::new(origin, direction)
+            .with_kind(RayKind::Camera)
+            .with_time(time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::point;
+
+    use crate::material::Material;
+    use crate::picture::Color;
+
+    use super::*;
+
+    #[test]
+    fn halving_f_stop_doubles_lens_radius() {
+        let mut camera = CameraBuilder::new(point![0.0, 0.0, 5.0]).build();
+        camera.set_f_stop(0.05, 4.0);
+        let radius = camera.viewport(16, 16).lens_radius;
+        camera.set_f_stop(0.05, 2.0);
+        assert!((camera.viewport(16, 16).lens_radius - 2.0 * radius).abs() < 1e-6);
+    }
+
+    #[test]
+    fn builder_defaults_to_a_pinhole_camera() {
+        let position = point![3.0, 4.0, 0.0];
+        let camera = CameraBuilder::new(position).look_at(Point3::origin()).build();
+        assert_eq!(camera.aperture, 0.0);
+        assert_eq!(camera.fov_deg, DEFAULT_FOV_DEG);
+        assert!((camera.focus_distance - 5.0).abs() < 1e-6);
+
+        // every ray through the center leaves the same point towards the target.
+        let viewport = camera.viewport(8, 8);
+        let mut rng = Rng::with_seed(0);
+        for _ in 0..8 {
+            let ray = viewport.emit_ray(&point![0.5, 0.5], 0, 1, &mut rng);
+            assert!((ray.origin - position).magnitude() < 1e-5);
+            assert!((ray.direction.normalize() - (-position.coords / 5.0)).magnitude() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn framed_unit_box_is_within_the_frustum() {
+        let corner = |c: f32| Object::sphere(point![c, c, c], 1e-3, Material::lambert(Color::WHITE));
+        let scene = Object::list(vec![corner(-0.5), corner(0.5)]);
+
+        for aspect_ratio in [0.5, 1.0, 2.0] {
+            let camera = Camera::frame_scene(&scene, aspect_ratio);
+            let rotation = camera.direction.direction(&camera.position);
+            let half_vertical = (camera.fov_deg.to_radians() / 2.0).tan();
+            let half_horizontal = half_vertical * aspect_ratio;
+            assert!((camera.focus_distance - (camera.position - Point3::origin()).magnitude()).abs() < 1e-4);
+
+            for x in [-0.5, 0.5] {
+                for y in [-0.5, 0.5] {
+                    for z in [-0.5, 0.5] {
+                        // in camera space the view looks down negative z.
+                        let local = rotation.inverse() * (point![x, y, z] - camera.position);
+                        let depth = -local.z;
+                        assert!(depth > 0.0);
+                        assert!(local.x.abs() <= half_horizontal * depth, "{aspect_ratio}: x {x} {y} {z}");
+                        assert!(local.y.abs() <= half_vertical * depth, "{aspect_ratio}: y {x} {y} {z}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn circle_of_confusion_grows_away_from_the_focus_plane() {
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0])
+            .fov_deg(90.0)
+            .aperture(0.2)
+            .focus_distance(5.0)
+            .build();
+
+        assert_eq!(camera.circle_of_confusion(5.0, 100.0), 0.0);
+        // 10 pixels per unit at the focus plane, times a lens radius of 0.1 scaled by 5 / 10.
+        assert!((camera.circle_of_confusion(10.0, 100.0) - 0.5).abs() < 1e-5);
+        assert!(camera.circle_of_confusion(2.5, 100.0) > camera.circle_of_confusion(10.0, 100.0));
+        assert!((camera.viewport(100, 100).circle_of_confusion(10.0) - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn turntable_half_turn_is_opposite_at_the_same_height() {
+        let center = point![1.0, 0.5, -2.0];
+        let front = turntable_camera(center, 4.0, 1.5, 0.0);
+        let back = turntable_camera(center, 4.0, 1.5, 180.0);
+
+        assert!((front.position - point![1.0, 2.0, 2.0]).magnitude() < 1e-5);
+        assert!((back.position - point![1.0, 2.0, -6.0]).magnitude() < 1e-5);
+        let forward = |camera: &Camera| camera.direction.direction(&camera.position) * -Vector3::z();
+        assert!((forward(&front) - (center - front.position).normalize()).magnitude() < 1e-5);
+        assert!((forward(&back) - (center - back.position).normalize()).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn focusing_on_the_look_at_point_uses_its_distance() {
+        let mut camera = CameraBuilder::new(point![0.0, 6.0, 8.0]).focus_distance(1.0).build();
+        camera.focus_on_lookat();
+        assert!((camera.focus_distance - 10.0).abs() < 1e-5);
+
+        let mut rotated = CameraBuilder::new(point![0.0, 6.0, 8.0]).direction(CameraDirection::Rotation(Rotation3::identity())).focus_distance(3.0).build();
+        rotated.focus_on_lookat();
+        assert_eq!(rotated.focus_distance, 3.0);
+    }
+
+    #[test]
+    fn aperture_follows_later_lens_changes() {
+        let mut camera = CameraBuilder::new(point![0.0, 0.0, 5.0]).aperture(1.0).f_stop(0.05, 2.0).build();
+        assert!((camera.aperture_diameter() - 0.025).abs() < 1e-6);
+
+        let lens = camera.lens.as_mut().unwrap();
+        lens.f_stop = 1.0;
+        lens.focal_length = 0.1;
+        assert!((camera.viewport(16, 16).lens_radius - 0.05).abs() < 1e-6);
+
+        camera.lens = None;
+        assert_eq!(camera.aperture_diameter(), 1.0);
+    }
+
+
+    #[test]
+    fn primary_rays_start_past_the_near_plane() {
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0]).fov_deg(90.0).near(2.0).build();
+        let viewport = camera.viewport(8, 8);
+        let mut rng = Rng::with_seed(0);
+
+        for p in [point![0.5, 0.5], point![0.0, 0.0], point![1.0, 0.25]] {
+            let ray = viewport.emit_ray(&p, 0, 1, &mut rng);
+            // rays start on the plane two units in front of the camera, wherever they point.
+            assert!((ray.origin.z - 3.0).abs() < 1e-5, "{:?} starts at {:?}", p, ray.origin);
+        }
     }
 }
@@ -1,6 +1,7 @@
 use std::iter::once;
 use std::marker::PhantomData;
 use std::mem::size_of;
+use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
 
 use bytemuck::bytes_of;
@@ -9,7 +10,7 @@ use bytemuck_derive::{Pod, Zeroable};
 use log::debug;
 use nalgebra::{point, Point2, Point3};
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
-use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferUsages, ColorTargetState, ColorWrites, CommandEncoderDescriptor, DeviceDescriptor, Extent3d, FragmentState, ImageCopyTexture, ImageDataLayout, include_wgsl, InstanceDescriptor, LoadOp, Operations, Origin3d, PipelineLayoutDescriptor, PrimitiveState, PrimitiveTopology, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, RequestAdapterOptions, SamplerBindingType, ShaderStages, Surface, SurfaceError, TextureAspect, TextureDescriptor, TextureDimension, TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension, vertex_attr_array, VertexBufferLayout, VertexState, VertexStepMode};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CommandEncoderDescriptor, COPY_BYTES_PER_ROW_ALIGNMENT, DeviceDescriptor, Extent3d, FragmentState, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, include_wgsl, InstanceDescriptor, LoadOp, Maintain, MapMode, Operations, Origin3d, PipelineLayoutDescriptor, PrimitiveState, PrimitiveTopology, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, RequestAdapterOptions, SamplerBindingType, ShaderStages, Surface, SurfaceError, TextureAspect, TextureDescriptor, TextureDimension, TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension, vertex_attr_array, VertexBufferLayout, VertexState, VertexStepMode};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
 use crate::picture::{Picture, PixelFormat, RGBA8};
@@ -40,11 +41,27 @@ const VERTEX_DATA: [Vertex; 4] = [
     },
 ];
 
+/// Largest rectangle of the given aspect ratio centered within a surface, as `(x, y, width,
+/// height)`. The rest of the surface forms bars along two of its edges.
+pub fn letterbox(size: (u32, u32), aspect_ratio: f32) -> (u32, u32, u32, u32) {
+    let (width, height) = size;
+    if (width as f32 / height as f32) > aspect_ratio {
+        let inner = ((height as f32 * aspect_ratio).round() as u32).min(width);
+        ((width - inner) / 2, 0, inner, height)
+    } else {
+        let inner = ((width as f32 / aspect_ratio).round() as u32).min(height);
+        (0, (height - inner) / 2, width, inner)
+    }
+}
+
 pub struct Renderer {
     gpu: Gpu,
     vertex_buffer: Buffer,
     surface: Surface,
     screen: Screen,
+    size: (u32, u32),
+    /// Aspect ratio the frame is kept at regardless of the surface's, with bars filling the rest.
+    aspect_lock: Option<f32>,
 }
 
 impl Renderer {
@@ -54,18 +71,27 @@ impl Renderer {
             usage: BufferUsages::VERTEX,
             contents: bytes_of(&VERTEX_DATA),
         });
-        let viewport = Screen::new(&gpu, &surface, size);
+        let viewport = Screen::new(&gpu, &surface, size, None);
 
         Renderer {
             gpu,
             vertex_buffer,
             surface,
             screen: viewport,
+            size,
+            aspect_lock: None,
         }
     }
 
     pub fn surface_resize(&mut self, size: (u32, u32)) {
-        self.screen = Screen::new(&self.gpu, &self.surface, size);
+        self.size = size;
+        self.screen = Screen::new(&self.gpu, &self.surface, size, self.aspect_lock);
+    }
+
+    /// Locks the frame to an aspect ratio, or unlocks it. Replaces the frame, like resizing.
+    pub fn set_aspect_lock(&mut self, aspect_lock: Option<f32>) {
+        self.aspect_lock = aspect_lock;
+        self.surface_resize(self.size);
     }
 
     pub fn render(&self) {
@@ -78,17 +104,18 @@ impl Renderer {
 
         {
             let frame = self.screen.frame.lock().expect("frame upload");
+            let texture = frame.texture.as_ref().expect("frame texture");
             self.gpu.queue.write_texture(
                 ImageCopyTexture {
-                    texture: &frame.texture,
+                    texture: &texture.texture,
                     mip_level: 0,
                     aspect: TextureAspect::All,
                     origin: Origin3d::ZERO,
                 },
-                &frame.buffer,
+                frame.bytes(),
                 ImageDataLayout {
                     offset: 0,
-                    bytes_per_row: Some(size_of::<RGBA8>() as u32 * frame.width()),
+                    bytes_per_row: Some(frame.row_stride() as u32),
                     rows_per_image: Some(frame.height() as _),
                 },
                 Extent3d {
@@ -107,13 +134,15 @@ impl Renderer {
                 color_attachments: &[Some(RenderPassColorAttachment {
                     view: &target_view,
                     ops: Operations {
-                        load: LoadOp::Clear(wgpu::Color::WHITE),
+                        load: LoadOp::Clear(wgpu::Color::BLACK),
                         store: true,
                     },
                     resolve_target: None,
                 })],
                 depth_stencil_attachment: None,
             });
+            let (x, y, width, height) = self.screen.rect;
+            render_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
             render_pass.set_pipeline(&self.screen.pipeline);
             render_pass.set_bind_group(0, &self.screen.bind_group, &[]);
             render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
@@ -133,6 +162,8 @@ impl Renderer {
 
 struct Screen {
     frame: Arc<Mutex<Frame<RGBA8>>>,
+    /// Region of the surface the frame is displayed in, as `(x, y, width, height)`.
+    rect: (u32, u32, u32, u32),
     pipeline: RenderPipeline,
     bind_group: BindGroup,
 }
@@ -140,7 +171,7 @@ struct Screen {
 const RENDER_SCALE: u32 = 1;
 
 impl Screen {
-    pub fn new(gpu: &Gpu, surface: &Surface, size: (u32, u32)) -> Self {
+    pub fn new(gpu: &Gpu, surface: &Surface, size: (u32, u32), aspect_lock: Option<f32>) -> Self {
         let (width, height) = size;
         let mut surface_config = surface.get_default_config(&gpu.adapter, width, height)
             .expect("default surface config");
@@ -149,7 +180,11 @@ impl Screen {
 
         debug!(target:"app", "Surface: {:?}", surface_config);
 
-        let frame = Frame::new((width / RENDER_SCALE, height / RENDER_SCALE), &gpu);
+        let rect = match aspect_lock {
+            Some(aspect_ratio) => letterbox(size, aspect_ratio),
+            None => (0, 0, width, height),
+        };
+        let frame = Frame::new((rect.2 / RENDER_SCALE, rect.3 / RENDER_SCALE), gpu);
 
         let texture_bind_group_layout = gpu.device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: None,
@@ -213,7 +248,8 @@ impl Screen {
             multiview: None,
         });
 
-        let frame_texture_view = frame.texture.create_view(&TextureViewDescriptor::default());
+        let texture = frame.texture.as_ref().expect("frame texture");
+        let frame_texture_view = texture.texture.create_view(&TextureViewDescriptor::default());
         let bind_group = gpu.device.create_bind_group(&BindGroupDescriptor {
             label: None,
             layout: &texture_bind_group_layout,
@@ -224,23 +260,29 @@ impl Screen {
                 },
                 BindGroupEntry {
                     binding: 1,
-                    resource: BindingResource::Sampler(&frame.sampler),
+                    resource: BindingResource::Sampler(&texture.sampler),
                 },
             ],
         });
 
         Screen {
             frame: Arc::new(Mutex::new(frame)),
+            rect,
             pipeline,
             bind_group,
         }
     }
 }
 
-pub struct Frame<P> {
-    buffer: Vec<u8>,
+struct FrameTexture {
     texture: wgpu::Texture,
     sampler: wgpu::Sampler,
+}
+
+pub struct Frame<P> {
+    buffer: Vec<u8>,
+    /// GPU copy of the frame, absent for frames living only on the CPU.
+    texture: Option<FrameTexture>,
     size: (u32, u32),
     _phantom_format: PhantomData<P>,
 }
@@ -253,6 +295,20 @@ impl<P> Frame<P> {
     pub fn height(&self) -> u32 {
         self.size.1
     }
+
+    /// Raw pixel data, rows laid out back to back.
+    pub fn bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    /// Number of bytes per row of pixels.
+    pub fn row_stride(&self) -> usize {
+        self.width() as usize * size_of::<P>()
+    }
 }
 
 impl<P: PixelFormat> Frame<P> {
@@ -271,7 +327,8 @@ impl<P: PixelFormat> Frame<P> {
             dimension: TextureDimension::D2,
             format: P::texture_format(),
 
-            usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+            // rendered into when the frame is computed on the GPU rather than uploaded.
+            usage: TextureUsages::COPY_DST | TextureUsages::COPY_SRC | TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
             view_formats: &[P::texture_format().remove_srgb_suffix()],
         });
         let sampler = gpu.device.create_sampler(&Default::default());
@@ -282,13 +339,80 @@ impl<P: PixelFormat> Frame<P> {
 
         Frame {
             buffer,
-            texture,
-            sampler,
+            texture: Some(FrameTexture { texture, sampler }),
             size: (width, height),
             _phantom_format: Default::default(),
         }
     }
 
+    /// Wraps existing pixel data in a frame without a GPU texture.
+    pub fn from_buffer(buffer: Vec<u8>, size: (u32, u32)) -> Self {
+        let (width, height) = size;
+        assert_eq!(buffer.len(), width as usize * height as usize * size_of::<P>(), "frame buffer length");
+
+        Frame {
+            buffer,
+            texture: None,
+            size,
+            _phantom_format: Default::default(),
+        }
+    }
+
+    /// Replaces the pixel data with the contents of the frame's GPU texture, for frames rendered on
+    /// the GPU. Blocks until the copy is complete.
+    pub fn read_back(&mut self, gpu: &Gpu) {
+        let texture = self.texture.as_ref().expect("frame texture");
+        let (width, height) = self.size;
+        let row_stride = self.row_stride();
+        // the GPU copies whole rows at a time, aligned to a fixed number of bytes.
+        let padded_stride = (row_stride as u32).next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let readback = gpu.device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: padded_stride as u64 * height as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = gpu.device.create_command_encoder(&CommandEncoderDescriptor::default());
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &texture.texture,
+                mip_level: 0,
+                aspect: TextureAspect::All,
+                origin: Origin3d::ZERO,
+            },
+            ImageCopyBuffer {
+                buffer: &readback,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_stride),
+                    rows_per_image: Some(height),
+                },
+            },
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        gpu.queue.submit(once(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let (sender, receiver) = channel();
+        slice.map_async(MapMode::Read, move |result| sender.send(result).expect("readback result"));
+        gpu.device.poll(Maintain::Wait);
+        receiver.recv()
+            .expect("readback result")
+            .expect("map readback buffer");
+
+        let mapped = slice.get_mapped_range();
+        for (row, padded) in self.buffer.chunks_mut(row_stride).zip(mapped.chunks(padded_stride as usize)) {
+            row.copy_from_slice(&padded[..row_stride]);
+        }
+        drop(mapped);
+        readback.unmap();
+    }
+
     pub fn picture(&self) -> Picture<&[P]> {
         let pixels = cast_slice(&self.buffer);
         Picture::new(pixels, self.size)
@@ -325,3 +449,71 @@ impl Gpu {
             .expect("surface")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::picture::Color;
+
+    use super::*;
+
+    #[test]
+    fn frame_from_buffer_reads_back_its_pixels() {
+        let buffer = (0..2 * 3 * 4).map(|i| i as u8).collect();
+        let frame = Frame::<RGBA8>::from_buffer(buffer, (2, 3));
+        let picture = frame.picture();
+
+        assert_eq!((picture.width(), picture.height()), (2, 3));
+        assert_eq!(Color::from(*picture.pixel(0, 0)), Color::from(RGBA8::new(0, 1, 2, 3)));
+        assert_eq!(Color::from(*picture.pixel(1, 2)), Color::from(RGBA8::new(20, 21, 22, 23)));
+    }
+
+    #[test]
+    fn row_stride_spans_a_row_of_pixels() {
+        let frame = Frame::<RGBA8>::from_buffer(vec![0; 5 * 2 * 4], (5, 2));
+        assert_eq!(frame.row_stride(), 5 * size_of::<RGBA8>());
+        assert_eq!(frame.bytes().len(), frame.row_stride() * 2);
+    }
+
+    #[test]
+    fn texture_cleared_on_the_gpu_reads_back_its_color() {
+        let instance = wgpu::Instance::new(InstanceDescriptor::default());
+        let Some(adapter) = smol::block_on(instance.request_adapter(&RequestAdapterOptions::default())) else {
+            // nothing to read back from without a GPU.
+            return;
+        };
+        let (device, queue) = smol::block_on(adapter.request_device(&DeviceDescriptor::default(), None))
+            .expect("wgpu device");
+        let gpu = Gpu { instance, adapter, device, queue };
+
+        let mut frame = Frame::<RGBA8>::new((3, 2), &gpu);
+        let texture = &frame.texture.as_ref().expect("frame texture").texture;
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let mut encoder = gpu.device.create_command_encoder(&CommandEncoderDescriptor::default());
+        encoder.begin_render_pass(&RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(wgpu::Color { r: 1.0, g: 0.0, b: 1.0, a: 1.0 }),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        gpu.queue.submit(once(encoder.finish()));
+
+        frame.read_back(&gpu);
+        let picture = frame.picture();
+        for (x, y) in [(0, 0), (2, 1)] {
+            assert_eq!(Color::from(*picture.pixel(x, y)), Color::from(RGBA8::new(255, 0, 255, 255)));
+        }
+    }
+
+    #[test]
+    fn wide_lock_on_a_narrower_window_adds_horizontal_bars() {
+        assert_eq!(letterbox((800, 600), 16.0 / 9.0), (0, 75, 800, 450));
+        assert_eq!(letterbox((800, 300), 16.0 / 9.0), (133, 0, 533, 300));
+        assert_eq!(letterbox((160, 90), 16.0 / 9.0), (0, 0, 160, 90));
+    }
+}
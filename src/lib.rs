@@ -0,0 +1,22 @@
+//! Simple ray-tracing rendering engine, following the
+//! [Ray Tracing in One Weekend](https://raytracing.github.io/) book series.
+
+pub mod gpu;
+pub mod ray;
+pub mod camera;
+pub mod object;
+pub mod render;
+pub mod picture;
+pub mod material;
+pub mod post;
+pub mod scene;
+pub mod background;
+pub mod aabb;
+pub mod bvh;
+pub mod light;
+pub mod load;
+pub mod metadata;
+pub mod texture;
+pub mod pool;
+#[cfg(feature = "video")]
+pub mod video;
@@ -0,0 +1,190 @@
+use std::f32::consts::PI;
+
+use nalgebra::{Point3, Vector3};
+
+use crate::render::Rng;
+
+/// Relative difference between distances to a light under which they are taken to reach the same
+/// point on it.
+const DISTANCE_TOLERANCE: f32 = 1e-3;
+
+/// Emitter which diffuse surfaces sample directly, so they find small lights without waiting for
+/// their paths to bounce into them. Only its shape is kept: the light itself comes from the
+/// material of the surface found where it is sampled.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Light {
+    /// Parallelogram spanned by the edges `u` and `v` from `corner`, lit on the side `u × v` faces.
+    Quad { corner: Point3<f32>, u: Vector3<f32>, v: Vector3<f32> },
+    /// Sphere lit all over, sampled within the cone it fills as seen from a point, so its shadows
+    /// soften with its size.
+    Sphere { center: Point3<f32>, radius: f32 },
+}
+
+/// Point drawn on a light, as seen from a point in the scene.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LightSample {
+    /// Unit direction towards the point.
+    pub direction: Vector3<f32>,
+    pub distance: f32,
+    /// Density of the direction per unit solid angle.
+    pub pdf: f32,
+}
+
+impl Light {
+    /// Draws a point on the lit side of the light, or `None` if the origin cannot see that side.
+    pub fn sample(&self, origin: &Point3<f32>, rng: &mut Rng) -> Option<LightSample> {
+        match self {
+            Light::Quad { corner, u, v } => {
+                let offset = corner + rng.f32() * u + rng.f32() * v - origin;
+                let distance = offset.magnitude();
+                let direction = offset / distance;
+                let pdf = quad_pdf(u, v, &direction, distance)?;
+                Some(LightSample { direction, distance, pdf })
+            }
+            Light::Sphere { center, radius } => {
+                let cone = SphereCone::new(center, *radius, origin)?;
+                // uniformly distributed over the cone's solid angle.
+                let cosine = 1.0 + rng.f32() * (cone.cos_max - 1.0);
+                let sine = (1.0 - cosine * cosine).max(0.0).sqrt();
+                let angle = 2.0 * PI * rng.f32();
+                let helper = if cone.axis.x.abs() > 0.9 { Vector3::y() } else { Vector3::x() };
+                let tangent = cone.axis.cross(&helper).normalize();
+                let bitangent = cone.axis.cross(&tangent);
+                let direction = (sine * angle.cos() * tangent + sine * angle.sin() * bitangent + cosine * cone.axis).normalize();
+                let distance = sphere_distance(center, *radius, origin, &direction);
+                Some(LightSample { direction, distance, pdf: cone.pdf() })
+            }
+        }
+    }
+
+    /// Density with which `sample` picks the unit direction from the origin, for the point of the
+    /// light `distance` along it. Zero if the light is not there or shows its unlit side.
+    pub fn pdf(&self, origin: &Point3<f32>, direction: &Vector3<f32>, distance: f32) -> f32 {
+        match self {
+            Light::Quad { corner, u, v } => {
+                let normal = u.cross(v);
+                let t = normal.dot(&(corner - origin)) / normal.dot(direction);
+                if t.is_nan() || (t - distance).abs() > DISTANCE_TOLERANCE * distance.max(1.0) {
+                    return 0.0;
+                }
+                // coordinates of the point along the edges, as fractions of them.
+                let planar = origin + direction * t - corner;
+                let w = normal / normal.magnitude_squared();
+                let (alpha, beta) = (w.dot(&planar.cross(v)), w.dot(&u.cross(&planar)));
+                if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+                    return 0.0;
+                }
+                quad_pdf(u, v, direction, t).unwrap_or(0.0)
+            }
+            Light::Sphere { center, radius } => {
+                let Some(cone) = SphereCone::new(center, *radius, origin) else {
+                    return 0.0;
+                };
+                let t = sphere_distance(center, *radius, origin, direction);
+                if direction.dot(&cone.axis) < cone.cos_max || t.is_nan() || (t - distance).abs() > DISTANCE_TOLERANCE * distance.max(1.0) {
+                    return 0.0;
+                }
+                cone.pdf()
+            }
+        }
+    }
+
+    /// The light in another space, with points and vectors taken there by a similarity transform.
+    pub fn transformed(&self, point: impl Fn(&Point3<f32>) -> Point3<f32>, vector: impl Fn(&Vector3<f32>) -> Vector3<f32>) -> Light {
+        match self {
+            Light::Quad { corner, u, v } => Light::Quad { corner: point(corner), u: vector(u), v: vector(v) },
+            Light::Sphere { center, radius } => Light::Sphere {
+                center: point(center),
+                radius: vector(&(Vector3::x() * *radius)).magnitude(),
+            },
+        }
+    }
+}
+
+/// Solid angle density of uniformly drawn points on a parallelogram, at one seen along the unit
+/// direction from the given distance, or `None` if the direction reaches its unlit side.
+fn quad_pdf(u: &Vector3<f32>, v: &Vector3<f32>, direction: &Vector3<f32>, distance: f32) -> Option<f32> {
+    let normal = u.cross(v);
+    let area = normal.magnitude();
+    let cosine = -direction.dot(&normal) / area;
+    (cosine > 0.0).then(|| distance * distance / (cosine * area))
+}
+
+/// Cone of directions in which a sphere is seen from outside of it.
+struct SphereCone {
+    axis: Vector3<f32>,
+    cos_max: f32,
+}
+
+impl SphereCone {
+    /// `None` from inside the sphere, which fills every direction.
+    fn new(center: &Point3<f32>, radius: f32, origin: &Point3<f32>) -> Option<Self> {
+        let offset = center - origin;
+        let distance_squared = offset.magnitude_squared();
+        if distance_squared <= radius * radius {
+            return None;
+        }
+        let cos_max = (1.0 - radius * radius / distance_squared).sqrt();
+        Some(SphereCone { axis: offset / distance_squared.sqrt(), cos_max })
+    }
+
+    /// Density of directions drawn uniformly within the cone, per unit solid angle.
+    fn pdf(&self) -> f32 {
+        1.0 / (2.0 * PI * (1.0 - self.cos_max))
+    }
+}
+
+/// Distance along the unit direction to the near side of a sphere, grazing it where the direction
+/// only just misses it due to rounding.
+fn sphere_distance(center: &Point3<f32>, radius: f32, origin: &Point3<f32>, direction: &Vector3<f32>) -> f32 {
+    let oc = origin - center;
+    let half_b = oc.dot(direction);
+    let c = oc.magnitude_squared() - radius * radius;
+    -half_b - (half_b * half_b - c).max(0.0).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{point, vector};
+
+    use super::*;
+
+    #[test]
+    fn quad_samples_are_on_its_lit_side_with_matching_density() {
+        let light = Light::Quad { corner: point![-1.0, 2.0, -1.0], u: vector![2.0, 0.0, 0.0], v: vector![0.0, 0.0, 2.0] };
+        let origin = point![0.3, 0.0, -0.2];
+        let mut rng = Rng::with_seed(2);
+
+        for _ in 0..100 {
+            let sample = light.sample(&origin, &mut rng).expect("facing down onto the origin");
+            let point = origin + sample.direction * sample.distance;
+            assert!((point.y - 2.0).abs() < 1e-5 && point.x.abs() <= 1.0 && point.z.abs() <= 1.0, "{:?}", point);
+            assert!((light.pdf(&origin, &sample.direction, sample.distance) - sample.pdf).abs() < 1e-3 * sample.pdf);
+        }
+        // above the light, only its unlit side shows, and past it or beside it there is nothing.
+        assert!(light.sample(&point![0.0, 3.0, 0.0], &mut rng).is_none());
+        assert_eq!(light.pdf(&origin, &Vector3::y(), 3.0), 0.0);
+        assert_eq!(light.pdf(&origin, &vector![1.0, 1.0, 0.0].normalize(), 2.0), 0.0);
+    }
+
+    #[test]
+    fn sphere_samples_fill_the_cone_it_is_seen_in() {
+        let light = Light::Sphere { center: point![0.0, 4.0, 0.0], radius: 2.0 };
+        let origin = Point3::origin();
+        let mut rng = Rng::with_seed(3);
+
+        // seen from twice its radius away, the sphere fills a cone of 30 degrees about the axis.
+        let mut widest: f32 = 0.0;
+        for _ in 0..1000 {
+            let sample = light.sample(&origin, &mut rng).expect("outside of the sphere");
+            let point = origin + sample.direction * sample.distance;
+            assert!(((point - point![0.0, 4.0, 0.0]).magnitude() - 2.0).abs() < 1e-3, "{:?}", point);
+            assert!(point.y <= 4.0);
+            assert!((light.pdf(&origin, &sample.direction, sample.distance) - sample.pdf).abs() < 1e-6);
+            widest = widest.max(sample.direction.angle(&Vector3::y()));
+        }
+        assert!(widest <= 30f32.to_radians() + 1e-4 && widest > 29f32.to_radians(), "{}", widest.to_degrees());
+        assert!(light.sample(&point![0.0, 3.0, 0.0], &mut rng).is_none());
+        assert_eq!(light.pdf(&origin, &vector![0.0, 1.0, 1.0].normalize(), 4.0), 0.0);
+    }
+}
@@ -0,0 +1,217 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use nalgebra::{point, Point3};
+
+use crate::material::Material;
+use crate::object::Object;
+use crate::picture::Color;
+
+/// Failure to load a scene description.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// A line which could not be understood, numbered from 1.
+    Parse { line: usize, message: String },
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{}", err),
+            LoadError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(err) => Some(err),
+            LoadError::Parse { .. } => None,
+        }
+    }
+}
+
+impl From<LoadError> for io::Error {
+    fn from(value: LoadError) -> Self {
+        match value {
+            LoadError::Io(err) => err,
+            parse @ LoadError::Parse { .. } => io::Error::new(io::ErrorKind::InvalidData, parse),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(value: io::Error) -> Self {
+        LoadError::Io(value)
+    }
+}
+
+/// Loads spheres from a file of `x,y,z,radius` lines, optionally followed by `,r,g,b` to give the
+/// sphere a Lambert material of that color instead of `default_material`.
+pub fn load_spheres_csv(path: impl AsRef<Path>, default_material: Material) -> Result<Object, LoadError> {
+    read_spheres_csv(BufReader::new(File::open(path)?), default_material)
+}
+
+/// Reads spheres in the format of `load_spheres_csv`. Blank lines are skipped.
+pub fn read_spheres_csv(reader: impl BufRead, default_material: Material) -> Result<Object, LoadError> {
+    let mut spheres = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parse_error = |message: String| LoadError::Parse { line: index + 1, message };
+
+        let values = line.split(',')
+            .map(|value| value.trim().parse::<f32>().map_err(|err| parse_error(format!("{:?}: {}", value, err))))
+            .collect::<Result<Vec<_>, _>>()?;
+        let material = match values[..] {
+            [_, _, _, _] => default_material.clone(),
+            [_, _, _, _, r, g, b] => Material::lambert(Color::new(r, g, b, 1.0)),
+            _ => return Err(parse_error(format!("expected 4 or 7 values, found {}", values.len()))),
+        };
+        spheres.push(Object::sphere(point![values[0], values[1], values[2]], values[3], material));
+    }
+    Ok(Object::list(spheres))
+}
+
+/// Loads the triangles of a Wavefront OBJ mesh, all with the same material. Polygons with more than
+/// three vertices are split into a fan of triangles. Texture coordinates, normals, and any other
+/// statements are ignored.
+pub fn load_obj(path: impl AsRef<Path>, material: Material) -> Result<Object, LoadError> {
+    read_obj(BufReader::new(File::open(path)?), material)
+}
+
+/// Reads a mesh in the format of `load_obj`.
+pub fn read_obj(reader: impl BufRead, material: Material) -> Result<Object, LoadError> {
+    let mut vertices: Vec<Point3<f32>> = Vec::new();
+    let mut triangles = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let parse_error = |message: String| LoadError::Parse { line: index + 1, message };
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let coordinates = words.take(3)
+                    .map(|word| word.parse::<f32>().map_err(|err| parse_error(format!("{:?}: {}", word, err))))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let [x, y, z] = coordinates[..] else {
+                    return Err(parse_error("vertex with fewer than 3 coordinates".to_string()));
+                };
+                vertices.push(point![x, y, z]);
+            }
+            Some("f") => {
+                let corners = words
+                    .map(|word| obj_vertex(word, &vertices).ok_or_else(|| parse_error(format!("invalid face vertex {:?}", word))))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if corners.len() < 3 {
+                    return Err(parse_error(format!("face with {} vertices", corners.len())));
+                }
+                for pair in corners[1..].windows(2) {
+                    triangles.push(Object::triangle(corners[0], pair[0], pair[1], material.clone()));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(Object::list(triangles))
+}
+
+/// Position referenced by a face vertex such as `3`, `3/1` or `-1//2`. Indices start at 1, while
+/// negative indices count back from the last vertex so far.
+fn obj_vertex(word: &str, vertices: &[Point3<f32>]) -> Option<Point3<f32>> {
+    let index: i64 = word.split('/').next()?.parse().ok()?;
+    let index = match index {
+        0 => return None,
+        1.. => index - 1,
+        _ => vertices.len() as i64 + index,
+    };
+    vertices.get(usize::try_from(index).ok()?).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_lines_become_spheres() {
+        let csv = "0, 1, 2, 0.5\n\n-1,0,3,2,0.9,0.1,0.1\n";
+        let scene = read_spheres_csv(csv.as_bytes(), Material::metal(Color::WHITE, 0.0)).unwrap();
+
+        let Object::List(objects) = scene else { panic!("not a list") };
+        let spheres: Vec<_> = objects.iter()
+            .map(|object| match object {
+                Object::Sphere(sphere) => sphere,
+                _ => panic!("not a sphere"),
+            })
+            .collect();
+        assert_eq!(spheres.len(), 2);
+        assert_eq!((spheres[0].center, spheres[0].radius), (point![0.0, 1.0, 2.0], 0.5));
+        assert_eq!((spheres[1].center, spheres[1].radius), (point![-1.0, 0.0, 3.0], 2.0));
+        assert!(matches!(spheres[0].material, Material::Metal { .. }));
+        let Material::Lambert { albedo } = &spheres[1].material else { panic!("color not overridden") };
+        assert_eq!(albedo.value(0.0, 0.0, &Point3::origin()), Color::new(0.9, 0.1, 0.1, 1.0));
+    }
+
+    #[test]
+    fn csv_line_with_five_values_is_an_error() {
+        let error = read_spheres_csv("0,0,0,1\n0,0,0,1,1\n".as_bytes(), Material::lambert(Color::WHITE)).unwrap_err();
+        assert!(matches!(error, LoadError::Parse { line: 2, .. }), "{}", error);
+    }
+
+    #[test]
+    fn obj_cube_has_twelve_triangles() {
+        let cube = "\
+# unit cube
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 0 0 1
+v 1 0 1
+v 1 1 1
+v 0 1 1
+vn 0 0 1
+f 1 2 3 4
+f 5 8 7 6
+f 1 5 6 2
+f 2//1 6//1 7//1 3//1
+f 3 7 8 4
+f -8 -4 -1 -5
+";
+        let mesh = read_obj(cube.as_bytes(), Material::lambert(Color::WHITE)).unwrap();
+
+        let Object::List(triangles) = mesh else { panic!("not a list") };
+        assert_eq!(triangles.len(), 12);
+        assert!(triangles.iter().all(|triangle| matches!(triangle, Object::Triangle(_))));
+    }
+
+    #[test]
+    fn obj_face_past_the_last_vertex_is_an_error() {
+        let error = read_obj("v 0 0 0\nv 1 0 0\nf 1 2 3\n".as_bytes(), Material::lambert(Color::WHITE)).unwrap_err();
+        assert!(matches!(error, LoadError::Parse { line: 3, .. }), "{}", error);
+    }
+
+    #[test]
+    fn object_loads_obj_files() {
+        let path = std::env::temp_dir().join(format!("ray-tracing-{}-quad.obj", std::process::id()));
+        std::fs::write(&path, "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+        let quad = Object::load_obj(&path, Material::lambert(Color::WHITE));
+        std::fs::write(&path, "v 0 0 0\nf 1 x 1\n").unwrap();
+        let malformed = Object::load_obj(&path, Material::lambert(Color::WHITE));
+        std::fs::remove_file(&path).unwrap();
+
+        let Ok(Object::List(triangles)) = quad else { panic!("not a list") };
+        assert_eq!(triangles.len(), 2);
+        let error = malformed.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().starts_with("line 2:"), "{}", error);
+        assert_eq!(Object::load_obj(&path, Material::lambert(Color::WHITE)).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -1,37 +1,30 @@
-//! Simple ray-tracing rendering engine, following the
-//! [Ray Tracing in One Weekend](https://raytracing.github.io/) book series.
+//! Interactive viewer for the ray-tracing engine.
 
-use std::iter::once;
+use std::f32::consts::PI;
 use std::sync::{Arc, Mutex};
 use std::thread::{JoinHandle, spawn};
 use std::time::Instant;
 
 use log::info;
-use nalgebra::{point, vector, Vector3};
+use nalgebra::{point, Rotation3, vector, Vector3};
 use winit::dpi::LogicalSize;
 use winit::event::{ElementState, Event, MouseScrollDelta, VirtualKeyCode, WindowEvent};
 use winit::event_loop::EventLoop;
 use winit::window::WindowBuilder;
 
-use object::{Object, Sphere};
-use picture::RGBA8;
-
-use crate::camera::{Camera, CameraDirection};
-use crate::gpu::{Frame, Gpu, Renderer};
-use crate::material::Material;
-use crate::picture::Color;
-
-use crate::render::{MULTISAMPLE_8X_PATTERN, random, random_in, render_frame_async};
-
-mod gpu;
-mod ray;
-mod camera;
-mod object;
-mod render;
-mod picture;
-mod material;
+use ray_tracing::background::Background;
+use ray_tracing::camera::{Camera, CameraDirection, RollPitchYaw};
+use ray_tracing::gpu::{Frame, Gpu, Renderer};
+use ray_tracing::object::Object;
+use ray_tracing::picture::RGBA8;
+use ray_tracing::scene::{random_scene, RandomSceneOptions};
+use ray_tracing::render::{accumulate_frame_async, Accumulation, FocusPeaking, render_frame_async, RenderMode, RenderSettings, Rng, SamplePatternKind};
 
 const LOOK_SENSITIVITY: f32 = 0.005;
+/// Radians the background turns per key press.
+const BACKGROUND_YAW_STEP: f32 = PI / 12.0;
+/// Samples per pixel after which a still view stops being refined.
+const MAX_ACCUMULATED_SAMPLES: usize = 1024;
 
 #[derive(Clone, Default)]
 struct Controls {
@@ -54,72 +47,21 @@ impl Controls {
     }
 }
 
+/// Display options which, like the camera, require a new frame when changed.
+#[derive(Clone, Default, PartialEq)]
+struct View {
+    mode: RenderMode,
+    /// Rotation of the background about the vertical axis.
+    background_yaw: f32,
+    focus_peaking: bool,
+}
+
 #[derive(Clone)]
 struct State {
     camera: Camera,
     world: Object,
     controls: Controls,
-}
-
-fn random_scene() -> Object {
-    let spheres = (-11..11).flat_map(|a| (-11..11).map(move |b| {
-        point![a as f32 + 0.9 * random(), 0.2, b as f32 + 0.9 * random()]
-    }))
-        .filter(|center| (center - point![4.0, 0.2, 0.0]).magnitude() > 0.9)
-        .map(|center| {
-            let material = random();
-            let material = if material < 0.8 {
-                // diffuse
-                let color = Color::new(
-                    random() * random(),
-                    random() * random(),
-                    random() * random(),
-                    1.0,
-                );
-                Material::lambert(color)
-            } else if material < 0.95 {
-                // metal
-                let albedo = Color::new(
-                    random_in(0.5..1.0),
-                    random_in(0.5..1.0),
-                    random_in(0.5..1.0),
-                    1.0,
-                );
-                let fuzz = random_in(0.0..0.5);
-                Material::metal(albedo, fuzz)
-            } else {
-                // glass
-                Material::dielectric(1.5)
-            };
-            Object::sphere(center, 0.2, material)
-        });
-    let ground = Object::sphere(
-        point![0.0, -1000.0, 0.0],
-        1000.0,
-        Material::lambert(Color::new(0.5, 0.5, 0.5, 1.0)),
-    );
-    Object::List(
-        once(ground)
-            .chain(spheres)
-            .chain([
-                Object::sphere(
-                    point![0.0, 1.0, 0.0],
-                    1.0,
-                    Material::dielectric(1.5),
-                ),
-                Object::sphere(
-                    point![-4.0, 1.0, 0.0],
-                    1.0,
-                    Material::lambert(Color::new(0.4, 0.2, 0.1, 1.0)),
-                ),
-                Object::sphere(
-                    point![4.0, 1.0, 0.0],
-                    1.0,
-                    Material::metal(Color::new(0.7, 0.6, 0.5, 1.0), 0.0),
-                ),
-            ])
-            .collect()
-    )
+    view: View,
 }
 
 fn spawn_worker(frame: &Arc<Mutex<Frame<RGBA8>>>, state: Arc<Mutex<State>>) -> JoinHandle<()> {
@@ -132,19 +74,47 @@ fn spawn_worker(frame: &Arc<Mutex<Frame<RGBA8>>>, state: Arc<Mutex<State>>) -> J
         f32::NAN,
     );
 
+    let mut last_view = None;
+    // whether the last frame was rendered at full quality rather than as a preview.
+    let mut refined = true;
+
+    let mut settings = RenderSettings::default();
+    let mut accumulation = Accumulation::default();
+
     info!(target: "app", "Spawning worker thread");
     spawn(move || {
         while let Some(frame) = frame.upgrade() {
             let state = state.lock().expect("state lock").clone();
 
-            if last_camera != state.camera {
+            let changed = last_camera != state.camera || last_view.as_ref() != Some(&state.view);
+            if changed {
                 last_camera = state.camera.clone();
+                last_view = Some(state.view.clone());
+                settings.mode = state.view.mode;
+                settings.lights = state.world.lights();
+                settings.focus_peaking = state.view.focus_peaking.then(FocusPeaking::default);
+                settings.background = Background::default()
+                    .rotated(Rotation3::from_axis_angle(&Vector3::y_axis(), state.view.background_yaw));
+                accumulation.reset();
+            }
+            if changed || !refined {
+                // preview while the view changes, then refine once it settles.
+                settings.preview = changed;
+                refined = !changed;
 
                 info!(target: "app", "Starting frame render...");
                 let start = Instant::now();
-                render_frame_async(frame.as_ref(), &state.camera, &state.world, &MULTISAMPLE_8X_PATTERN);
+                render_frame_async(frame.as_ref(), &state.camera, &state.world, &settings);
+                settings.frame_index += 1;
                 let elapsed = start.elapsed();
                 info!(target: "app", "Finished rendering. Took {:?}", elapsed);
+            } else if matches!(settings.mode, RenderMode::Beauty | RenderMode::StandardError)
+                && settings.focus_peaking.is_none()
+                && accumulation.sample_count() < MAX_ACCUMULATED_SAMPLES {
+                // keep converging while the view stays still, with samples placed anew every pass.
+                let pass = RenderSettings { samples: SamplePatternKind::Stratified4x4, ..settings.clone() };
+                accumulate_frame_async(frame.as_ref(), &mut accumulation, &state.camera, &state.world, &pass);
+                info!(target: "app", "Accumulated {} samples per pixel", accumulation.sample_count());
             }
         }
         info!(target: "app", "Worker lost frame, stopping");
@@ -182,8 +152,9 @@ fn main() {
             0.1,
             10.0,
         ),
-        world: random_scene(),
+        world: random_scene(&RandomSceneOptions::default(), &mut Rng::with_seed(0)),
         controls: Default::default(),
+        view: Default::default(),
     }));
 
     spawn_worker(&renderer.frame(), state.clone());
@@ -206,10 +177,7 @@ fn main() {
                     let movement = state.camera.direction.direction(&state.camera.position) * state.controls.movement() * MOVE_SPEED * elapsed;
                     state.camera.position += movement;
 
-                    // update focus
-                    if let CameraDirection::LookAt { look_at, up } = &state.camera.direction {
-                        state.camera.focus_distance = (state.camera.position - look_at).magnitude();
-                    }
+                    state.camera.focus_on_lookat();
                 }
 
                 renderer.render();
@@ -225,8 +193,13 @@ fn main() {
                 WindowEvent::CloseRequested => control_flow.set_exit(),
                 WindowEvent::MouseWheel { delta: MouseScrollDelta::PixelDelta(position), .. } if interactive => {
                     let mut state = state.lock().expect("state write lock");
-                    /*state.camera.yaw += position.x as f32 * LOOK_SENSITIVITY;
-                    state.camera.pitch += position.y as f32 * LOOK_SENSITIVITY;*/
+                    let rotation = state.camera.direction.direction(&state.camera.position);
+                    let look = RollPitchYaw::new(
+                        position.y as f32 * LOOK_SENSITIVITY,
+                        position.x as f32 * LOOK_SENSITIVITY,
+                        0.0,
+                    );
+                    state.camera.direction = CameraDirection::Rotation(rotation * Rotation3::from(look));
                 }
                 WindowEvent::KeyboardInput { input, .. } => {
                     let pressed = matches!(input.state, ElementState::Pressed);
@@ -237,6 +210,16 @@ fn main() {
                         Some(VirtualKeyCode::D | VirtualKeyCode::Right) => state.lock().unwrap().controls.right = pressed,
                         Some(VirtualKeyCode::E) => state.lock().unwrap().controls.up = pressed,
                         Some(VirtualKeyCode::Q) => state.lock().unwrap().controls.down = pressed,
+                        Some(VirtualKeyCode::F) if pressed => {
+                            let mut state = state.lock().unwrap();
+                            state.view.focus_peaking = !state.view.focus_peaking;
+                        }
+                        Some(VirtualKeyCode::LBracket) if pressed => state.lock().unwrap().view.background_yaw -= BACKGROUND_YAW_STEP,
+                        Some(VirtualKeyCode::RBracket) if pressed => state.lock().unwrap().view.background_yaw += BACKGROUND_YAW_STEP,
+                        Some(VirtualKeyCode::Tab) if pressed => {
+                            let mut state = state.lock().unwrap();
+                            state.view.mode = state.view.mode.next();
+                        }
                         _ => {}
                     }
                 }
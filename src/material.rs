@@ -1,14 +1,60 @@
 use std::ops::Neg;
+use std::sync::{Arc, OnceLock};
 use nalgebra::Vector3;
-use crate::picture::Color;
+use crate::picture::{Color, Picture};
 use crate::ray::{Face, Hit, Ray};
-use crate::render::{random, random_unit_vec, random_vec_in_unit_sphere};
+use crate::render::{random_cosine_direction, random_unit_vec, random_vec_in_unit_sphere, Rng};
+use crate::texture::{SolidColor, Texture};
 
 #[derive(Clone, Debug)]
 pub enum Material {
-    Lambert { albedo: Color },
+    Lambert { albedo: Arc<dyn Texture> },
     Metal { albedo: Color, fuzz: f32 },
-    Dielectric { index_of_refraction: f32 },
+    /// Glass-like material. Light travelling through it is attenuated per channel following Beer's
+    /// law, by `absorption` per unit of distance.
+    Dielectric { index_of_refraction: f32, absorption: Vector3<f32> },
+    /// Gives off light of the given color without scattering any. One-sided lights only give it off
+    /// from the front face.
+    DiffuseLight { emit: Color, one_sided: bool },
+    /// Shaded by looking up the picture of a lit sphere with the surface normal in view space,
+    /// without any bounces.
+    Matcap { texture: Arc<Picture<Vec<Color>>> },
+    /// Scatters light equally in every direction, as particles of a participating medium do.
+    Isotropic { albedo: Arc<dyn Texture> },
+}
+
+/// Named kinds of colored glass with tuned refraction and absorption.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GlassPreset {
+    Water,
+    Amber,
+    Sapphire,
+}
+
+impl GlassPreset {
+    pub fn index_of_refraction(&self) -> f32 {
+        match self {
+            GlassPreset::Water => 1.333,
+            GlassPreset::Amber => 1.55,
+            GlassPreset::Sapphire => 1.77,
+        }
+    }
+
+    /// Absorption per unit of distance for the red, green and blue channels.
+    pub fn absorption(&self) -> Vector3<f32> {
+        match self {
+            GlassPreset::Water => Vector3::new(0.45, 0.06, 0.02),
+            GlassPreset::Amber => Vector3::new(0.05, 0.6, 2.2),
+            GlassPreset::Sapphire => Vector3::new(1.6, 0.9, 0.1),
+        }
+    }
+}
+
+/// Whether a material scatters light diffusely or along a mirror/refraction direction.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ScatterKind {
+    Diffuse,
+    Specular,
 }
 
 fn reflect(v: &Vector3<f32>, n: &Vector3<f32>) -> Vector3<f32> {
@@ -22,50 +68,133 @@ fn refract(uv: &Vector3<f32>, n: &Vector3<f32>, etai_over_etat: f32) -> Vector3<
     r_out_perp + r_out_parallel
 }
 
+/// Fraction of light of each channel left after travelling `distance` through an absorbing medium.
+fn beer_attenuation(absorption: &Vector3<f32>, distance: f32) -> Color {
+    let transmitted = absorption.map(|coefficient| (-coefficient * distance).exp());
+    Color::new(transmitted.x, transmitted.y, transmitted.z, 1.0)
+}
+
+/// Color of a matcap for a unit normal in view space, where x points right, y up and z towards the
+/// viewer. The normal facing the viewer samples the center of the picture.
+pub fn sample_matcap(texture: &Picture<Vec<Color>>, view_normal: &Vector3<f32>) -> Color {
+    let texture = texture.view();
+    let texel = |coordinate: f32, size: u32| {
+        ((coordinate * 0.5 + 0.5).clamp(0.0, 1.0) * (size - 1) as f32).round() as u32
+    };
+    *texture.pixel(texel(view_normal.x, texture.width()), texel(view_normal.y, texture.height()))
+}
+
 fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
     let r0 = ((1.0 - ref_idx) / (1.0 + ref_idx)).powi(2);
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
 
 impl Material {
-    pub fn scatter(&self, ray: &Ray, hit: &Hit) -> (Color, Ray) {
-        match self {
+    /// Neutral diffuse material for judging form and lighting without material distraction.
+    pub fn clay() -> &'static Material {
+        static CLAY: OnceLock<Material> = OnceLock::new();
+        CLAY.get_or_init(|| Material::lambert(Color::new(0.7, 0.7, 0.7, 1.0)))
+    }
+
+    /// Cheap stand-in for dielectrics, reflecting light rather than refracting it.
+    pub const PREVIEW_GLASS: Material = Material::Metal { albedo: Color::new(0.8, 0.85, 0.9, 1.0), fuzz: 0.0 };
+
+    /// Attenuation and direction of the light bouncing off the surface towards the ray, or `None`
+    /// if the surface absorbs it.
+    pub fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Option<(Color, Ray)> {
+        let scattered = match self {
             Material::Lambert { albedo } => {
-                let scatter_direction = hit.normal + random_unit_vec();
-                let scatter_ray = Ray::new(hit.point, scatter_direction);
-                (*albedo, scatter_ray)
+                let scatter_direction = random_cosine_direction(rng, &hit.normal);
+                let scatter_ray = Ray::new(hit.point, scatter_direction).with_time(ray.time);
+                (albedo.value(hit.u, hit.v, &hit.point), scatter_ray)
             }
             Material::Metal { albedo, fuzz } => {
-                let reflected = reflect(&ray.direction.normalize(), &hit.normal) + *fuzz * random_vec_in_unit_sphere();
-                let reflected = Ray::new(hit.point, reflected);
+                let reflected = reflect(&ray.direction.normalize(), &hit.normal) + *fuzz * random_vec_in_unit_sphere(rng);
+                let reflected = Ray::new(hit.point, reflected).with_time(ray.time);
                 (*albedo, reflected)
             }
-            Material::Dielectric { index_of_refraction } => {
+            Material::Dielectric { index_of_refraction, absorption } => {
                 let refraction_ratio = match hit.face {
                     Face::Front => 1.0 / index_of_refraction,
                     Face::Back => *index_of_refraction,
                 };
 
                 let unit_direction = ray.direction.normalize();
+                let travelled = hit.t * ray.direction.magnitude();
 
                 let cos_theta = unit_direction.neg().dot(&hit.normal).min(1.0);
                 let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
-                let direction = if refraction_ratio * sin_theta > 1.0 || reflectance(cos_theta, refraction_ratio) > random() {
+                let direction = if refraction_ratio * sin_theta > 1.0 || reflectance(cos_theta, refraction_ratio) > rng.f32() {
                     reflect(&unit_direction, &hit.normal)
                 } else {
                     refract(&unit_direction, &hit.normal, refraction_ratio)
                 };
 
-                let ray = Ray::new(hit.point, direction);
+                let ray = Ray::new(hit.point, direction).with_time(ray.time);
 
-                (Color::WHITE, ray)
+                // leaving the material, the incoming ray has travelled through it since entering.
+                let attenuation = match hit.face {
+                    Face::Front => Color::WHITE,
+                    Face::Back => beer_attenuation(absorption, travelled),
+                };
+
+                (attenuation, ray)
             }
+            Material::Isotropic { albedo } => {
+                let scatter_ray = Ray::new(hit.point, random_unit_vec(rng)).with_time(ray.time);
+                (albedo.value(hit.u, hit.v, &hit.point), scatter_ray)
+            }
+            Material::DiffuseLight { .. } | Material::Matcap { .. } => return None,
+        };
+        Some(scattered)
+    }
+
+    /// Light the surface gives off by itself at the hit.
+    pub fn emitted(&self, hit: &Hit) -> Color {
+        match self {
+            Material::DiffuseLight { one_sided: true, .. } if matches!(hit.face, Face::Back) => Color::BLACK,
+            Material::DiffuseLight { emit, .. } => *emit,
+            _ => Color::BLACK,
+        }
+    }
+
+    pub fn scatter_kind(&self) -> ScatterKind {
+        match self {
+            Material::Lambert { .. } | Material::DiffuseLight { .. } | Material::Matcap { .. } | Material::Isotropic { .. } => ScatterKind::Diffuse,
+            Material::Metal { .. } | Material::Dielectric { .. } => ScatterKind::Specular,
         }
     }
 
+    /// Color the material tints light with, independent of lighting. Dielectrics have no meaningful
+    /// albedo and are reported as white.
+    pub fn base_albedo(&self, hit: &Hit) -> Color {
+        match self {
+            Material::Lambert { albedo } | Material::Isotropic { albedo } => albedo.value(hit.u, hit.v, &hit.point),
+            Material::Metal { albedo, .. } => *albedo,
+            Material::Dielectric { .. } => Color::WHITE,
+            Material::DiffuseLight { emit, .. } => *emit,
+            Material::Matcap { texture } => sample_matcap(texture, &Vector3::z()),
+        }
+    }
+
+    /// Fraction of light the surface blocks rather than lets straight through, taken from the
+    /// albedo's alpha.
+    pub fn opacity(&self, hit: &Hit) -> f32 {
+        self.base_albedo(hit).a
+    }
+
     pub fn lambert(albedo: Color) -> Material {
-        Material::Lambert { albedo }
+        Material::textured(SolidColor(albedo))
+    }
+
+    pub fn isotropic(albedo: Color) -> Material {
+        Material::Isotropic { albedo: Arc::new(SolidColor(albedo)) }
+    }
+
+    /// Diffuse material with its color varying across the surface.
+    pub fn textured(albedo: impl Texture + 'static) -> Material {
+        Material::Lambert { albedo: Arc::new(albedo) }
     }
 
     pub fn metal(albedo: Color, fuzz: f32) -> Material {
@@ -73,6 +202,74 @@ impl Material {
     }
 
     pub fn dielectric(index_of_refraction: f32) -> Material {
-        Material::Dielectric { index_of_refraction }
+        Material::Dielectric { index_of_refraction, absorption: Vector3::zeros() }
+    }
+
+    pub fn diffuse_light(emit: Color) -> Material {
+        Material::DiffuseLight { emit, one_sided: false }
+    }
+
+    /// Light given off only from the front face of the surface.
+    pub fn one_sided_light(emit: Color) -> Material {
+        Material::DiffuseLight { emit, one_sided: true }
+    }
+
+    pub fn matcap(texture: Arc<Picture<Vec<Color>>>) -> Material {
+        Material::Matcap { texture }
+    }
+
+    /// Clear dielectric tinting the light passing through it, darker the further it travels.
+    pub fn absorbing_dielectric(index_of_refraction: f32, absorption: Vector3<f32>) -> Material {
+        Material::Dielectric { index_of_refraction, absorption }
+    }
+
+    pub fn glass_preset(preset: GlassPreset) -> Material {
+        Material::absorbing_dielectric(preset.index_of_refraction(), preset.absorption())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point3;
+
+    use super::*;
+
+    fn albedo(material: &Material) -> Color {
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), -Vector3::z());
+        material.base_albedo(&Hit::new(&ray, 1.0, Vector3::z(), material))
+    }
+
+    #[test]
+    fn base_albedo_of_each_material() {
+        let red = Color::new(0.9, 0.1, 0.1, 1.0);
+        let gold = Color::new(1.0, 0.8, 0.3, 1.0);
+        assert_eq!(albedo(&Material::lambert(red)), red);
+        assert_eq!(albedo(&Material::metal(gold, 0.2)), gold);
+        assert_eq!(albedo(&Material::dielectric(1.5)), Color::WHITE);
+    }
+
+    #[test]
+    fn glass_presets_refract_and_tint() {
+        for (preset, index_of_refraction) in [(GlassPreset::Water, 1.333), (GlassPreset::Amber, 1.55), (GlassPreset::Sapphire, 1.77)] {
+            let Material::Dielectric { index_of_refraction: ior, absorption } = Material::glass_preset(preset) else {
+                panic!("{:?} is not a dielectric", preset);
+            };
+            assert_eq!(ior, index_of_refraction);
+
+            let tint = beer_attenuation(&absorption, 1.0);
+            assert!(tint.r < 1.0 || tint.g < 1.0 || tint.b < 1.0, "{:?}", preset);
+            assert!(tint.r != tint.g || tint.g != tint.b, "{:?} is not tinted", preset);
+        }
+    }
+
+    #[test]
+    fn normal_facing_the_camera_samples_the_matcap_center() {
+        let texels = (0..9).map(|i| Color::new(i as f32, 0.0, 0.0, 1.0)).collect();
+        let texture = Picture::new(texels, (3, 3));
+
+        assert_eq!(sample_matcap(&texture, &Vector3::z()).r, 4.0);
+        assert_eq!(sample_matcap(&texture, &Vector3::x()).r, 5.0);
+        assert_eq!(sample_matcap(&texture, &-Vector3::y()).r, 1.0);
+        assert_eq!(albedo(&Material::matcap(Arc::new(texture))).r, 4.0);
     }
 }
@@ -0,0 +1,440 @@
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+
+use nalgebra::{Matrix3, point, Point3, Rotation3, Unit, Vector3};
+
+use crate::background::Background;
+use crate::camera::{ApertureShape, Camera, CameraDirection, Lens, Projection};
+use crate::picture::Color;
+use crate::render::{AdaptiveSampling, ContactShadows, EdgeDetection, FocusPeaking, Foveation, IndirectClamp, NormalSpace, RenderMode, RenderSettings, RussianRoulette, SamplePatternKind, ToneMap};
+
+/// Keyword of the PNG text chunk holding render metadata.
+pub const METADATA_KEYWORD: &str = "ray-tracing";
+
+/// What is needed to reproduce a render, embedded into saved images.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderMetadata {
+    pub camera: Camera,
+    /// Settings the image was rendered with. Environment images are too large to embed and are read
+    /// back as the default background, and the irradiance map and environment sampling are left to
+    /// be derived again from the background.
+    pub settings: RenderSettings,
+    /// Path of the file the scene was loaded from, if any.
+    pub scene: Option<String>,
+}
+
+impl RenderMetadata {
+    pub fn new(camera: &Camera, settings: &RenderSettings) -> Self {
+        RenderMetadata {
+            camera: camera.clone(),
+            settings: RenderSettings { irradiance: None, environment_sampling: None, lights: Vec::new(), ..settings.clone() },
+            scene: None,
+        }
+    }
+
+    /// Writes the metadata as `key=value` lines.
+    pub fn to_text(&self) -> String {
+        let camera = &self.camera;
+        let mut lines = vec![format!("position={}", join(camera.position.iter()))];
+        match &camera.direction {
+            CameraDirection::LookAt { look_at, up } => {
+                lines.push(format!("look_at={}", join(look_at.iter())));
+                lines.push(format!("up={}", join(up.iter())));
+            }
+            CameraDirection::Rotation(rotation) => lines.push(format!("rotation={}", join(rotation.matrix().iter()))),
+        }
+        lines.push(match camera.projection {
+            Projection::Perspective => "projection=perspective".to_string(),
+            Projection::Orthographic { height } => format!("projection=orthographic {:?}", height),
+        });
+        lines.push(format!("fov_deg={:?}", camera.fov_deg));
+        lines.push(format!("aperture={:?}", camera.aperture));
+        if let Some(lens) = &camera.lens {
+            lines.push(format!("lens={:?} {:?}", lens.focal_length, lens.f_stop));
+        }
+        lines.push(match camera.aperture_shape {
+            ApertureShape::Circle => "aperture_shape=circle".to_string(),
+            ApertureShape::Polygon { blades, rotation } => format!("aperture_shape=polygon {} {:?}", blades, rotation),
+        });
+        lines.push(format!("focus_distance={:?}", camera.focus_distance));
+        lines.push(format!("shutter={:?} {:?}", camera.time0, camera.time1));
+        lines.push(format!("near={:?}", camera.near));
+        if let Some(scene) = &self.scene {
+            lines.push(format!("scene={}", scene));
+        }
+        settings_text(&self.settings, &mut lines);
+        lines.join("\n")
+    }
+
+    /// Reads metadata written by `to_text`, or `None` if it is incomplete or malformed.
+    pub fn from_text(text: &str) -> Option<Self> {
+        let value = |key: &str| text.lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='));
+
+        let direction = match value("rotation") {
+            Some(rotation) => {
+                let matrix = Matrix3::from_column_slice(&floats::<9>(rotation)?);
+                CameraDirection::Rotation(Rotation3::from_matrix_unchecked(matrix))
+            }
+            None => CameraDirection::LookAt {
+                look_at: Point3::from(floats::<3>(value("look_at")?)?),
+                up: Unit::new_unchecked(Vector3::from(floats::<3>(value("up")?)?)),
+            },
+        };
+        let projection = match value("projection")?.split(' ').collect::<Vec<_>>()[..] {
+            ["perspective"] => Projection::Perspective,
+            ["orthographic", height] => Projection::Orthographic { height: height.parse().ok()? },
+            _ => return None,
+        };
+        let aperture_shape = match value("aperture_shape")?.split(' ').collect::<Vec<_>>()[..] {
+            ["circle"] => ApertureShape::Circle,
+            ["polygon", blades, rotation] => ApertureShape::Polygon { blades: blades.parse().ok()?, rotation: rotation.parse().ok()? },
+            _ => return None,
+        };
+        // written since motion blur was added, older renders used an instant shutter.
+        let [time0, time1] = match value("shutter") {
+            Some(shutter) => floats::<2>(shutter)?,
+            None => [0.0, 0.0],
+        };
+        let lens = match value("lens") {
+            Some(lens) => {
+                let [focal_length, f_stop] = floats::<2>(lens)?;
+                Some(Lens { focal_length, f_stop })
+            }
+            None => None,
+        };
+        let camera = Camera {
+            position: Point3::from(floats::<3>(value("position")?)?),
+            direction,
+            projection,
+            fov_deg: value("fov_deg")?.parse().ok()?,
+            aperture: value("aperture")?.parse().ok()?,
+            lens,
+            aperture_shape,
+            focus_distance: value("focus_distance")?.parse().ok()?,
+            time0,
+            time1,
+            near: value("near").map_or(Some(0.0), |near| near.parse().ok())?,
+        };
+
+        Some(RenderMetadata {
+            camera,
+            settings: settings_from_text(text)?,
+            scene: value("scene").map(str::to_string),
+        })
+    }
+}
+
+fn settings_text(settings: &RenderSettings, lines: &mut Vec<String>) {
+    lines.push(format!("mode={}", match settings.mode {
+        RenderMode::Beauty => "beauty",
+        RenderMode::SampleCount => "sample_count",
+        RenderMode::Albedo => "albedo",
+        RenderMode::Normal(NormalSpace::World) => "normal world",
+        RenderMode::Normal(NormalSpace::View) => "normal view",
+        RenderMode::BvhHeatmap => "bvh_heatmap",
+        RenderMode::StandardError => "standard_error",
+        RenderMode::Overdraw => "overdraw",
+    }));
+    lines.push(format!("samples={}", settings.samples.name()));
+    if let Some(foveation) = &settings.foveation {
+        lines.push(format!("foveation={} {:?} {:?}", join(foveation.focus.iter()), foveation.inner_radius, foveation.outer_radius));
+    }
+    if let Some(detection) = &settings.edge_detection {
+        lines.push(format!("edge_detection={:?}", detection.threshold));
+    }
+    if let Some(adaptive) = &settings.adaptive_sampling {
+        lines.push(format!("adaptive_sampling={} {} {:?}", adaptive.min_samples, adaptive.max_samples, adaptive.tolerance));
+    }
+    lines.push(format!("max_diffuse_bounces={}", settings.max_diffuse_bounces));
+    lines.push(format!("max_specular_bounces={}", settings.max_specular_bounces));
+    if let Some(clamp) = &settings.indirect_clamp {
+        lines.push(format!("indirect_clamp={:?} {}", clamp.max_luminance, clamp.from_depth));
+    }
+    lines.push(format!("tone_map={}", match settings.tone_map {
+        ToneMap::Clamp => "clamp".to_string(),
+        ToneMap::Reinhard => "reinhard".to_string(),
+        ToneMap::ReinhardExtended { white } => format!("reinhard_extended {:?}", white),
+        ToneMap::Aces => "aces".to_string(),
+    }));
+    lines.push(format!("gamma={:?}", settings.gamma));
+    lines.push(format!("clip={:?} {:?}", settings.near, settings.far));
+    lines.push(format!("seed={}", settings.seed));
+    lines.push(format!("frame_index={}", settings.frame_index));
+    lines.push(format!("animate_noise={}", settings.animate_noise));
+    lines.push(format!("profile={}", settings.profile));
+    lines.push(format!("clay={}", settings.clay));
+    if let Some(contact) = &settings.contact_shadows {
+        lines.push(format!("contact_shadows={:?} {:?} {}", contact.radius, contact.strength, contact.samples));
+    }
+    if let Some(roulette) = &settings.russian_roulette {
+        lines.push(format!("russian_roulette={}", roulette.from_depth));
+    }
+    if let Some(budget) = settings.time_budget {
+        lines.push(format!("time_budget_ns={}", budget.as_nanos()));
+    }
+    lines.push(format!("transparency={}", settings.transparency));
+    lines.push(format!("stochastic_alpha={}", settings.stochastic_alpha));
+    if let Some(background) = background_text(&settings.background) {
+        lines.push(format!("background={}", background));
+    }
+    lines.push(format!("ray_epsilon={:?}", settings.ray_epsilon));
+    if let Some(peaking) = &settings.focus_peaking {
+        lines.push(format!("focus_peaking={:?} {}", peaking.max_blur, join(color_values(&peaking.color).iter())));
+    }
+    lines.push(format!("preview={}", settings.preview));
+    lines.push(format!("sample_trim={:?}", settings.sample_trim));
+}
+
+/// Reads settings written by `settings_text`. Only the settings written since before they were all
+/// embedded are required, the rest keep their defaults when missing.
+fn settings_from_text(text: &str) -> Option<RenderSettings> {
+    let value = |key: &str| text.lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix('='));
+    // parses the value of an optional key, failing only if it is present but malformed.
+    fn parse<T>(value: Option<&str>, parse: impl FnOnce(&str) -> Option<T>) -> Option<Option<T>> {
+        value.map(parse).map_or(Some(None), |parsed| parsed.map(Some))
+    }
+    let defaults = RenderSettings::default();
+
+    let mode = parse(value("mode"), |mode| Some(match mode {
+        "beauty" => RenderMode::Beauty,
+        "sample_count" => RenderMode::SampleCount,
+        "albedo" => RenderMode::Albedo,
+        "normal world" => RenderMode::Normal(NormalSpace::World),
+        "normal view" => RenderMode::Normal(NormalSpace::View),
+        "bvh_heatmap" => RenderMode::BvhHeatmap,
+        "standard_error" => RenderMode::StandardError,
+        "overdraw" => RenderMode::Overdraw,
+        _ => return None,
+    }))?;
+    let foveation = parse(value("foveation"), |foveation| {
+        let [x, y, inner_radius, outer_radius] = floats::<4>(foveation)?;
+        Some(Foveation { focus: point![x, y], inner_radius, outer_radius })
+    })?;
+    let indirect_clamp = parse(value("indirect_clamp"), |clamp| match clamp.split(' ').collect::<Vec<_>>()[..] {
+        [max_luminance, from_depth] => Some(IndirectClamp { max_luminance: max_luminance.parse().ok()?, from_depth: from_depth.parse().ok()? }),
+        _ => None,
+    })?;
+    let tone_map = parse(value("tone_map"), |tone_map| match tone_map.split(' ').collect::<Vec<_>>()[..] {
+        ["clamp"] => Some(ToneMap::Clamp),
+        ["reinhard"] => Some(ToneMap::Reinhard),
+        ["reinhard_extended", white] => Some(ToneMap::ReinhardExtended { white: white.parse().ok()? }),
+        ["aces"] => Some(ToneMap::Aces),
+        _ => None,
+    })?;
+    let clip = parse(value("clip"), floats::<2>)?;
+    let contact_shadows = parse(value("contact_shadows"), |contact| match contact.split(' ').collect::<Vec<_>>()[..] {
+        [radius, strength, samples] => Some(ContactShadows { radius: radius.parse().ok()?, strength: strength.parse().ok()?, samples: samples.parse().ok()? }),
+        _ => None,
+    })?;
+    let background = parse(value("background"), |background| {
+        let mut tokens = background.split(' ');
+        let background = parse_background(&mut tokens)?;
+        tokens.next().is_none().then_some(background)
+    })?;
+    let focus_peaking = parse(value("focus_peaking"), |peaking| {
+        let [max_blur, r, g, b, a] = floats::<5>(peaking)?;
+        Some(FocusPeaking { max_blur, color: Color::new(r, g, b, a) })
+    })?;
+    let flag = |key: &str, default: bool| parse(value(key), |flag| flag.parse().ok()).map(|flag| flag.unwrap_or(default));
+    let float = |key: &str, default: f32| parse(value(key), |float| float.parse().ok()).map(|float| float.unwrap_or(default));
+
+    Some(RenderSettings {
+        mode: mode.unwrap_or(defaults.mode),
+        samples: SamplePatternKind::from_name(value("samples")?)?,
+        foveation,
+        edge_detection: parse(value("edge_detection"), |threshold| Some(EdgeDetection { threshold: threshold.parse().ok()? }))?,
+        adaptive_sampling: parse(value("adaptive_sampling"), |adaptive| match adaptive.split(' ').collect::<Vec<_>>()[..] {
+            [min_samples, max_samples, tolerance] => Some(AdaptiveSampling {
+                min_samples: min_samples.parse().ok()?,
+                max_samples: max_samples.parse().ok()?,
+                tolerance: tolerance.parse().ok()?,
+            }),
+            _ => None,
+        })?,
+        max_diffuse_bounces: value("max_diffuse_bounces")?.parse().ok()?,
+        max_specular_bounces: value("max_specular_bounces")?.parse().ok()?,
+        indirect_clamp,
+        tone_map: tone_map.unwrap_or(defaults.tone_map),
+        gamma: float("gamma", defaults.gamma)?,
+        near: clip.map_or(defaults.near, |[near, _]| near),
+        far: clip.map_or(defaults.far, |[_, far]| far),
+        seed: value("seed")?.parse().ok()?,
+        frame_index: value("frame_index")?.parse().ok()?,
+        animate_noise: flag("animate_noise", defaults.animate_noise)?,
+        profile: flag("profile", defaults.profile)?,
+        clay: flag("clay", defaults.clay)?,
+        contact_shadows,
+        russian_roulette: parse(value("russian_roulette"), |from_depth| Some(RussianRoulette { from_depth: from_depth.parse().ok()? }))?,
+        time_budget: parse(value("time_budget_ns"), |nanos| Some(Duration::from_nanos(nanos.parse().ok()?)))?,
+        transparency: flag("transparency", defaults.transparency)?,
+        stochastic_alpha: flag("stochastic_alpha", defaults.stochastic_alpha)?,
+        background: background.unwrap_or(defaults.background),
+        ray_epsilon: float("ray_epsilon", defaults.ray_epsilon)?,
+        focus_peaking,
+        preview: flag("preview", defaults.preview)?,
+        irradiance: None,
+        environment_sampling: None,
+        lights: Vec::new(),
+        sample_trim: float("sample_trim", defaults.sample_trim)?,
+    })
+}
+
+/// Background as space-separated tokens, or `None` for environment images, which are not embedded.
+fn background_text(background: &Background) -> Option<String> {
+    Some(match background {
+        Background::SolidColor(color) => format!("solid {}", join(color_values(color).iter())),
+        Background::Gradient { bottom, top } => format!("gradient {} {}", join(color_values(bottom).iter()), join(color_values(top).iter())),
+        Background::Sun { bottom, top, direction, angular_radius, intensity } => format!(
+            "sun {} {} {} {:?} {}",
+            join(color_values(bottom).iter()),
+            join(color_values(top).iter()),
+            join(direction.iter()),
+            angular_radius,
+            join(color_values(intensity).iter()),
+        ),
+        Background::Rotated { rotation, background } => format!("rotated {} {}", join(rotation.matrix().iter()), background_text(background)?),
+        Background::Environment(_) => return None,
+    })
+}
+
+fn parse_background<'a>(tokens: &mut impl Iterator<Item=&'a str>) -> Option<Background> {
+    let kind = tokens.next()?;
+    let mut floats = |count: usize| (0..count)
+        .map(|_| tokens.next()?.parse::<f32>().ok())
+        .collect::<Option<Vec<_>>>();
+    let color = |values: &[f32]| Color::new(values[0], values[1], values[2], values[3]);
+    Some(match kind {
+        "solid" => Background::SolidColor(color(&floats(4)?)),
+        "gradient" => {
+            let values = floats(8)?;
+            Background::Gradient { bottom: color(&values[..4]), top: color(&values[4..]) }
+        }
+        "sun" => {
+            let values = floats(16)?;
+            Background::Sun {
+                bottom: color(&values[..4]),
+                top: color(&values[4..8]),
+                direction: Unit::new_unchecked(Vector3::from_column_slice(&values[8..11])),
+                angular_radius: values[11],
+                intensity: color(&values[12..]),
+            }
+        }
+        "rotated" => {
+            let matrix = Matrix3::from_column_slice(&floats(9)?);
+            Background::Rotated {
+                rotation: Rotation3::from_matrix_unchecked(matrix),
+                background: Box::new(parse_background(tokens)?),
+            }
+        }
+        _ => return None,
+    })
+}
+
+fn color_values(color: &Color) -> [f32; 4] {
+    [color.r, color.g, color.b, color.a]
+}
+
+fn join<'a>(values: impl Iterator<Item=&'a f32>) -> String {
+    values.map(|value| format!("{:?}", value)).collect::<Vec<_>>().join(" ")
+}
+
+fn floats<const N: usize>(text: &str) -> Option<[f32; N]> {
+    let values = text.split(' ')
+        .map(|value| value.parse().ok())
+        .collect::<Option<Vec<f32>>>()?;
+    values.try_into().ok()
+}
+
+/// Reads the settings a PNG was rendered with from its render metadata, or `None` if it has none.
+pub fn read_render_settings(path: impl AsRef<Path>) -> Result<Option<RenderSettings>, png::DecodingError> {
+    Ok(read_render_metadata(path)?.map(|metadata| metadata.settings))
+}
+
+/// Reads the render metadata embedded into a PNG, or `None` if it has none.
+pub fn read_render_metadata(path: impl AsRef<Path>) -> Result<Option<RenderMetadata>, png::DecodingError> {
+    let decoder = png::Decoder::new(File::open(path)?);
+    let reader = decoder.read_info()?;
+    Ok(reader.info().uncompressed_latin1_text.iter()
+        .find(|chunk| chunk.keyword == METADATA_KEYWORD)
+        .and_then(|chunk| RenderMetadata::from_text(&chunk.text)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nalgebra::{vector, Vector3};
+
+    use crate::camera::CameraBuilder;
+    use crate::picture::{Picture, RGBA8};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ray-tracing-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn settings_round_trip_through_png() {
+        let camera = CameraBuilder::new(point![1.0, 2.0, 3.0])
+            .aperture(0.1)
+            .f_stop(0.05, 2.8)
+            .shutter(0.0, 0.5)
+            .near(0.5)
+            .build();
+        let settings = RenderSettings {
+            mode: RenderMode::Normal(NormalSpace::View),
+            samples: SamplePatternKind::Stratified4x4,
+            foveation: Some(Foveation { focus: point![10.0, 20.0], inner_radius: 0.3, outer_radius: 0.8 }),
+            edge_detection: Some(EdgeDetection { threshold: 0.1 }),
+            adaptive_sampling: Some(AdaptiveSampling { min_samples: 4, max_samples: 64, tolerance: 0.02 }),
+            max_diffuse_bounces: 3,
+            max_specular_bounces: 7,
+            indirect_clamp: Some(IndirectClamp { max_luminance: 4.0, from_depth: 2 }),
+            tone_map: ToneMap::ReinhardExtended { white: 3.5 },
+            gamma: 2.2,
+            near: 0.25,
+            far: 100.0,
+            seed: 42,
+            frame_index: 9,
+            animate_noise: true,
+            profile: true,
+            clay: true,
+            contact_shadows: Some(ContactShadows { radius: 0.2, strength: 0.7, samples: 6 }),
+            russian_roulette: Some(RussianRoulette { from_depth: 5 }),
+            time_budget: Some(Duration::from_millis(1234)),
+            transparency: true,
+            stochastic_alpha: true,
+            background: Background::sun(vector![1.0, 1.0, 0.0], 0.05, Color::new(10.0, 9.0, 8.0, 1.0))
+                .rotated(Rotation3::from_axis_angle(&Vector3::y_axis(), 0.3)),
+            ray_epsilon: 1e-3,
+            focus_peaking: Some(FocusPeaking { max_blur: 0.75, color: Color::new(0.0, 1.0, 0.0, 1.0) }),
+            preview: true,
+            irradiance: None,
+            environment_sampling: None,
+            lights: Vec::new(),
+            sample_trim: 0.1,
+        };
+        let metadata = RenderMetadata { scene: Some("scenes/spheres.csv".to_string()), ..RenderMetadata::new(&camera, &settings) };
+
+        let path = temp_path("settings.png");
+        Picture::new(vec![RGBA8::WHITE; 4], (2, 2)).view().save_png_with_metadata(&path, &metadata).unwrap();
+        let read_metadata = read_render_metadata(&path).unwrap();
+        let read_settings = read_render_settings(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read_metadata, Some(metadata));
+        assert_eq!(read_settings, Some(settings));
+    }
+
+    #[test]
+    fn environment_is_not_embedded() {
+        let environment = Background::Environment(Arc::new(Picture::new(vec![Color::WHITE], (1, 1))));
+        let settings = RenderSettings { background: environment, ..Default::default() };
+        let text = RenderMetadata::new(&CameraBuilder::new(Point3::origin()).build(), &settings).to_text();
+        assert_eq!(RenderMetadata::from_text(&text).unwrap().settings.background, Background::default());
+    }
+}
@@ -1,10 +1,43 @@
-use std::ops::RangeBounds;
+use std::f32::consts::PI;
+use std::io;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::sync::Arc;
 
 use float_ord::FloatOrd;
-use nalgebra::Point3;
+use nalgebra::{Point2, Point3, Similarity3, Translation3, UnitQuaternion, UnitVector3, Vector3};
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::light::Light;
+use crate::load;
 use crate::material::Material;
 
-use crate::ray::{Face, Hit, Ray};
+use crate::picture::Color;
+use crate::ray::{Face, Hit, Ray, RayKind};
+use crate::render::{count_intersections, ray_seed, Rng};
+
+/// Both intersections of the ray with a sphere's surface, nearest first.
+fn sphere_roots(center: &Point3<f32>, radius: f32, ray: &Ray) -> Option<(f32, f32)> {
+    let oc = ray.origin - center;
+    let a = ray.direction.magnitude_squared();
+    let half_b = oc.dot(&ray.direction);
+    let c = oc.magnitude_squared() - radius * radius;
+
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let sqrtd = discriminant.sqrt();
+    Some(((-half_b - sqrtd) / a, (-half_b + sqrtd) / a))
+}
+
+/// Coordinates of a point on the unit sphere, with `u` going around the vertical axis starting from
+/// -x and `v` from the bottom to the top.
+fn sphere_uv(point: &Vector3<f32>) -> (f32, f32) {
+    let theta = (-point.y).clamp(-1.0, 1.0).acos();
+    let phi = (-point.z).atan2(point.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
 
 #[derive(Clone, Debug)]
 pub struct Sphere {
@@ -18,49 +51,514 @@ impl Sphere {
         Sphere { center, radius, material }
     }
 
-    pub fn hit<R>(&self, ray: &Ray, t_rng: R) -> Option<Hit>
+    pub fn hit<R>(&self, ray: &Ray, t_rng: R) -> Option<Hit<'_>>
+        where R: RangeBounds<f32> {
+        let (near, far) = sphere_roots(&self.center, self.radius, ray)?;
+
+        // find the nearest root that lies in the acceptable range.
+        let root = [near, far].into_iter().find(|root| t_rng.contains(root))?;
+        Some(self.hit_at(ray, root))
+    }
+
+    pub fn hit_all<R>(&self, ray: &Ray, t_rng: R) -> Vec<Hit<'_>>
+        where R: RangeBounds<f32> {
+        let Some((near, far)) = sphere_roots(&self.center, self.radius, ray) else {
+            return Vec::new();
+        };
+        [near, far].into_iter()
+            .filter(|root| t_rng.contains(root))
+            .map(|root| self.hit_at(ray, root))
+            .collect()
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        Aabb::around(self.center, self.radius)
+    }
+
+    fn hit_at(&self, ray: &Ray, root: f32) -> Hit<'_> {
+        let outward_normal = (ray.at(root) - self.center) / self.radius;
+        let (u, v) = sphere_uv(&outward_normal);
+        Hit::new(ray, root, outward_normal, &self.material).with_uv(u, v)
+    }
+}
+
+/// Sphere moving in a straight line from `center0` at `time0` to `center1` at `time1`.
+#[derive(Clone, Debug)]
+pub struct MovingSphere {
+    pub center0: Point3<f32>,
+    pub center1: Point3<f32>,
+    pub time0: f32,
+    pub time1: f32,
+    pub radius: f32,
+    pub material: Material,
+}
+
+impl MovingSphere {
+    pub fn new(center0: Point3<f32>, center1: Point3<f32>, time0: f32, time1: f32, radius: f32, material: Material) -> Self {
+        MovingSphere { center0, center1, time0, time1, radius, material }
+    }
+
+    /// Center at the given time, continuing along the same line outside of `time0..time1`.
+    pub fn center_at(&self, time: f32) -> Point3<f32> {
+        if self.time1 == self.time0 {
+            return self.center0;
+        }
+        self.center0 + (time - self.time0) / (self.time1 - self.time0) * (self.center1 - self.center0)
+    }
+
+    pub fn hit<R>(&self, ray: &Ray, t_rng: R) -> Option<Hit<'_>>
         where R: RangeBounds<f32> {
-        let oc = ray.origin - self.center;
-        let a = ray.direction.magnitude_squared();
-        let half_b = oc.dot(&ray.direction);
-        let c = oc.magnitude_squared() - self.radius * self.radius;
+        let center = self.center_at(ray.time);
+        let (near, far) = sphere_roots(&center, self.radius, ray)?;
+        let root = [near, far].into_iter().find(|root| t_rng.contains(root))?;
+        Some(self.hit_at(&center, ray, root))
+    }
+
+    pub fn hit_all<R>(&self, ray: &Ray, t_rng: R) -> Vec<Hit<'_>>
+        where R: RangeBounds<f32> {
+        let center = self.center_at(ray.time);
+        let Some((near, far)) = sphere_roots(&center, self.radius, ray) else {
+            return Vec::new();
+        };
+        [near, far].into_iter()
+            .filter(|root| t_rng.contains(root))
+            .map(|root| self.hit_at(&center, ray, root))
+            .collect()
+    }
+
+    /// Box enclosing the sphere throughout `time0..time1`.
+    pub fn bounding_box(&self) -> Aabb {
+        Aabb::around(self.center0, self.radius).union(&Aabb::around(self.center1, self.radius))
+    }
+
+    fn hit_at(&self, center: &Point3<f32>, ray: &Ray, root: f32) -> Hit<'_> {
+        let outward_normal = (ray.at(root) - center) / self.radius;
+        let (u, v) = sphere_uv(&outward_normal);
+        Hit::new(ray, root, outward_normal, &self.material).with_uv(u, v)
+    }
+}
 
+/// Cylinder between two points capped with hemispheres, i.e. every point within `radius` of the
+/// segment `a`-`b`.
+#[derive(Clone, Debug)]
+pub struct Capsule {
+    pub a: Point3<f32>,
+    pub b: Point3<f32>,
+    pub radius: f32,
+    pub material: Material,
+}
+
+impl Capsule {
+    pub fn new(a: Point3<f32>, b: Point3<f32>, radius: f32, material: Material) -> Self {
+        Capsule { a, b, radius, material }
+    }
+
+    /// Intersections of the ray with the capsule's surface as `t` and outward normal, unordered.
+    fn intersections<'a>(&'a self, ray: &'a Ray) -> impl Iterator<Item=(f32, Vector3<f32>)> + 'a {
+        let ba = self.b - self.a;
+        let oa = ray.origin - self.a;
+        let baba = ba.magnitude_squared();
+        let bard = ba.dot(&ray.direction);
+        let baoa = ba.dot(&oa);
+        // position along the axis, where 0 is `a` and `baba` is `b`.
+        let axial = move |t: f32| baoa + t * bard;
+
+        // body: distance to the axis equals the radius, between the end points.
+        let a = baba * ray.direction.magnitude_squared() - bard * bard;
+        let half_b = baba * ray.direction.dot(&oa) - baoa * bard;
+        let c = baba * oa.magnitude_squared() - baoa * baoa - self.radius * self.radius * baba;
         let discriminant = half_b * half_b - a * c;
-        if discriminant < 0.0 {
+        let body = if a.abs() > f32::EPSILON && discriminant >= 0.0 {
+            let sqrtd = discriminant.sqrt();
+            [(-half_b - sqrtd) / a, (-half_b + sqrtd) / a]
+        } else {
+            [f32::NAN; 2]
+        };
+        let body = body.into_iter()
+            .filter(move |&t| axial(t) > 0.0 && axial(t) < baba)
+            .map(move |t| {
+                let point = ray.at(t);
+                let on_axis = self.a + ba * (axial(t) / baba);
+                (t, (point - on_axis) / self.radius)
+            });
+
+        // caps: the halves of the end spheres beyond the segment. Both ends of a capsule without
+        // length accept the whole sphere, so only one of them is kept.
+        let ends = if baba > 0.0 { 2 } else { 1 };
+        let caps = [(self.a, true), (self.b, false)].into_iter()
+            .take(ends)
+            .flat_map(move |(center, below)| {
+                let roots = sphere_roots(&center, self.radius, ray)
+                    .map_or([f32::NAN; 2], |(near, far)| [near, far]);
+                roots.into_iter()
+                    .filter(move |&t| if below { axial(t) <= 0.0 } else { axial(t) >= baba })
+                    .map(move |t| (t, (ray.at(t) - center) / self.radius))
+            });
+
+        body.chain(caps)
+    }
+
+    pub fn hit<R>(&self, ray: &Ray, t_rng: R) -> Option<Hit<'_>>
+        where R: RangeBounds<f32> {
+        self.intersections(ray)
+            .filter(|(t, _)| t_rng.contains(t))
+            .min_by_key(|(t, _)| FloatOrd(*t))
+            .map(|(t, normal)| Hit::new(ray, t, normal, &self.material))
+    }
+
+    pub fn hit_all<R>(&self, ray: &Ray, t_rng: R) -> Vec<Hit<'_>>
+        where R: RangeBounds<f32> {
+        let mut hits: Vec<_> = self.intersections(ray)
+            .filter(|(t, _)| t_rng.contains(t))
+            .map(|(t, normal)| Hit::new(ray, t, normal, &self.material))
+            .collect();
+        hits.sort_by_key(|hit| FloatOrd(hit.t));
+        hits
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        Aabb::around(self.a, self.radius).union(&Aabb::around(self.b, self.radius))
+    }
+}
+
+/// Counts a single primitive test.
+fn count_hit(hit: Option<Hit>) -> Option<Hit> {
+    count_intersections(1, 0, hit.is_some() as u64);
+    hit
+}
+
+/// Counts a single primitive test which may intersect the primitive several times.
+fn count_hits(hits: Vec<Hit>) -> Vec<Hit> {
+    count_intersections(1, 0, !hits.is_empty() as u64);
+    hits
+}
+
+/// Infinite flat surface through `point`, facing `normal`.
+#[derive(Clone, Debug)]
+pub struct Plane {
+    pub point: Point3<f32>,
+    pub normal: UnitVector3<f32>,
+    pub material: Material,
+}
+
+impl Plane {
+    pub fn new(point: Point3<f32>, normal: UnitVector3<f32>, material: Material) -> Self {
+        Plane { point, normal, material }
+    }
+
+    pub fn hit<R>(&self, ray: &Ray, t_rng: R) -> Option<Hit<'_>>
+        where R: RangeBounds<f32> {
+        let denominator = ray.direction.dot(&self.normal);
+        if denominator.abs() < f32::EPSILON {
             return None;
         }
-        let sqrtd = discriminant.sqrt();
+        let t = (self.point - ray.origin).dot(&self.normal) / denominator;
+        t_rng.contains(&t).then(|| Hit::new(ray, t, self.normal.into_inner(), &self.material))
+    }
 
-        // find the nearest root that lies in the acceptable range.
-        let mut root = (-half_b - sqrtd) / a;
-        if !t_rng.contains(&root) {
-            root = (-half_b + sqrtd) / a;
-            if !t_rng.contains(&root) {
-                return None;
-            }
+    pub fn hit_all<R>(&self, ray: &Ray, t_rng: R) -> Vec<Hit<'_>>
+        where R: RangeBounds<f32> {
+        self.hit(ray, t_rng).into_iter().collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Triangle {
+    pub a: Point3<f32>,
+    pub b: Point3<f32>,
+    pub c: Point3<f32>,
+    pub material: Material,
+}
+
+impl Triangle {
+    pub fn new(a: Point3<f32>, b: Point3<f32>, c: Point3<f32>, material: Material) -> Self {
+        Triangle { a, b, c, material }
+    }
+
+    pub fn area(&self) -> f32 {
+        (self.b - self.a).cross(&(self.c - self.a)).magnitude() / 2.0
+    }
+
+    /// Möller–Trumbore intersection.
+    pub fn hit<R>(&self, ray: &Ray, t_rng: R) -> Option<Hit<'_>>
+        where R: RangeBounds<f32> {
+        let ab = self.b - self.a;
+        let ac = self.c - self.a;
+        let p = ray.direction.cross(&ac);
+        let determinant = ab.dot(&p);
+        // the ray is parallel to the triangle.
+        if determinant.abs() < f32::EPSILON {
+            return None;
         }
 
-        let point = ray.at(root);
-        let outward_normal = (point - self.center) / self.radius;
-        let (face, normal) = if ray.direction.dot(&outward_normal) < 0.0 {
-            (Face::Front, outward_normal)
-        } else {
-            (Face::Back, -outward_normal)
-        };
-        Some(Hit {
-            point,
-            normal,
-            t: root,
-            face,
-            material: &self.material,
-        })
+        let inverse_determinant = 1.0 / determinant;
+        let ao = ray.origin - self.a;
+        let u = ao.dot(&p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = ao.cross(&ab);
+        let v = ray.direction.dot(&q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = ac.dot(&q) * inverse_determinant;
+        if !t_rng.contains(&t) {
+            return None;
+        }
+        let normal = ab.cross(&ac).normalize();
+        Some(Hit::new(ray, t, normal, &self.material).with_uv(u, v))
+    }
+
+    pub fn hit_all<R>(&self, ray: &Ray, t_rng: R) -> Vec<Hit<'_>>
+        where R: RangeBounds<f32> {
+        self.hit(ray, t_rng).into_iter().collect()
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.a.inf(&self.b).inf(&self.c), self.a.sup(&self.b).sup(&self.c)).padded()
+    }
+}
+
+/// Parallelogram spanned by the edges `u` and `v` from `corner`, facing `u × v`.
+#[derive(Clone, Debug)]
+pub struct Quad {
+    pub corner: Point3<f32>,
+    pub u: Vector3<f32>,
+    pub v: Vector3<f32>,
+    pub material: Material,
+}
+
+impl Quad {
+    pub fn new(corner: Point3<f32>, u: Vector3<f32>, v: Vector3<f32>, material: Material) -> Self {
+        Quad { corner, u, v, material }
+    }
+
+    pub fn area(&self) -> f32 {
+        self.u.cross(&self.v).magnitude()
+    }
+
+    pub fn hit<R>(&self, ray: &Ray, t_rng: R) -> Option<Hit<'_>>
+        where R: RangeBounds<f32> {
+        let normal = self.u.cross(&self.v);
+        let denominator = normal.dot(&ray.direction);
+        // the ray is parallel to the quad.
+        if denominator.abs() < f32::EPSILON {
+            return None;
+        }
+        let t = normal.dot(&(self.corner - ray.origin)) / denominator;
+        if !t_rng.contains(&t) {
+            return None;
+        }
+
+        // coordinates of the hit along the edges, as fractions of them.
+        let planar = ray.at(t) - self.corner;
+        let w = normal / normal.magnitude_squared();
+        let (u, v) = (w.dot(&planar.cross(&self.v)), w.dot(&self.u.cross(&planar)));
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        Some(Hit::new(ray, t, normal.normalize(), &self.material).with_uv(u, v))
+    }
+
+    pub fn hit_all<R>(&self, ray: &Ray, t_rng: R) -> Vec<Hit<'_>>
+        where R: RangeBounds<f32> {
+        self.hit(ray, t_rng).into_iter().collect()
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        let corners = [self.corner + self.u, self.corner + self.v, self.corner + self.u + self.v];
+        let (min, max) = corners.iter()
+            .fold((self.corner, self.corner), |(min, max), corner| (min.inf(corner), max.sup(corner)));
+        Aabb::new(min, max).padded()
+    }
+}
+
+/// Rectangle lying in a plane perpendicular to one of the axes, `k` along it. `min` and `max` are
+/// its corners' coordinates along the two other axes, in x, y, z order.
+#[derive(Clone, Debug)]
+pub struct Rect {
+    pub min: Point2<f32>,
+    pub max: Point2<f32>,
+    pub k: f32,
+    pub material: Material,
+}
+
+impl Rect {
+    pub fn new(min: Point2<f32>, max: Point2<f32>, k: f32, material: Material) -> Self {
+        Rect { min, max, k, material }
+    }
+
+    /// The two axes the rectangle spans when perpendicular to the given one.
+    fn plane_axes(axis: usize) -> (usize, usize) {
+        match axis {
+            0 => (1, 2),
+            1 => (0, 2),
+            _ => (0, 1),
+        }
+    }
+
+    /// Intersection with the rectangle placed perpendicular to `axis`, facing its positive side.
+    /// `u` and `v` run from 0 to 1 across the rectangle.
+    pub fn hit<R>(&self, axis: usize, ray: &Ray, t_rng: R) -> Option<Hit<'_>>
+        where R: RangeBounds<f32> {
+        let t = (self.k - ray.origin[axis]) / ray.direction[axis];
+        if !t_rng.contains(&t) {
+            return None;
+        }
+        let (a, b) = Rect::plane_axes(axis);
+        let point = ray.at(t);
+        let u = (point[a] - self.min.x) / (self.max.x - self.min.x);
+        let v = (point[b] - self.min.y) / (self.max.y - self.min.y);
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        let mut outward_normal = Vector3::zeros();
+        outward_normal[axis] = 1.0;
+        Some(Hit::new(ray, t, outward_normal, &self.material).with_uv(u, v))
+    }
+
+    pub fn hit_all<R>(&self, axis: usize, ray: &Ray, t_rng: R) -> Vec<Hit<'_>>
+        where R: RangeBounds<f32> {
+        self.hit(axis, ray, t_rng).into_iter().collect()
+    }
+
+    pub fn bounding_box(&self, axis: usize) -> Aabb {
+        let (a, b) = Rect::plane_axes(axis);
+        let mut min = Point3::origin();
+        let mut max = Point3::origin();
+        (min[a], min[b], min[axis]) = (self.min.x, self.min.y, self.k);
+        (max[a], max[b], max[axis]) = (self.max.x, self.max.y, self.k);
+        Aabb::new(min, max).padded()
+    }
+}
+
+/// Value of a range bound, or `unbounded` if it has none.
+fn bound_value(bound: Bound<f32>, unbounded: f32) -> f32 {
+    match bound {
+        Bound::Included(value) | Bound::Excluded(value) => value,
+        Bound::Unbounded => unbounded,
+    }
+}
+
+/// Brings a hit found in a transformed object's local space back into the parent's space. `t` is
+/// unaffected, as the local ray was transformed along with the object.
+fn transform_hit<'a>(transform: &Similarity3<f32>, hit: Hit<'a>) -> Hit<'a> {
+    Hit {
+        point: transform * hit.point,
+        normal: transform.isometry.rotation * hit.normal,
+        ..hit
+    }
+}
+
+/// Point at which a ray travelling through a medium of the given density scatters off a particle,
+/// if it does before leaving the boundary, at a distance drawn from `rng`.
+fn medium_hit<'a, R>(boundary: &Object, density: f32, phase: &'a Material, ray: &Ray, t_rng: R, rng: &mut Rng) -> Option<Hit<'a>>
+    where R: RangeBounds<f32> {
+    // the boundary may be entered behind the ray's origin, when it starts inside of the medium.
+    let enter = boundary.hit_with_rng(ray, .., rng)?;
+    let exit = boundary.hit_with_rng(ray, enter.t + 1e-4.., rng)?;
+    let enter = enter.t.max(bound_value(t_rng.start_bound().cloned(), f32::NEG_INFINITY)).max(0.0);
+    let exit = exit.t.min(bound_value(t_rng.end_bound().cloned(), f32::INFINITY));
+    if enter >= exit {
+        return None;
+    }
+
+    let length = ray.direction.magnitude();
+    // 1 - x lies in (0, 1], keeping the logarithm finite.
+    let distance = -(1.0 - rng.f32()).ln() / density;
+    if distance > (exit - enter) * length {
+        return None;
+    }
+    let t = enter + distance / length;
+    // particles scatter light the same way in every direction, so the normal is arbitrary.
+    Some(Hit {
+        point: ray.at(t),
+        normal: Vector3::x(),
+        face: Face::Front,
+        t,
+        u: 0.0,
+        v: 0.0,
+        material: phase,
+    })
+}
+
+/// Rotates the vector about the vertical axis by the angle with the given sine and cosine.
+fn rotate_y(sin: f32, cos: f32, vector: &Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(cos * vector.x + sin * vector.z, vector.y, -sin * vector.x + cos * vector.z)
+}
+
+fn rotate_y_ray(sin: f32, cos: f32, ray: &Ray) -> Ray {
+    Ray::new(
+        Point3::from(rotate_y(sin, cos, &ray.origin.coords)),
+        rotate_y(sin, cos, &ray.direction),
+    ).with_kind(ray.kind).with_time(ray.time)
+}
+
+/// Brings a hit found in a rotated object's local space back into the parent's space.
+fn rotate_y_hit(sin: f32, cos: f32, hit: Hit) -> Hit {
+    Hit {
+        point: Point3::from(rotate_y(sin, cos, &hit.point.coords)),
+        normal: rotate_y(sin, cos, &hit.normal),
+        ..hit
+    }
+}
+
+/// Which kinds of rays an object can be hit by.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VisibilityFlags {
+    pub camera: bool,
+    pub shadow: bool,
+    pub reflection: bool,
+}
+
+impl VisibilityFlags {
+    pub const ALL: VisibilityFlags = VisibilityFlags { camera: true, shadow: true, reflection: true };
+
+    pub fn visible_to(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Shadow => self.shadow,
+            RayKind::Reflection => self.reflection,
+        }
+    }
+}
+
+impl Default for VisibilityFlags {
+    fn default() -> Self {
+        VisibilityFlags::ALL
     }
 }
 
 #[derive(Clone, Debug)]
 pub enum Object {
     Sphere(Sphere),
+    MovingSphere(MovingSphere),
+    Capsule(Capsule),
+    Plane(Plane),
+    Triangle(Triangle),
+    Quad(Quad),
+    RectXY(Rect),
+    RectXZ(Rect),
+    RectYZ(Rect),
     List(Vec<Object>),
+    /// Places the child, defined in its own local space, into the parent's space.
+    Transform { transform: Similarity3<f32>, child: Box<Object> },
+    /// Moves the child by the offset.
+    Translate { offset: Vector3<f32>, child: Box<Object> },
+    /// Turns the child about the vertical axis by the angle with the given sine and cosine.
+    RotateY { sin: f32, cos: f32, child: Box<Object> },
+    /// Hides the child from some kinds of rays.
+    Visibility { flags: VisibilityFlags, child: Box<Object> },
+    /// Geometry used in several places of a scene without being copied.
+    Shared(Arc<Object>),
+    /// Node of a bounding volume hierarchy, only testing its children against rays which pass
+    /// through their combined bounds.
+    Bvh { bounds: Aabb, left: Box<Object>, right: Box<Object> },
+    /// Fog or smoke of constant density filling the boundary, scattering rays at random points
+    /// inside of it with the phase material.
+    ConstantMedium { boundary: Box<Object>, density: f32, phase: Material },
 }
 
 impl Object {
@@ -68,15 +566,544 @@ impl Object {
         Object::Sphere(Sphere::new(center, radius, material))
     }
 
-    pub fn hit<R>(&self, ray: &Ray, t_rng: R) -> Option<Hit>
+    pub fn moving_sphere(center0: Point3<f32>, center1: Point3<f32>, time0: f32, time1: f32, radius: f32, material: Material) -> Self {
+        Object::MovingSphere(MovingSphere::new(center0, center1, time0, time1, radius, material))
+    }
+
+    pub fn plane(point: Point3<f32>, normal: UnitVector3<f32>, material: Material) -> Self {
+        Object::Plane(Plane::new(point, normal, material))
+    }
+
+    pub fn triangle(a: Point3<f32>, b: Point3<f32>, c: Point3<f32>, material: Material) -> Self {
+        Object::Triangle(Triangle::new(a, b, c, material))
+    }
+
+    pub fn quad(corner: Point3<f32>, u: Vector3<f32>, v: Vector3<f32>, material: Material) -> Self {
+        Object::Quad(Quad::new(corner, u, v, material))
+    }
+
+    /// Rectangular light spanning `u` and `v` from the corner, giving off `emit` scaled by
+    /// `intensity` only from the side `u × v` points towards. It is among the object's `lights`,
+    /// so surfaces sample it directly once those are set as the render settings' lights.
+    pub fn area_light(corner: Point3<f32>, u: Vector3<f32>, v: Vector3<f32>, emit: Color, intensity: f32) -> Self {
+        Object::quad(corner, u, v, Material::one_sided_light(emit * intensity))
+    }
+
+    pub fn rect_xy(min: Point2<f32>, max: Point2<f32>, z: f32, material: Material) -> Self {
+        Object::RectXY(Rect::new(min, max, z, material))
+    }
+
+    pub fn rect_xz(min: Point2<f32>, max: Point2<f32>, y: f32, material: Material) -> Self {
+        Object::RectXZ(Rect::new(min, max, y, material))
+    }
+
+    pub fn rect_yz(min: Point2<f32>, max: Point2<f32>, x: f32, material: Material) -> Self {
+        Object::RectYZ(Rect::new(min, max, x, material))
+    }
+
+    pub fn list(objects: Vec<Object>) -> Self {
+        Object::List(objects)
+    }
+
+    pub fn capsule(a: Point3<f32>, b: Point3<f32>, radius: f32, material: Material) -> Self {
+        Object::Capsule(Capsule::new(a, b, radius, material))
+    }
+
+    /// Nearest intersection along the ray within the range. Random decisions, such as where a ray
+    /// scatters within a medium, are drawn from a generator seeded by the ray, so testing the same
+    /// ray again gives the same result.
+    pub fn hit<R>(&self, ray: &Ray, t_rng: R) -> Option<Hit<'_>>
+        where R: RangeBounds<f32> + Clone {
+        self.hit_with_rng(ray, t_rng, &mut Rng::with_seed(ray_seed(ray)))
+    }
+
+    /// Nearest intersection along the ray within the range, drawing random decisions from `rng`.
+    pub fn hit_with_rng<R>(&self, ray: &Ray, t_rng: R, rng: &mut Rng) -> Option<Hit<'_>>
         where R: RangeBounds<f32> + Clone {
         match self {
-            Object::Sphere(sphere) => sphere.hit(ray, t_rng),
+            Object::Sphere(sphere) => count_hit(sphere.hit(ray, t_rng)),
+            Object::MovingSphere(sphere) => count_hit(sphere.hit(ray, t_rng)),
+            Object::Capsule(capsule) => count_hit(capsule.hit(ray, t_rng)),
+            Object::Plane(plane) => count_hit(plane.hit(ray, t_rng)),
+            Object::Triangle(triangle) => count_hit(triangle.hit(ray, t_rng)),
+            Object::Quad(quad) => count_hit(quad.hit(ray, t_rng)),
+            Object::RectXY(rect) => count_hit(rect.hit(2, ray, t_rng)),
+            Object::RectXZ(rect) => count_hit(rect.hit(1, ray, t_rng)),
+            Object::RectYZ(rect) => count_hit(rect.hit(0, ray, t_rng)),
             Object::List(list) => {
+                count_intersections(0, 1, 0);
                 list.iter()
-                    .filter_map(|obj| obj.hit(ray, t_rng.clone()))
+                    .filter_map(|obj| obj.hit_with_rng(ray, t_rng.clone(), rng))
                     .min_by_key(|hit| FloatOrd(hit.t))
             }
+            Object::Transform { transform, child } => {
+                let local = Ray::new(
+                    transform.inverse_transform_point(&ray.origin),
+                    transform.inverse_transform_vector(&ray.direction),
+                ).with_kind(ray.kind).with_time(ray.time);
+                count_intersections(0, 1, 0);
+                child.hit_with_rng(&local, t_rng, rng).map(|hit| transform_hit(transform, hit))
+            }
+            Object::Translate { offset, child } => {
+                let local = Ray::new(ray.origin - offset, ray.direction).with_kind(ray.kind).with_time(ray.time);
+                count_intersections(0, 1, 0);
+                child.hit_with_rng(&local, t_rng, rng).map(|hit| Hit { point: hit.point + offset, ..hit })
+            }
+            Object::RotateY { sin, cos, child } => {
+                count_intersections(0, 1, 0);
+                child.hit_with_rng(&rotate_y_ray(-sin, *cos, ray), t_rng, rng).map(|hit| rotate_y_hit(*sin, *cos, hit))
+            }
+            Object::Visibility { flags, child } => {
+                count_intersections(0, 1, 0);
+                if flags.visible_to(ray.kind) { child.hit_with_rng(ray, t_rng, rng) } else { None }
+            }
+            Object::Shared(object) => object.hit_with_rng(ray, t_rng, rng),
+            Object::Bvh { bounds, left, right } => {
+                count_intersections(0, 1, 0);
+                let (start, end) = (t_rng.start_bound().cloned(), t_rng.end_bound().cloned());
+                if !bounds.hit(ray, bound_value(start, f32::NEG_INFINITY), bound_value(end, f32::INFINITY)) {
+                    return None;
+                }
+                // only look for hits in the right subtree nearer than the one found in the left.
+                let left = left.hit_with_rng(ray, (start, end), rng);
+                let end = left.as_ref().map_or(end, |hit| Bound::Excluded(hit.t));
+                right.hit_with_rng(ray, (start, end), rng).or(left)
+            }
+            Object::ConstantMedium { boundary, density, phase } => {
+                count_intersections(0, 1, 0);
+                medium_hit(boundary, *density, phase, ray, t_rng, rng)
+            }
+        }
+    }
+
+    /// Every intersection along the ray within the range, ordered by distance, with random decisions
+    /// drawn as by `hit`.
+    pub fn hit_all<R>(&self, ray: &Ray, t_rng: R) -> Vec<Hit<'_>>
+        where R: RangeBounds<f32> + Clone {
+        self.hit_all_with_rng(ray, t_rng, &mut Rng::with_seed(ray_seed(ray)))
+    }
+
+    /// Every intersection along the ray within the range, ordered by distance, drawing random
+    /// decisions from `rng`.
+    pub fn hit_all_with_rng<R>(&self, ray: &Ray, t_rng: R, rng: &mut Rng) -> Vec<Hit<'_>>
+        where R: RangeBounds<f32> + Clone {
+        match self {
+            Object::Sphere(sphere) => count_hits(sphere.hit_all(ray, t_rng)),
+            Object::MovingSphere(sphere) => count_hits(sphere.hit_all(ray, t_rng)),
+            Object::Capsule(capsule) => count_hits(capsule.hit_all(ray, t_rng)),
+            Object::Plane(plane) => count_hits(plane.hit_all(ray, t_rng)),
+            Object::Triangle(triangle) => count_hits(triangle.hit_all(ray, t_rng)),
+            Object::Quad(quad) => count_hits(quad.hit_all(ray, t_rng)),
+            Object::RectXY(rect) => count_hits(rect.hit_all(2, ray, t_rng)),
+            Object::RectXZ(rect) => count_hits(rect.hit_all(1, ray, t_rng)),
+            Object::RectYZ(rect) => count_hits(rect.hit_all(0, ray, t_rng)),
+            Object::List(list) => {
+                count_intersections(0, 1, 0);
+                let mut hits: Vec<_> = list.iter()
+                    .flat_map(|obj| obj.hit_all_with_rng(ray, t_rng.clone(), rng))
+                    .collect();
+                hits.sort_by_key(|hit| FloatOrd(hit.t));
+                hits
+            }
+            Object::Transform { transform, child } => {
+                let local = Ray::new(
+                    transform.inverse_transform_point(&ray.origin),
+                    transform.inverse_transform_vector(&ray.direction),
+                ).with_kind(ray.kind).with_time(ray.time);
+                count_intersections(0, 1, 0);
+                child.hit_all_with_rng(&local, t_rng, rng)
+                    .into_iter()
+                    .map(|hit| transform_hit(transform, hit))
+                    .collect()
+            }
+            Object::Translate { offset, child } => {
+                let local = Ray::new(ray.origin - offset, ray.direction).with_kind(ray.kind).with_time(ray.time);
+                count_intersections(0, 1, 0);
+                child.hit_all_with_rng(&local, t_rng, rng)
+                    .into_iter()
+                    .map(|hit| Hit { point: hit.point + offset, ..hit })
+                    .collect()
+            }
+            Object::RotateY { sin, cos, child } => {
+                count_intersections(0, 1, 0);
+                child.hit_all_with_rng(&rotate_y_ray(-sin, *cos, ray), t_rng, rng)
+                    .into_iter()
+                    .map(|hit| rotate_y_hit(*sin, *cos, hit))
+                    .collect()
+            }
+            Object::Visibility { flags, child } => {
+                count_intersections(0, 1, 0);
+                if flags.visible_to(ray.kind) { child.hit_all_with_rng(ray, t_rng, rng) } else { Vec::new() }
+            }
+            Object::Shared(object) => object.hit_all_with_rng(ray, t_rng, rng),
+            Object::Bvh { bounds, left, right } => {
+                count_intersections(0, 1, 0);
+                let (start, end) = (t_rng.start_bound().cloned(), t_rng.end_bound().cloned());
+                if !bounds.hit(ray, bound_value(start, f32::NEG_INFINITY), bound_value(end, f32::INFINITY)) {
+                    return Vec::new();
+                }
+                let mut hits = left.hit_all_with_rng(ray, t_rng.clone(), rng);
+                hits.extend(right.hit_all_with_rng(ray, t_rng, rng));
+                hits.sort_by_key(|hit| FloatOrd(hit.t));
+                hits
+            }
+            Object::ConstantMedium { boundary, density, phase } => {
+                count_intersections(0, 1, 0);
+                medium_hit(boundary, *density, phase, ray, t_rng, rng).into_iter().collect()
+            }
+        }
+    }
+
+    /// Total area of flat geometry made of triangles, quads and rectangles, or `None` if the object
+    /// holds anything else.
+    pub fn area(&self) -> Option<f32> {
+        match self {
+            Object::Triangle(triangle) => Some(triangle.area()),
+            Object::Quad(quad) => Some(quad.area()),
+            Object::RectXY(rect) | Object::RectXZ(rect) | Object::RectYZ(rect) => Some((rect.max - rect.min).product()),
+            Object::List(list) => list.iter().map(Object::area).sum(),
+            _ => None,
+        }
+    }
+
+    /// Box containing every surface of the object, or `None` if it has none or is unbounded.
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        match self {
+            Object::Sphere(sphere) => Some(sphere.bounding_box()),
+            Object::MovingSphere(sphere) => Some(sphere.bounding_box()),
+            Object::Capsule(capsule) => Some(capsule.bounding_box()),
+            Object::Plane(_) => None,
+            Object::Triangle(triangle) => Some(triangle.bounding_box()),
+            Object::Quad(quad) => Some(quad.bounding_box()),
+            Object::RectXY(rect) => Some(rect.bounding_box(2)),
+            Object::RectXZ(rect) => Some(rect.bounding_box(1)),
+            Object::RectYZ(rect) => Some(rect.bounding_box(0)),
+            Object::List(list) => list.iter()
+                .filter_map(Object::bounding_box)
+                .reduce(|a, b| a.union(&b)),
+            Object::Transform { transform, child } => child.bounding_box().map(|aabb| aabb.transform(transform)),
+            Object::Translate { offset, child } => child.bounding_box()
+                .map(|aabb| Aabb::new(aabb.min + offset, aabb.max + offset)),
+            Object::RotateY { sin, cos, child } => child.bounding_box().map(|aabb| {
+                let corners = aabb.corners().map(|corner| Point3::from(rotate_y(*sin, *cos, &corner.coords)));
+                corners[1..].iter()
+                    .fold(Aabb::new(corners[0], corners[0]), |aabb, corner| aabb.union(&Aabb::new(*corner, *corner)))
+            }),
+            Object::Visibility { child, .. } => child.bounding_box(),
+            Object::Shared(object) => object.bounding_box(),
+            Object::Bvh { bounds, .. } => Some(*bounds),
+            Object::ConstantMedium { boundary, .. } => boundary.bounding_box(),
+        }
+    }
+
+    /// Every emissive quad and sphere in the object, placed in its space, for surfaces to sample
+    /// directly.
+    pub fn lights(&self) -> Vec<Light> {
+        match self {
+            Object::Sphere(sphere) if matches!(sphere.material, Material::DiffuseLight { .. }) => {
+                vec![Light::Sphere { center: sphere.center, radius: sphere.radius }]
+            }
+            Object::Quad(quad) if matches!(quad.material, Material::DiffuseLight { .. }) => {
+                vec![Light::Quad { corner: quad.corner, u: quad.u, v: quad.v }]
+            }
+            Object::List(list) => list.iter().flat_map(Object::lights).collect(),
+            Object::Transform { transform, child } => child.lights().iter()
+                .map(|light| light.transformed(|point| transform * point, |vector| transform * vector))
+                .collect(),
+            Object::Translate { offset, child } => child.lights().iter()
+                .map(|light| light.transformed(|point| point + offset, |vector| *vector))
+                .collect(),
+            Object::RotateY { sin, cos, child } => child.lights().iter()
+                .map(|light| light.transformed(|point| Point3::from(rotate_y(*sin, *cos, &point.coords)), |vector| rotate_y(*sin, *cos, vector)))
+                .collect(),
+            Object::Visibility { child, .. } => child.lights(),
+            Object::Shared(object) => object.lights(),
+            Object::Bvh { left, right, .. } => left.lights().into_iter().chain(right.lights()).collect(),
+            _ => Vec::new(),
         }
     }
+
+    pub fn transform(transform: Similarity3<f32>, child: Object) -> Self {
+        Object::Transform { transform, child: Box::new(child) }
+    }
+
+    /// Fills the boundary, which must be closed, with fog of the given density and color.
+    pub fn constant_medium(boundary: Object, density: f32, albedo: Color) -> Self {
+        Object::ConstantMedium { boundary: Box::new(boundary), density, phase: Material::isotropic(albedo) }
+    }
+
+    pub fn translate(offset: Vector3<f32>, child: Object) -> Self {
+        Object::Translate { offset, child: Box::new(child) }
+    }
+
+    /// Turns the child counter-clockwise, seen from above, about the vertical axis through the
+    /// origin.
+    pub fn rotate_y(angle_deg: f32, child: Object) -> Self {
+        let (sin, cos) = angle_deg.to_radians().sin_cos();
+        Object::RotateY { sin, cos, child: Box::new(child) }
+    }
+
+    /// Groups objects into a bounding volume hierarchy, so rays are tested against a number of them
+    /// roughly logarithmic in their count instead of every one. Unbounded objects are kept in a
+    /// list next to the hierarchy.
+    pub fn bvh(objects: Vec<Object>) -> Self {
+        Bvh::build(&objects).object(objects)
+    }
+
+    /// Lays out `nx` by `nz` instances `spacing` apart along the X and Z axes, starting at the
+    /// origin. The instance in column `i` and row `j` is given by `instance(i, j)`, which may return
+    /// the same geometry for many cells.
+    pub fn grid(nx: u32, nz: u32, spacing: f32, mut instance: impl FnMut(u32, u32) -> Arc<Object>) -> Self {
+        let instances = (0..nz)
+            .flat_map(|j| (0..nx).map(move |i| (i, j)))
+            .map(|(i, j)| {
+                let translation = Translation3::new(i as f32 * spacing, 0.0, j as f32 * spacing);
+                Object::transform(Similarity3::from_parts(translation, UnitQuaternion::identity(), 1.0), Object::Shared(instance(i, j)))
+            })
+            .collect();
+        Object::list(instances)
+    }
+
+    /// Loads the triangles of a Wavefront OBJ mesh, all with the same material, as a list. Lines
+    /// which cannot be understood are reported as `InvalidData` errors. See `load::load_obj`.
+    pub fn load_obj(path: &Path, material: Material) -> io::Result<Object> {
+        Ok(load::load_obj(path, material)?)
+    }
+
+    pub fn visibility(flags: VisibilityFlags, child: Object) -> Self {
+        Object::Visibility { flags, child: Box::new(child) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{point, vector};
+
+    use super::*;
+
+    fn medium(density: f32) -> Object {
+        Object::constant_medium(Object::sphere(Point3::origin(), 1.0, Material::lambert(Color::WHITE)), density, Color::WHITE)
+    }
+
+    fn rays() -> impl Iterator<Item = Ray> {
+        (0..100).map(|i| Ray::new(Point3::new(i as f32 / 200.0, 0.0, -5.0), Vector3::z()))
+    }
+
+    #[test]
+    fn area_light_spans_its_edges_and_emits_from_the_front() {
+        let (u, v) = (vector![2.0, 0.0, 0.0], vector![0.0, 0.0, -3.0]);
+        let light = Object::area_light(point![-1.0, 2.0, 1.0], u, v, Color::WHITE, 4.0);
+        assert!((light.area().expect("flat") - u.cross(&v).magnitude()).abs() < 1e-5);
+
+        // u × v points up.
+        let emitted = |origin: Point3<f32>, direction: Vector3<f32>| {
+            let hit = light.hit(&Ray::new(origin, direction), 0.0..).expect("light");
+            hit.material.emitted(&hit)
+        };
+        assert_eq!(emitted(point![0.0, 5.0, 0.0], -Vector3::y()), Color::WHITE * 4.0);
+        assert_eq!(emitted(Point3::origin(), Vector3::y()), Color::BLACK);
+
+        // registered as a light wherever it is placed.
+        assert_eq!(light.lights(), vec![Light::Quad { corner: point![-1.0, 2.0, 1.0], u, v }]);
+        let moved = Object::translate(vector![0.0, 1.0, 0.0], Object::list(vec![light]));
+        assert_eq!(moved.lights(), vec![Light::Quad { corner: point![-1.0, 3.0, 1.0], u, v }]);
+        assert!(Object::quad(Point3::origin(), u, v, Material::lambert(Color::WHITE)).lights().is_empty());
+    }
+
+    #[test]
+    fn dense_medium_scatters_rays_passing_through() {
+        let mut rng = Rng::with_seed(1);
+        let dense = medium(100.0);
+        let thin = medium(1e-6);
+        assert!(rays().all(|ray| dense.hit_with_rng(&ray, 0.0.., &mut rng).is_some()));
+        assert!(rays().all(|ray| thin.hit_with_rng(&ray, 0.0.., &mut rng).is_none()));
+    }
+
+    #[test]
+    fn medium_scatters_same_ray_at_different_depths() {
+        let mut rng = Rng::with_seed(1);
+        let dense = medium(1.0);
+        let ray = Ray::new(Point3::new(0.0, 0.0, -5.0), Vector3::z());
+        let depths: Vec<_> = (0..8).filter_map(|_| dense.hit_with_rng(&ray, 0.0.., &mut rng)).map(|hit| hit.t).collect();
+        assert!(depths.len() > 1);
+        assert!(depths.iter().any(|&t| t != depths[0]));
+    }
+
+    #[test]
+    fn capsule_hits_body_and_caps_within_its_radius() {
+        let capsule = Object::capsule(point![0.0, -1.0, 0.0], point![0.0, 1.0, 0.0], 0.5, Material::lambert(Color::WHITE));
+        let hit = |origin: Point3<f32>, direction: Vector3<f32>| capsule.hit(&Ray::new(origin, direction), 0.0..);
+
+        let body = hit(point![0.0, 0.0, 5.0], -Vector3::z()).expect("body");
+        assert!((body.point - point![0.0, 0.0, 0.5]).magnitude() < 1e-4);
+        assert!((body.normal - Vector3::z()).magnitude() < 1e-4);
+
+        let cap = hit(point![0.0, 5.0, 0.0], -Vector3::y()).expect("cap");
+        assert!((cap.point - point![0.0, 1.5, 0.0]).magnitude() < 1e-4);
+        assert!((cap.normal - Vector3::y()).magnitude() < 1e-4);
+
+        // beside the body, and above the body's edge but outside of the cap's sphere.
+        assert!(hit(point![0.6, 0.0, 5.0], -Vector3::z()).is_none());
+        assert!(hit(point![0.45, 1.4, 5.0], -Vector3::z()).is_none());
+    }
+
+    #[test]
+    fn nested_spheres_give_four_ordered_hits() {
+        let spheres = Object::list(vec![
+            Object::sphere(Point3::origin(), 1.0, Material::dielectric(1.5)),
+            Object::sphere(Point3::origin(), 2.0, Material::dielectric(1.5)),
+        ]);
+        let hits = spheres.hit_all(&Ray::new(point![0.0, 0.0, 5.0], -Vector3::z()), 0.0..);
+
+        let distances: Vec<_> = hits.iter().map(|hit| hit.t).collect();
+        assert_eq!(distances.len(), 4);
+        for (distance, expected) in distances.iter().zip([3.0, 4.0, 6.0, 7.0]) {
+            assert!((distance - expected).abs() < 1e-4, "{:?}", distances);
+        }
+    }
+
+    #[test]
+    fn object_hidden_from_shadows_still_seen_by_camera() {
+        let flags = VisibilityFlags { shadow: false, ..VisibilityFlags::ALL };
+        let hidden = Object::visibility(flags, Object::sphere(Point3::origin(), 1.0, Material::lambert(Color::WHITE)));
+        let ray = |kind| Ray::new(point![0.0, 0.0, 5.0], -Vector3::z()).with_kind(kind);
+
+        assert!(hidden.hit(&ray(RayKind::Camera), 0.0..).is_some());
+        assert!(hidden.hit(&ray(RayKind::Shadow), 0.0..).is_none());
+    }
+
+    #[test]
+    fn grid_places_an_instance_in_every_cell() {
+        let sphere = Arc::new(Object::sphere(Point3::origin(), 0.2, Material::lambert(Color::WHITE)));
+        let grid = Object::grid(3, 3, 2.0, |_, _| sphere.clone());
+
+        let Object::List(instances) = &grid else { panic!("grid is not a list") };
+        assert_eq!(instances.len(), 9);
+        let down = |x: f32, z: f32| Ray::new(point![x, 5.0, z], -Vector3::y());
+        for i in 0..3 {
+            for j in 0..3 {
+                let (x, z) = (i as f32 * 2.0, j as f32 * 2.0);
+                assert!(grid.hit(&down(x, z), 0.0..).is_some(), "no instance at {x}, {z}");
+                assert!(grid.hit(&down(x + 1.0, z), 0.0..).is_none());
+            }
+        }
+        assert_eq!(Arc::strong_count(&sphere), 10);
+    }
+
+    #[test]
+    fn list_hit_returns_the_nearer_material() {
+        let near = Material::lambert(Color::new(1.0, 0.0, 0.0, 1.0));
+        let far = Material::metal(Color::WHITE, 0.0);
+        let list = Object::list(vec![
+            Object::sphere(point![0.0, 0.0, -10.0], 1.0, far),
+            Object::sphere(point![0.0, 0.0, -4.0], 1.0, near),
+        ]);
+
+        let hit = list.hit(&Ray::new(Point3::origin(), -Vector3::z()), 0.0..).expect("hit");
+        assert!((hit.t - 3.0).abs() < 1e-5);
+        assert!(matches!(hit.material, Material::Lambert { .. }));
+    }
+
+    #[test]
+    fn ray_at_a_unit_triangle_hits_it() {
+        let triangle = Triangle::new(Point3::origin(), point![1.0, 0.0, 0.0], point![0.0, 1.0, 0.0], Material::lambert(Color::WHITE));
+        let ray = Ray::new(point![0.25, 0.5, 2.0], -Vector3::z());
+
+        let hit = triangle.hit(&ray, 0.0..).expect("hit");
+        assert!((hit.t - 2.0).abs() < 1e-6);
+        assert!((hit.point - point![0.25, 0.5, 0.0]).magnitude() < 1e-6);
+        assert!((hit.u - 0.25).abs() < 1e-6 && (hit.v - 0.5).abs() < 1e-6);
+        assert!(matches!(hit.face, Face::Front));
+
+        assert!(triangle.hit(&ray, 0.0..1.5).is_none());
+        assert!(triangle.hit(&Ray::new(point![0.8, 0.8, 2.0], -Vector3::z()), 0.0..).is_none());
+        assert!(triangle.hit(&Ray::new(point![0.2, 0.2, 2.0], Vector3::x()), 0.0..).is_none());
+    }
+
+    #[test]
+    fn sphere_bounds_and_their_union() {
+        let white = || Material::lambert(Color::WHITE);
+        let a = Object::sphere(point![1.0, 2.0, 3.0], 0.5, white());
+        let b = Object::sphere(point![-2.0, 0.0, 0.0], 1.0, white());
+        assert_eq!(a.bounding_box(), Some(Aabb::new(point![0.5, 1.5, 2.5], point![1.5, 2.5, 3.5])));
+
+        let union = Object::list(vec![a, b]).bounding_box().unwrap();
+        assert_eq!(union, Aabb::new(point![-3.0, -1.0, -1.0], point![1.5, 2.5, 3.5]));
+        assert!(union.hit(&Ray::new(point![-10.0, 0.0, 0.0], Vector3::x()), 0.0, f32::INFINITY));
+        assert!(!union.hit(&Ray::new(point![-10.0, 5.0, 0.0], Vector3::x()), 0.0, f32::INFINITY));
+        assert_eq!(Object::list(Vec::new()).bounding_box(), None);
+    }
+
+    #[test]
+    fn rotated_translated_sphere_is_hit_where_it_was_moved() {
+        let sphere = Object::translate(vector![2.0, 0.0, 0.0], Object::sphere(Point3::origin(), 1.0, Material::lambert(Color::WHITE)));
+        let rotated = Object::translate(vector![0.0, 1.0, 0.0], Object::rotate_y(90.0, sphere));
+
+        // a quarter turn takes the sphere from +x to -z.
+        let hit = rotated.hit(&Ray::new(point![0.0, 5.0, -2.0], -Vector3::y()), 0.0..).expect("hit");
+        assert!((hit.point - point![0.0, 2.0, -2.0]).magnitude() < 1e-4, "{}", hit.point);
+        assert!((hit.normal - Vector3::y()).magnitude() < 1e-4);
+        assert!(matches!(hit.face, Face::Front));
+
+        let side = rotated.hit(&Ray::new(point![-5.0, 1.0, -2.0], Vector3::x()), 0.0..).expect("hit");
+        assert!((side.point - point![-1.0, 1.0, -2.0]).magnitude() < 1e-4, "{}", side.point);
+        assert!((side.normal + Vector3::x()).magnitude() < 1e-4);
+        assert!(rotated.hit(&Ray::new(point![2.0, 5.0, 0.0], -Vector3::y()), 0.0..).is_none());
+    }
+
+    #[test]
+    fn xz_rect_is_hit_within_its_bounds() {
+        let rect = Object::rect_xz(point![0.0, 0.0], point![2.0, 1.0], 3.0, Material::lambert(Color::WHITE));
+        let down = |x: f32, z: f32| Ray::new(point![x, 5.0, z], -Vector3::y());
+
+        let hit = rect.hit(&down(1.5, 0.25), 0.0..).expect("hit");
+        assert!((hit.t - 2.0).abs() < 1e-6);
+        assert!((hit.normal - Vector3::y()).magnitude() < 1e-6);
+        assert!((hit.u - 0.75).abs() < 1e-6 && (hit.v - 0.25).abs() < 1e-6);
+
+        assert!(rect.hit(&down(2.5, 0.25), 0.0..).is_none());
+        assert!(rect.hit(&down(1.5, 1.25), 0.0..).is_none());
+        assert!(rect.hit(&Ray::new(point![1.0, 5.0, 0.5], Vector3::x()), 0.0..).is_none());
+    }
+
+    #[test]
+    fn moving_sphere_is_hit_where_it_is_at_the_ray_time() {
+        let sphere = Object::moving_sphere(Point3::origin(), point![2.0, 0.0, 0.0], 0.0, 1.0, 0.5, Material::lambert(Color::WHITE));
+        let ray = |time: f32| Ray::new(point![-5.0, 0.0, 0.0], Vector3::x()).with_time(time);
+
+        let start = sphere.hit(&ray(0.0), 0.0..).expect("hit at time 0");
+        let end = sphere.hit(&ray(1.0), 0.0..).expect("hit at time 1");
+        assert!((start.point - point![-0.5, 0.0, 0.0]).magnitude() < 1e-5);
+        assert!((end.point - point![1.5, 0.0, 0.0]).magnitude() < 1e-5);
+
+        let bounds = sphere.bounding_box().unwrap();
+        assert!(bounds.contains(&point![-0.5, 0.0, 0.0]) && bounds.contains(&point![2.5, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn sphere_uvs_at_poles_and_equator() {
+        let sphere = Object::sphere(point![0.0, 1.0, 0.0], 2.0, Material::lambert(Color::WHITE));
+        let uv = |origin: Point3<f32>| {
+            let hit = sphere.hit(&Ray::new(origin, point![0.0, 1.0, 0.0] - origin), 0.0..).expect("hit");
+            (hit.u, hit.v)
+        };
+        let close = |(u, v): (f32, f32), expected: (f32, f32)| (u - expected.0).abs() < 1e-4 && (v - expected.1).abs() < 1e-4;
+
+        assert!(uv(point![0.0, 10.0, 0.0]).1 > 0.9999);
+        assert!(uv(point![0.0, -10.0, 0.0]).1 < 1e-4);
+        assert!(close(uv(point![10.0, 1.0, 0.0]), (0.5, 0.5)), "{:?}", uv(point![10.0, 1.0, 0.0]));
+        assert!(close(uv(point![0.0, 1.0, 10.0]), (0.25, 0.5)), "{:?}", uv(point![0.0, 1.0, 10.0]));
+        assert!(close(uv(point![0.0, 1.0, -10.0]), (0.75, 0.5)), "{:?}", uv(point![0.0, 1.0, -10.0]));
+    }
+
+    #[test]
+    fn capsule_without_length_is_a_single_sphere() {
+        let point = point![1.0, 2.0, 0.0];
+        let capsule = Object::capsule(point, point, 0.5, Material::lambert(Color::WHITE));
+        let ray = Ray::new(point![1.0, 2.0, 5.0], -Vector3::z());
+
+        let ts: Vec<_> = capsule.hit_all(&ray, 0.0..).iter().map(|hit| hit.t).collect();
+        assert_eq!(ts.len(), 2, "{:?}", ts);
+        assert!((ts[0] - 4.5).abs() < 1e-5 && (ts[1] - 5.5).abs() < 1e-5, "{:?}", ts);
+    }
 }
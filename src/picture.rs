@@ -1,11 +1,17 @@
+use std::fs::File;
+use std::io::BufWriter;
 use std::iter::Sum;
 use std::ops::{Add, Mul};
+use std::path::Path;
 
 use bytemuck_derive::{AnyBitPattern, NoUninit};
 use nalgebra::Vector3;
 use wgpu::TextureFormat;
 
-#[derive(Copy, Clone, Debug)]
+use crate::metadata::{METADATA_KEYWORD, RenderMetadata};
+use crate::render::{gamma_correct, ToneMap};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Color {
     pub r: f32,
     pub g: f32,
@@ -31,6 +37,32 @@ impl Color {
         Self { r, g, b, a }
     }
 
+    /// Limits each color channel to the range, leaving alpha untouched.
+    pub fn clamp(&self, min: f32, max: f32) -> Color {
+        Color::new(
+            self.r.clamp(min, max),
+            self.g.clamp(min, max),
+            self.b.clamp(min, max),
+            self.a,
+        )
+    }
+
+    /// Limits each color channel to `0..=1`.
+    pub fn saturate(&self) -> Color {
+        self.clamp(0.0, 1.0)
+    }
+
+    /// Relative luminance using Rec. 709 weights.
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    /// Gray of equal luminance.
+    pub fn to_grayscale(&self) -> Color {
+        let luminance = self.luminance();
+        Color::new(luminance, luminance, luminance, self.a)
+    }
+
     pub fn visualize_normal(vector: &Vector3<f32>) -> Self {
         Color::new(
             (vector.x + 1.0) * 0.5,
@@ -67,11 +99,13 @@ impl Mul<f32> for Color {
     }
 }
 
+/// Componentwise product, as when a surface tints incoming light. Keeps the alpha of the left
+/// operand.
 impl Mul<Color> for Color {
     type Output = Color;
 
     fn mul(self, rhs: Color) -> Self::Output {
-        Color::new(self.r * rhs.r, self.g * rhs.g, self.b * rhs.b, self.a * rhs.a)
+        Color::new(self.r * rhs.r, self.g * rhs.g, self.b * rhs.b, self.a)
     }
 }
 
@@ -114,7 +148,7 @@ fn normalize(value: f32) -> u8 {
 }
 
 impl RGBA8 {
-    pub(crate) const WHITE: RGBA8 = RGBA8::new_hex(0xFFFFFFFF);
+    pub const WHITE: RGBA8 = RGBA8::new_hex(0xFFFFFFFF);
 
     pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
         RGBA8 { r, g, b, a }
@@ -144,6 +178,7 @@ pub trait PixelFormat: From<Color> + bytemuck::AnyBitPattern + bytemuck::NoUnini
     fn texture_format() -> TextureFormat;
 }
 
+#[derive(Debug, PartialEq)]
 pub struct Picture<P> {
     pixels: P,
     size: (u32, u32),
@@ -167,13 +202,23 @@ impl<P> Picture<P> {
     }
 }
 
-impl<'a, T> Picture<&'a [T]> {
+impl<T> Picture<Vec<T>> {
+    pub fn view(&self) -> Picture<&[T]> {
+        Picture::new(&self.pixels, self.size)
+    }
+
+    pub fn view_mut(&mut self) -> Picture<&mut [T]> {
+        Picture::new(&mut self.pixels, self.size)
+    }
+}
+
+impl<T> Picture<&[T]> {
     pub fn pixel(&self, x: u32, y: u32) -> &T {
         &self.pixels[self.to_index(x, y)]
     }
 }
 
-impl<'a, T> Picture<&'a mut [T]> {
+impl<T> Picture<&mut [T]> {
     pub fn pixel_mut(&mut self, x: u32, y: u32) -> &mut T {
         let index = self.to_index(x, y);
         &mut self.pixels[index]
@@ -189,7 +234,7 @@ impl<'a, T> Picture<&'a mut [T]> {
     }
 }
 
-impl<'a> Picture<&'a mut [RGBA8]> {
+impl Picture<&mut [RGBA8]> {
     pub fn fill_gradient(&mut self) {
         let (width, height) = self.size;
         for y in 0..height {
@@ -206,3 +251,233 @@ impl<'a> Picture<&'a mut [RGBA8]> {
         self.pixels.fill(color);
     }
 }
+
+impl<T: Copy> Picture<&[T]> {
+    /// Pixels reordered to start at the top row, as image files do, where pictures start at the
+    /// bottom row.
+    fn top_down_rows(&self) -> Vec<T> {
+        self.pixels.chunks(self.width().max(1) as usize).rev().flatten().copied().collect()
+    }
+}
+
+impl<T: Copy + Into<Color>> Picture<&[T]> {
+    /// Shrinks the picture to fit within `max_dim` pixels in both dimensions, keeping its aspect
+    /// ratio, by averaging the pixels covered by each pixel of the result. Pictures which already
+    /// fit are kept at their size.
+    fn downscale(&self, max_dim: u32) -> Picture<Vec<Color>> {
+        let (width, height) = self.size;
+        let scale = (max_dim as f32 / width.max(height) as f32).min(1.0);
+        let target = (
+            ((width as f32 * scale).round() as u32).max(1),
+            ((height as f32 * scale).round() as u32).max(1),
+        );
+        // source pixels covered by a target pixel along one dimension.
+        let span = |i: u32, source: u32, target: u32| {
+            let from = i * source / target;
+            let to = ((i + 1) * source / target).max(from + 1);
+            from..to
+        };
+
+        let pixels = (0..target.1)
+            .flat_map(|y| (0..target.0).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (xs, ys) = (span(x, width, target.0), span(y, height, target.1));
+                let count = (xs.len() * ys.len()) as f32;
+                let sum: Color = ys.flat_map(|y| xs.clone().map(move |x| (x, y)))
+                    .map(|(x, y)| (*self.pixel(x, y)).into())
+                    .sum();
+                sum * (1.0 / count)
+            })
+            .collect();
+        Picture::new(pixels, target)
+    }
+}
+
+impl Picture<&[RGBA8]> {
+    /// Box-filtered copy fitting within `max_dim` pixels in both dimensions.
+    pub fn thumbnail(&self, max_dim: u32) -> Picture<Vec<RGBA8>> {
+        let small = self.downscale(max_dim);
+        Picture::new(small.pixels.into_iter().map(RGBA8::from).collect(), small.size)
+    }
+}
+
+impl Picture<&[Color]> {
+    /// Box-filtered copy fitting within `max_dim` pixels in both dimensions, with the linear high
+    /// dynamic range colors tone mapped and gamma corrected for display as a render with the same
+    /// settings would be.
+    pub fn thumbnail(&self, max_dim: u32, tone_map: ToneMap, gamma: f32) -> Picture<Vec<RGBA8>> {
+        let small = self.downscale(max_dim);
+        let pixels = small.pixels.into_iter()
+            .map(|color| gamma_correct(tone_map.apply(color), gamma).into())
+            .collect();
+        Picture::new(pixels, small.size)
+    }
+}
+
+/// Width of the divider between the halves of a comparison image.
+pub const COMPARISON_DIVIDER: u32 = 4;
+
+impl Picture<&[RGBA8]> {
+    /// Places this picture and another of equal size next to each other, separated by a divider.
+    pub fn side_by_side(&self, other: &Picture<&[RGBA8]>, divider: u32, divider_color: RGBA8) -> Picture<Vec<RGBA8>> {
+        assert_eq!(self.size, other.size, "compared pictures must be of equal size");
+        let (width, height) = self.size;
+        let combined_width = width * 2 + divider;
+
+        let mut pixels = Vec::with_capacity(combined_width as usize * height as usize);
+        for y in 0..height {
+            let row = self.to_index(0, y)..self.to_index(0, y) + width as usize;
+            pixels.extend_from_slice(&self.pixels[row.clone()]);
+            pixels.extend((0..divider).map(|_| divider_color));
+            pixels.extend_from_slice(&other.pixels[row]);
+        }
+        Picture::new(pixels, (combined_width, height))
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), png::EncodingError> {
+        self.write_png(path, None)
+    }
+
+    /// Saves the picture as a PNG, along with what is needed to render it again. See
+    /// `read_render_metadata`.
+    pub fn save_png_with_metadata(&self, path: impl AsRef<Path>, metadata: &RenderMetadata) -> Result<(), png::EncodingError> {
+        self.write_png(path, Some(metadata))
+    }
+
+    fn write_png(&self, path: impl AsRef<Path>, metadata: Option<&RenderMetadata>) -> Result<(), png::EncodingError> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width(), self.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        if let Some(metadata) = metadata {
+            encoder.add_text_chunk(METADATA_KEYWORD.to_string(), metadata.to_text())?;
+        }
+        encoder.write_header()?.write_image_data(bytemuck::cast_slice(&self.top_down_rows()))
+    }
+}
+
+/// Saves a before/after comparison of two equally sized pictures as a PNG.
+pub fn save_comparison(before: &Picture<&[RGBA8]>, after: &Picture<&[RGBA8]>, path: impl AsRef<Path>) -> Result<(), png::EncodingError> {
+    before.side_by_side(after, COMPARISON_DIVIDER, RGBA8::WHITE)
+        .view()
+        .save_png(path)
+}
+
+impl Picture<&[u16]> {
+    /// Saves the picture as a 16-bit grayscale PNG.
+    pub fn save_png16(&self, path: impl AsRef<Path>) -> Result<(), png::EncodingError> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width(), self.height());
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        let bytes: Vec<u8> = self.top_down_rows().iter().flat_map(|value| value.to_be_bytes()).collect();
+        encoder.write_header()?.write_image_data(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ray-tracing-{}-{}", std::process::id(), name))
+    }
+
+    fn numbered(size: (u32, u32), offset: u8) -> Picture<Vec<RGBA8>> {
+        let pixels = (0..size.0 * size.1).map(|i| RGBA8::new(i as u8 + offset, 0, 0, 255)).collect();
+        Picture::new(pixels, size)
+    }
+
+    #[test]
+    fn thumbnail_fits_within_max_dim() {
+        let picture = Picture::new(vec![Color::new(0.25, 0.25, 0.25, 1.0); 100 * 50], (100, 50));
+        let thumbnail = picture.view().thumbnail(10, ToneMap::Clamp, 2.0);
+        let thumbnail = thumbnail.view();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (10, 5));
+        // encoded as a render with the same tone map and gamma would be.
+        assert_eq!(thumbnail.pixel(3, 2).r, RGBA8::from(Color::new(0.5, 0.5, 0.5, 1.0)).r);
+
+        let thumbnail = numbered((100, 50), 0).view().thumbnail(10);
+        assert_eq!((thumbnail.view().width(), thumbnail.view().height()), (10, 5));
+    }
+
+    #[test]
+    fn side_by_side_places_both_pictures_around_the_divider() {
+        let (before, after) = (numbered((3, 2), 0), numbered((3, 2), 100));
+        let combined = before.view().side_by_side(&after.view(), 2, RGBA8::WHITE);
+        let combined = combined.view();
+
+        assert_eq!((combined.width(), combined.height()), (3 + 2 + 3, 2));
+        for y in 0..2 {
+            for x in 0..3 {
+                assert_eq!(combined.pixel(x, y).r, before.view().pixel(x, y).r);
+                assert_eq!(combined.pixel(x + 5, y).r, after.view().pixel(x, y).r);
+            }
+            assert_eq!(combined.pixel(3, y).r, 255);
+            assert_eq!(combined.pixel(4, y).r, 255);
+        }
+    }
+
+    #[test]
+    fn png_starts_at_the_top_row() {
+        let path = temp_path("top-row.png");
+        numbered((2, 3), 0).view().save_png(&path).unwrap();
+
+        let mut reader = png::Decoder::new(File::open(&path).unwrap()).read_info().unwrap();
+        let mut bytes = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut bytes).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // the first pixel written is the first of the picture's last row.
+        assert_eq!(bytes[0], 4);
+        assert_eq!(bytes[4], 5);
+        assert_eq!(bytes[8 * 2], 0);
+    }
+
+    #[test]
+    fn png16_starts_at_the_top_row() {
+        let path = temp_path("top-row-16.png");
+        Picture::new(vec![1u16, 2, 3, 4, 5, 6], (2, 3)).view().save_png16(&path).unwrap();
+
+        let mut reader = png::Decoder::new(File::open(&path).unwrap()).read_info().unwrap();
+        let mut bytes = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut bytes).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let values: Vec<u16> = bytes.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+        assert_eq!(values, [5, 6, 3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn clamp_limits_channels_but_not_alpha() {
+        let color = Color::new(2.0, 0.5, -1.0, 0.25).clamp(0.0, 1.5);
+        assert_eq!(color, Color::new(1.5, 0.5, 0.0, 0.25));
+    }
+
+    #[test]
+    fn saturate_raises_negative_channels_to_zero() {
+        assert_eq!(Color::new(-0.5, 0.25, 3.0, 1.0).saturate(), Color::new(0.0, 0.25, 1.0, 1.0));
+    }
+
+    #[test]
+    fn green_outshines_blue() {
+        let green = Color::new(0.0, 1.0, 0.0, 1.0);
+        let blue = Color::new(0.0, 0.0, 1.0, 1.0);
+        assert!(green.luminance() > blue.luminance());
+    }
+
+    #[test]
+    fn grayscale_keeps_luminance() {
+        let color = Color::new(0.8, 0.3, 0.1, 1.0);
+        let gray = color.to_grayscale();
+        assert!((gray.luminance() - color.luminance()).abs() < 1e-6);
+        assert!(gray.r == gray.g && gray.g == gray.b);
+    }
+
+    #[test]
+    fn red_times_white_is_red() {
+        let red = Color::new(0.9, 0.1, 0.1, 1.0);
+        assert_eq!(red * Color::WHITE, red);
+        assert_eq!(red * Color::new(0.5, 1.0, 0.0, 0.2), Color::new(0.45, 0.1, 0.0, 1.0));
+    }
+}
@@ -0,0 +1,96 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::picture::{Picture, RGBA8};
+use crate::render::{render_into, RenderSettings};
+use crate::scene::Scene;
+
+/// Frame of a scene to be rendered by a `RenderPool`.
+#[derive(Clone, Debug)]
+pub struct RenderJob {
+    pub scene: Scene,
+    pub size: (u32, u32),
+    pub settings: RenderSettings,
+}
+
+/// Frame rendered for the job submitted with the given id.
+pub struct RenderResult {
+    pub id: usize,
+    pub picture: Picture<Vec<RGBA8>>,
+}
+
+/// Renders batches of frames on a shared set of threads, handing each one back as soon as it is
+/// finished.
+pub struct RenderPool {
+    pool: ThreadPool,
+    sender: Sender<RenderResult>,
+    receiver: Receiver<RenderResult>,
+    submitted: usize,
+}
+
+impl RenderPool {
+    /// Pool using the given number of threads, or one per CPU core when zero.
+    pub fn new(threads: usize) -> Self {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|index| format!("render-pool-{}", index))
+            .build()
+            .expect("render thread pool");
+        let (sender, receiver) = channel();
+        RenderPool { pool, sender, receiver, submitted: 0 }
+    }
+
+    /// Queues a job, returning the id its result will carry.
+    pub fn submit(&mut self, job: RenderJob) -> usize {
+        let id = self.submitted;
+        self.submitted += 1;
+        let sender = self.sender.clone();
+        self.pool.spawn(move || {
+            let (width, height) = job.size;
+            let mut pixels = vec![RGBA8::default(); width as usize * height as usize];
+            render_into(&mut pixels, job.size, &job.scene.camera, &job.scene.world, &job.settings);
+            // the pool may have been dropped along with the receiver.
+            let _ = sender.send(RenderResult { id, picture: Picture::new(pixels, job.size) });
+        });
+        id
+    }
+
+    /// Results of finished jobs, in the order they finish.
+    pub fn results(&self) -> &Receiver<RenderResult> {
+        &self.receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use nalgebra::point;
+
+    use crate::camera::CameraBuilder;
+    use crate::material::Material;
+    use crate::object::Object;
+    use crate::picture::Color;
+
+    use super::*;
+
+    #[test]
+    fn two_jobs_give_two_results() {
+        let scene = Scene {
+            world: Object::sphere(point![0.0, 0.0, 0.0], 1.0, Material::lambert(Color::WHITE)),
+            camera: CameraBuilder::new(point![0.0, 0.0, 5.0]).build(),
+        };
+        let job = |size| RenderJob { scene: scene.clone(), size, settings: RenderSettings::default() };
+        let mut pool = RenderPool::new(2);
+        let ids = [pool.submit(job((4, 3))), pool.submit(job((2, 5)))];
+        assert_eq!(ids, [0, 1]);
+
+        let mut results: Vec<_> = (0..2)
+            .map(|_| pool.results().recv_timeout(Duration::from_secs(30)).expect("render result"))
+            .collect();
+        results.sort_by_key(|result| result.id);
+        let sizes: Vec<_> = results.iter().map(|result| (result.id, result.picture.width(), result.picture.height())).collect();
+        assert_eq!(sizes, [(0, 4, 3), (1, 2, 5)]);
+    }
+}
@@ -0,0 +1,118 @@
+use nalgebra::vector;
+
+use crate::camera::Camera;
+use crate::picture::{Color, Picture};
+
+/// Post-processing step spreading bright, out-of-focus highlights into disks shaped like the
+/// camera's aperture.
+pub struct Bokeh {
+    /// Channel value above which a pixel is considered a highlight.
+    pub threshold: f32,
+}
+
+struct Highlight {
+    x: u32,
+    y: u32,
+    excess: Color,
+    radius: f32,
+}
+
+impl Bokeh {
+    pub fn new(threshold: f32) -> Self {
+        Bokeh { threshold }
+    }
+
+    /// Moves the energy above the threshold of every highlight into a kernel sized by its circle of
+    /// confusion. `hdr` and `depth` must be of equal size, with depth measured from the camera.
+    pub fn apply(&self, hdr: &mut Picture<&mut [Color]>, depth: &Picture<&[f32]>, camera: &Camera) {
+        let (width, height) = (hdr.width(), hdr.height());
+        assert_eq!((width, height), (depth.width(), depth.height()), "depth and hdr size");
+
+        let mut highlights = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let radius = camera.circle_of_confusion(*depth.pixel(x, y), height as f32);
+                if radius < 1.0 {
+                    continue;
+                }
+
+                let pixel = hdr.pixel_mut(x, y);
+                let excess = Color::new(
+                    (pixel.r - self.threshold).max(0.0),
+                    (pixel.g - self.threshold).max(0.0),
+                    (pixel.b - self.threshold).max(0.0),
+                    0.0,
+                );
+                if excess.r + excess.g + excess.b <= 0.0 {
+                    continue;
+                }
+
+                pixel.r -= excess.r;
+                pixel.g -= excess.g;
+                pixel.b -= excess.b;
+                highlights.push(Highlight { x, y, excess, radius });
+            }
+        }
+
+        for highlight in highlights {
+            let reach = highlight.radius.ceil() as i64;
+            let kernel: Vec<_> = (-reach..=reach)
+                .flat_map(|dy| (-reach..=reach).map(move |dx| (dx, dy)))
+                .filter(|&(dx, dy)| {
+                    let offset = vector![dx as f32, dy as f32] / highlight.radius;
+                    camera.aperture_shape.contains(&offset)
+                })
+                .collect();
+            let share = highlight.excess * (1.0 / kernel.len() as f32);
+
+            for (dx, dy) in kernel {
+                let x = highlight.x as i64 + dx;
+                let y = highlight.y as i64 + dy;
+                if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                    continue;
+                }
+                let pixel = hdr.pixel_mut(x as u32, y as u32);
+                pixel.r += share.r;
+                pixel.g += share.g;
+                pixel.b += share.b;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::point;
+
+    use crate::camera::{ApertureShape, CameraBuilder};
+
+    use super::*;
+
+    #[test]
+    fn background_highlight_spreads_into_aperture_shape() {
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0])
+            .fov_deg(40.0)
+            .aperture(2.0)
+            .aperture_shape(ApertureShape::Polygon { blades: 4, rotation: 0.0 })
+            .focus_distance(5.0)
+            .build();
+        let size = (33, 33);
+        let mut hdr = Picture::new(vec![Color::BLACK; 33 * 33], size);
+        *hdr.view_mut().pixel_mut(16, 16) = Color::new(100.0, 100.0, 100.0, 1.0);
+        // far behind the focus plane.
+        let depth = Picture::new(vec![20.0; 33 * 33], size);
+
+        Bokeh::new(1.0).apply(&mut hdr.view_mut(), &depth.view(), &camera);
+        let radius = camera.circle_of_confusion(20.0, 33.0);
+        assert!(radius > 4.0);
+        let lit = |dx: f32, dy: f32| {
+            let pixel = hdr.view().pixel((16.0 + dx * radius).round() as u32, (16.0 + dy * radius).round() as u32).r;
+            pixel > 0.0
+        };
+
+        // four blades with a corner along x make a diamond, which a circle would fill out.
+        assert!(lit(0.0, 0.0) && lit(0.9, 0.0) && lit(0.0, -0.9) && lit(0.4, 0.4));
+        assert!(!lit(0.6, 0.6) && !lit(-0.6, 0.6));
+        assert!(!lit(1.3, 0.0));
+    }
+}
@@ -1,14 +1,36 @@
 use nalgebra::{Point3, Vector3};
 use crate::material::Material;
 
+/// What a ray is cast for, which decides the objects it can see.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RayKind {
+    /// Leaves the camera through a pixel.
+    Camera,
+    /// Scattered off a surface.
+    Reflection,
+    /// Only tests whether a point is occluded.
+    Shadow,
+}
+
 pub struct Ray {
     pub origin: Point3<f32>,
     pub direction: Vector3<f32>,
+    pub kind: RayKind,
+    /// Moment the ray is cast at, for geometry which moves while the shutter is open.
+    pub time: f32,
 }
 
 impl Ray {
     pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Self {
-        Self { origin, direction }
+        Self { origin, direction, kind: RayKind::Reflection, time: 0.0 }
+    }
+
+    pub fn with_kind(self, kind: RayKind) -> Self {
+        Self { kind, ..self }
+    }
+
+    pub fn with_time(self, time: f32) -> Self {
+        Self { time, ..self }
     }
 
     pub fn at(&self, t: f32) -> Point3<f32> {
@@ -26,5 +48,33 @@ pub struct Hit<'a> {
     pub normal: Vector3<f32>,
     pub face: Face,
     pub t: f32,
+    /// Coordinates of the hit across the surface, where the primitive defines them. Barycentric
+    /// weights of the second and third vertices for triangles.
+    pub u: f32,
+    pub v: f32,
     pub material: &'a Material,
 }
+
+impl<'a> Hit<'a> {
+    /// Hit at `t` along the ray, with the normal facing against the ray.
+    pub fn new(ray: &Ray, t: f32, outward_normal: Vector3<f32>, material: &'a Material) -> Self {
+        let (face, normal) = if ray.direction.dot(&outward_normal) < 0.0 {
+            (Face::Front, outward_normal)
+        } else {
+            (Face::Back, -outward_normal)
+        };
+        Hit {
+            point: ray.at(t),
+            normal,
+            face,
+            t,
+            u: 0.0,
+            v: 0.0,
+            material,
+        }
+    }
+
+    pub fn with_uv(self, u: f32, v: f32) -> Self {
+        Hit { u, v, ..self }
+    }
+}
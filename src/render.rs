@@ -1,27 +1,86 @@
-use std::iter::{once, repeat_with};
-use std::ops::{Range};
-use std::sync::Mutex;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::iter::repeat_with;
+use std::ops::{Add, Bound, Range};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use log::trace;
-use nalgebra::{point, Point2, vector, Vector2, Vector3};
+use float_ord::FloatOrd;
+use log::{debug, trace};
+use nalgebra::{point, Point2, Point3, Rotation3, vector, Vector2, Vector3};
 use rayon::prelude::*;
 
+use crate::background::{Background, EnvironmentSampler, IrradianceMap};
 use crate::camera::{Camera, Viewport};
 use crate::gpu::Frame;
+use crate::light::Light;
+use crate::material::{Material, sample_matcap, ScatterKind};
 use crate::object::Object;
-use crate::picture::{Color, PixelFormat};
-use crate::ray::{Hit, Ray};
+use crate::picture::{Color, Picture, PixelFormat};
+use crate::ray::{Hit, Ray, RayKind};
 
 pub trait SamplePattern: Sync {
-    fn sample_offsets(&self) -> &[Vector2<f32>];
+    /// Offsets within a pixel, in `[0, 1)` along both axes, to take one sample at each. Fixed
+    /// patterns borrow their offsets, while random ones draw new offsets from the pixel's generator
+    /// on every call.
+    fn sample_offsets(&self, rng: &mut Rng) -> Cow<'_, [Vector2<f32>]>;
+
+    /// Number of offsets returned by `sample_offsets`.
+    fn sample_count(&self) -> usize;
 }
 
 impl<const N: usize> SamplePattern for [Vector2<f32>; N] {
-    fn sample_offsets(&self) -> &[Vector2<f32>] {
-        self
+    fn sample_offsets(&self, _rng: &mut Rng) -> Cow<'_, [Vector2<f32>]> {
+        Cow::Borrowed(self)
+    }
+
+    fn sample_count(&self) -> usize {
+        N
+    }
+}
+
+impl<T: SamplePattern + ?Sized> SamplePattern for &T {
+    fn sample_offsets(&self, rng: &mut Rng) -> Cow<'_, [Vector2<f32>]> {
+        (**self).sample_offsets(rng)
+    }
+
+    fn sample_count(&self) -> usize {
+        (**self).sample_count()
+    }
+}
+
+/// One sample placed at random within each cell of a `grid` by `grid` division of the pixel, so the
+/// samples differ between calls while staying evenly spread. Suited to accumulating many passes.
+#[derive(Copy, Clone, Debug)]
+pub struct StratifiedPattern {
+    pub grid: u32,
+}
+
+impl StratifiedPattern {
+    pub const fn new(grid: u32) -> Self {
+        StratifiedPattern { grid }
+    }
+}
+
+impl SamplePattern for StratifiedPattern {
+    fn sample_offsets(&self, rng: &mut Rng) -> Cow<'_, [Vector2<f32>]> {
+        let size = 1.0 / self.grid as f32;
+        (0..self.grid)
+            .flat_map(|y| (0..self.grid).map(move |x| (x, y)))
+            // the sum may round up to 1 for large grids.
+            .map(|(x, y)| vector![(x as f32 + rng.f32()) * size, (y as f32 + rng.f32()) * size].map(|c| c.min(ONE_BELOW)))
+            .collect()
+    }
+
+    fn sample_count(&self) -> usize {
+        (self.grid * self.grid) as usize
     }
 }
 
+/// Largest `f32` below one.
+const ONE_BELOW: f32 = 1.0 - f32::EPSILON / 2.0;
+
 // patterns based on DirectX (https://learn.microsoft.com/en-us/windows/win32/api/d3d11/ne-d3d11-d3d11_standard_multisample_quality_levels)
 // 1/16=0.0625
 pub const SINGLE_SAMPLE_PATTERN: [Vector2<f32>; 1] = [vector![0.5, 0.5]];
@@ -45,7 +104,94 @@ pub const MULTISAMPLE_8X_PATTERN: [Vector2<f32>; 8] = [
     vector![0.8125, 0.4375],
     vector![0.9375, 0.9375],
 ];
+pub const STRATIFIED_4X4_PATTERN: StratifiedPattern = StratifiedPattern::new(4);
+
+/// Names the standard sample patterns, so they can be listed and selected by name.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SamplePatternKind {
+    Single,
+    Multisample2x,
+    Multisample4x,
+    Multisample8x,
+    Stratified4x4,
+}
+
+impl SamplePatternKind {
+    pub const ALL: [SamplePatternKind; 5] = [
+        SamplePatternKind::Single,
+        SamplePatternKind::Multisample2x,
+        SamplePatternKind::Multisample4x,
+        SamplePatternKind::Multisample8x,
+        SamplePatternKind::Stratified4x4,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SamplePatternKind::Single => "1x",
+            SamplePatternKind::Multisample2x => "2x",
+            SamplePatternKind::Multisample4x => "4x",
+            SamplePatternKind::Multisample8x => "8x",
+            SamplePatternKind::Stratified4x4 => "4x4 stratified",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        SamplePatternKind::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+pub fn pattern(kind: SamplePatternKind) -> &'static dyn SamplePattern {
+    match kind {
+        SamplePatternKind::Single => &SINGLE_SAMPLE_PATTERN,
+        SamplePatternKind::Multisample2x => &MULTISAMPLE_2X_PATTERN,
+        SamplePatternKind::Multisample4x => &MULTISAMPLE_4X_PATTERN,
+        SamplePatternKind::Multisample8x => &MULTISAMPLE_8X_PATTERN,
+        SamplePatternKind::Stratified4x4 => &STRATIFIED_4X4_PATTERN,
+    }
+}
+
+/// Scrambles the bits of a value, used to derive well-distributed seeds from structured input.
+fn mix(value: u64) -> u64 {
+    // splitmix64 finalizer
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
 
+/// Seed for the random numbers used while rendering a single pixel, so its result doesn't depend
+/// on which thread renders it or in what order.
+pub fn pixel_seed(p: Point2<u32>, seed: u64) -> u64 {
+    mix(mix(seed) ^ ((p.x as u64) << 32 | p.y as u64))
+}
+
+/// Generator for every random decision made while rendering a single pixel.
+pub fn pixel_rng(p: Point2<u32>, seed: u64) -> Rng {
+    Rng::with_seed(pixel_seed(p, seed))
+}
+
+/// Seed which only depends on the ray, for random decisions made where no generator is at hand.
+pub fn ray_seed(ray: &Ray) -> u64 {
+    ray.origin.iter()
+        .chain(ray.direction.iter())
+        .chain([ray.time].iter())
+        .fold(0, |hash, value| mix(hash ^ (value + 0.0).to_bits() as u64))
+}
+
+/// Value in `[0, 1)` which only depends on the given coordinates, for procedural variation which
+/// stays the same between frames and runs, independent of the random numbers used for rendering.
+pub fn hash_rng(x: f32, y: f32, z: f32) -> f32 {
+    // adding zero turns negative zero into zero, so both hash the same.
+    let hash = [x, y, z].into_iter()
+        .fold(0, |hash, value| mix(hash ^ (value + 0.0).to_bits() as u64));
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Source of the random numbers used while rendering, seeded for each pixel by `pixel_rng`.
+pub type Rng = fastrand::Rng;
+
+/// Random number from the thread's own generator, for building scenes. Rendering draws from an
+/// explicit `Rng` instead, so images are reproducible.
 pub fn random() -> f32 {
     fastrand::f32()
 }
@@ -54,109 +200,2575 @@ pub fn random_in(range: Range<f32>) -> f32 {
     random() * (range.end - range.start) + range.start
 }
 
-pub fn random_vec() -> Vector3<f32> {
-    vector![random() * 2.0 - 1.0, random() * 2.0 - 1.0, random() * 2.0 - 1.0]
+pub fn random_vec(rng: &mut Rng) -> Vector3<f32> {
+    vector![rng.f32() * 2.0 - 1.0, rng.f32() * 2.0 - 1.0, rng.f32() * 2.0 - 1.0]
 }
 
-pub fn random_vec_in_unit_sphere() -> Vector3<f32> {
-    repeat_with(random_vec)
+pub fn random_vec_in_unit_sphere(rng: &mut Rng) -> Vector3<f32> {
+    repeat_with(|| random_vec(rng))
         .find(|vec| vec.magnitude_squared() < 1.0)
         .expect("infinite iterator")
 }
 
-pub fn random_unit_vec() -> Vector3<f32> {
-    random_vec_in_unit_sphere().normalize()
+pub fn random_unit_vec(rng: &mut Rng) -> Vector3<f32> {
+    random_vec_in_unit_sphere(rng).normalize()
+}
+
+/// Random direction in the hemisphere around the unit normal, distributed following the cosine
+/// law of diffuse reflection so that Lambertian surfaces attenuate by exactly their albedo.
+pub fn random_cosine_direction(rng: &mut Rng, normal: &Vector3<f32>) -> Vector3<f32> {
+    let angle = 2.0 * std::f32::consts::PI * rng.f32();
+    let radius_squared = rng.f32();
+    let radius = radius_squared.sqrt();
+
+    let helper = if normal.x.abs() > 0.9 { Vector3::y() } else { Vector3::x() };
+    let tangent = normal.cross(&helper).normalize();
+    let bitangent = normal.cross(&tangent);
+    radius * angle.cos() * tangent + radius * angle.sin() * bitangent + (1.0 - radius_squared).sqrt() * normal
+}
+
+/// Cheaper approximation of `random_cosine_direction` from Ray Tracing in One Weekend, offsetting
+/// the normal by a random unit vector. Has the same distribution, but is not normalized, and falls
+/// back to the normal itself when the offset all but cancels it out.
+pub fn random_lambert_direction(rng: &mut Rng, normal: &Vector3<f32>) -> Vector3<f32> {
+    let direction = normal + random_unit_vec(rng);
+    if direction.magnitude_squared() < 1e-8 { *normal } else { direction }
 }
 
-pub fn random_vec_in_unit_disk() -> Vector3<f32> {
-    repeat_with(|| vector![random() * 2.0 - 1.0, random() * 2.0 - 1.0, 0.0])
+pub fn random_vec_in_unit_disk(rng: &mut Rng) -> Vector3<f32> {
+    repeat_with(|| vector![rng.f32() * 2.0 - 1.0, rng.f32() * 2.0 - 1.0, 0.0])
         .find(|vec| vec.magnitude_squared() < 1.0)
         .expect("infinite iterator")
 }
 
-pub fn render_ray(ray: &Ray, object: &Object, bounces_left: u32) -> Color {
-    if bounces_left <= 0 {
+/// Number of bounces of each kind a path may still take.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bounces {
+    pub diffuse: u32,
+    pub specular: u32,
+}
+
+impl Bounces {
+    /// Spends one bounce of the given kind, or `None` if that budget is exhausted.
+    pub fn take(self, kind: ScatterKind) -> Option<Bounces> {
+        match kind {
+            ScatterKind::Diffuse => Some(Bounces { diffuse: self.diffuse.checked_sub(1)?, ..self }),
+            ScatterKind::Specular => Some(Bounces { specular: self.specular.checked_sub(1)?, ..self }),
+        }
+    }
+}
+
+/// Maps linear high dynamic range colors into the displayable `0..1` range, before gamma
+/// correction.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ToneMap {
+    /// Cuts off anything brighter than 1, losing highlight detail.
+    #[default]
+    Clamp,
+    /// `c / (1 + c)` per channel, never quite reaching white.
+    Reinhard,
+    /// Reinhard scaled so that `white` and brighter map to 1.
+    ReinhardExtended { white: f32 },
+    /// Narkowicz's fit of the ACES filmic curve, with more contrast than Reinhard.
+    Aces,
+}
+
+impl ToneMap {
+    pub fn apply(&self, color: Color) -> Color {
+        let map = |c: f32| {
+            let c = c.max(0.0);
+            match self {
+                ToneMap::Clamp => c.min(1.0),
+                ToneMap::Reinhard => c / (1.0 + c),
+                ToneMap::ReinhardExtended { white } => (c * (1.0 + c / (white * white)) / (1.0 + c)).min(1.0),
+                ToneMap::Aces => ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0),
+            }
+        };
+        Color::new(map(color.r), map(color.g), map(color.b), color.a)
+    }
+}
+
+/// Limits the luminance of light carried by paths after a number of bounces, trading bias for the
+/// removal of fireflies from indirect lighting.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IndirectClamp {
+    pub max_luminance: f32,
+    /// Number of bounces a ray must have taken before its contribution is clamped.
+    pub from_depth: u32,
+}
+
+impl IndirectClamp {
+    pub fn new(max_luminance: f32) -> Self {
+        IndirectClamp { max_luminance, from_depth: 1 }
+    }
+
+    pub fn apply(&self, color: Color) -> Color {
+        let luminance = color.luminance();
+        if luminance > self.max_luminance {
+            color * (self.max_luminance / luminance)
+        } else {
+            color
+        }
+    }
+}
+
+/// Randomly ends paths which carry little light, boosting the contribution of those that continue
+/// to compensate, so that time is spent on the paths that matter without biasing the image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RussianRoulette {
+    /// Number of bounces a path always takes before it may be ended.
+    pub from_depth: u32,
+}
+
+impl Default for RussianRoulette {
+    fn default() -> Self {
+        RussianRoulette { from_depth: 3 }
+    }
+}
+
+impl RussianRoulette {
+    /// Chance of a path continuing, given the fraction of light it still carries in each channel.
+    pub fn survival(&self, throughput: Color) -> f32 {
+        throughput.r.max(throughput.g).max(throughput.b).min(1.0)
+    }
+}
+
+/// Darkens diffuse surfaces close to other geometry, grounding objects without full global
+/// illumination.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ContactShadows {
+    /// Distance within which other geometry occludes a point.
+    pub radius: f32,
+    /// How much a fully occluded point is darkened, from 0 to 1.
+    pub strength: f32,
+    pub samples: u32,
+}
+
+impl ContactShadows {
+    pub fn new(radius: f32, strength: f32) -> Self {
+        ContactShadows { radius, strength, samples: 4 }
+    }
+
+    /// Factor to multiply the color of a diffuse hit by.
+    pub fn factor(&self, point: &Point3<f32>, normal: &Vector3<f32>, object: &Object, rng: &mut Rng) -> f32 {
+        let occlusion = 1.0 - ambient_occlusion(point, normal, object, self.radius, self.samples, rng);
+        1.0 - self.strength * occlusion
+    }
+}
+
+/// Fraction of cosine-weighted rays from a surface which escape within `radius` without hitting
+/// anything, where 1 is fully exposed.
+pub fn ambient_occlusion(point: &Point3<f32>, normal: &Vector3<f32>, object: &Object, radius: f32, samples: u32, rng: &mut Rng) -> f32 {
+    let escaped = (0..samples)
+        .filter(|_| {
+            let mut direction = normal + random_unit_vec(rng);
+            if direction.magnitude_squared() < 1e-8 {
+                direction = *normal;
+            }
+            let ray = Ray::new(*point, direction.normalize()).with_kind(RayKind::Shadow);
+            object.hit_with_rng(&ray, surface_epsilon(point, DEFAULT_RAY_EPSILON)..radius, rng).is_none()
+        })
+        .count();
+    escaped as f32 / samples as f32
+}
+
+/// Range of `t` in which a ray which has already bounced `depth` times may hit geometry.
+fn t_range(ray: &Ray, settings: &RenderSettings, depth: u32) -> Range<f32> {
+    if depth == 0 {
+        // near and far are distances, while t is measured in multiples of the direction.
+        let length = ray.direction.magnitude();
+        (settings.near / length).max(0.001)..settings.far / length
+    } else {
+        surface_epsilon(&ray.origin, settings.ray_epsilon) / ray.direction.magnitude()..f32::INFINITY
+    }
+}
+
+/// Default for `RenderSettings::ray_epsilon`.
+pub const DEFAULT_RAY_EPSILON: f32 = 1e-4;
+
+/// Smallest distance a ray leaving a surface skips, for surfaces near the world origin.
+const MIN_RAY_EPSILON: f32 = 1e-5;
+
+/// Distance a ray leaving a surface at `point` skips to avoid hitting that surface again. Rounding
+/// error grows with the magnitude of coordinates, and so does the distance.
+fn surface_epsilon(point: &Point3<f32>, relative: f32) -> f32 {
+    (relative * point.coords.abs().max()).max(MIN_RAY_EPSILON)
+}
+
+/// Time spent in each stage of rendering a frame, summed over all threads.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct StageTimings {
+    pub ray_generation: Duration,
+    pub intersection: Duration,
+    pub shading: Duration,
+    /// Copying rendered pixels into the frame.
+    pub upload: Duration,
+}
+
+impl StageTimings {
+    pub fn total(&self) -> Duration {
+        self.ray_generation + self.intersection + self.shading + self.upload
+    }
+}
+
+impl Add for StageTimings {
+    type Output = StageTimings;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        StageTimings {
+            ray_generation: self.ray_generation + rhs.ray_generation,
+            intersection: self.intersection + rhs.intersection,
+            shading: self.shading + rhs.shading,
+            upload: self.upload + rhs.upload,
+        }
+    }
+}
+
+/// Work done rendering a frame, summed over all threads.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    /// Time spent in each stage, only measured when profiling.
+    pub timings: StageTimings,
+    /// Intersection tests of rays against individual primitives.
+    pub primitive_tests: u64,
+    /// Lists, transforms and other nodes containing primitives that rays were tested against.
+    pub node_visits: u64,
+    /// Primitive tests which found an intersection.
+    pub hits: u64,
+}
+
+impl Add for RenderStats {
+    type Output = RenderStats;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        RenderStats {
+            timings: self.timings + rhs.timings,
+            primitive_tests: self.primitive_tests + rhs.primitive_tests,
+            node_visits: self.node_visits + rhs.node_visits,
+            hits: self.hits + rhs.hits,
+        }
+    }
+}
+
+/// Intersection counters of a thread, kept apart from the timings so counting a test only touches
+/// the counter it adds to.
+struct IntersectionCounters {
+    primitive_tests: Cell<u64>,
+    node_visits: Cell<u64>,
+    hits: Cell<u64>,
+}
+
+thread_local! {
+    static COUNTERS: IntersectionCounters = const {
+        IntersectionCounters { primitive_tests: Cell::new(0), node_visits: Cell::new(0), hits: Cell::new(0) }
+    };
+    static TIMINGS: Cell<StageTimings> = Cell::new(StageTimings::default());
+}
+
+/// Adds to the current thread's intersection counters.
+pub(crate) fn count_intersections(primitive_tests: u64, node_visits: u64, hits: u64) {
+    COUNTERS.with(|counters| {
+        counters.primitive_tests.set(counters.primitive_tests.get() + primitive_tests);
+        counters.node_visits.set(counters.node_visits.get() + node_visits);
+        counters.hits.set(counters.hits.get() + hits);
+    });
+}
+
+/// Takes the stats accumulated by the current thread, leaving them zeroed.
+fn take_stats() -> RenderStats {
+    COUNTERS.with(|counters| RenderStats {
+        timings: TIMINGS.take(),
+        primitive_tests: counters.primitive_tests.take(),
+        node_visits: counters.node_visits.take(),
+        hits: counters.hits.take(),
+    })
+}
+
+/// Runs `f`, adding its duration to the current thread's timing of a stage when profiling.
+fn timed<T>(settings: &RenderSettings, stage: fn(&mut StageTimings) -> &mut Duration, f: impl FnOnce() -> T) -> T {
+    if !settings.profile {
+        return f();
+    }
+
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    TIMINGS.with(|timings| {
+        let mut sum = timings.get();
+        *stage(&mut sum) += elapsed;
+        timings.set(sum);
+    });
+    result
+}
+
+/// Takes the stats accumulated by every thread of the current pool, as well as the calling thread,
+/// which may not belong to the pool.
+fn collect_stats() -> RenderStats {
+    rayon::broadcast(|_| take_stats())
+        .into_iter()
+        .fold(take_stats(), Add::add)
+}
+
+/// Material a hit is shaded with, taking overrides into account.
+fn surface_material<'a>(hit: &Hit<'a>, settings: &RenderSettings) -> &'a Material {
+    match hit.material {
+        // lights keep lighting the scene, and media have no surface to shade as clay.
+        material @ (Material::Isotropic { .. } | Material::DiffuseLight { .. }) => material,
+        _ if settings.clay => Material::clay(),
+        Material::Dielectric { .. } if settings.preview => &Material::PREVIEW_GLASS,
+        material => material,
+    }
+}
+
+/// Traces a ray which has already bounced `depth` times, drawing every random decision from `rng`.
+/// Without a camera to look through, matcaps are looked up with world space as the view space.
+pub fn render_ray(ray: &Ray, object: &Object, settings: &RenderSettings, bounces: Bounces, depth: u32, rng: &mut Rng) -> Color {
+    trace(ray, object, settings, Path::new(bounces, depth, Rotation3::identity()), None, rng)
+}
+
+/// Traces a primary ray cast through the viewport, looking matcaps up in the camera's view space.
+fn render_camera_ray(ray: &Ray, viewport: &Viewport, object: &Object, settings: &RenderSettings, bounces: Bounces, rng: &mut Rng) -> Color {
+    trace(ray, object, settings, Path::new(bounces, 0, viewport.rotation), None, rng)
+}
+
+/// Where a path traced from the camera is at.
+#[derive(Copy, Clone, Debug)]
+struct Path {
+    bounces: Bounces,
+    /// Bounces taken so far.
+    depth: u32,
+    /// Fraction of the light found from here on which reaches the camera.
+    throughput: Color,
+    /// Probability density of the direction the last bounce scattered in, when light from the
+    /// environment or the lights was also sampled directly at that bounce, so the two can be
+    /// weighted.
+    scatter_pdf: Option<f32>,
+    /// Orientation of the camera the path started from, taking its view space to world space.
+    view: Rotation3<f32>,
+}
+
+impl Path {
+    fn new(bounces: Bounces, depth: u32, view: Rotation3<f32>) -> Self {
+        Path { bounces, depth, throughput: Color::WHITE, scatter_pdf: None, view }
+    }
+}
+
+/// Traces a ray, which was scattered off a diffuse surface with the given normal if any.
+fn trace(ray: &Ray, object: &Object, settings: &RenderSettings, path: Path, diffuse_normal: Option<&Vector3<f32>>, rng: &mut Rng) -> Color {
+    if settings.transparency {
+        return render_transparent(ray, object, settings, path, diffuse_normal, rng);
+    }
+
+    let hit = timed(settings, |t| &mut t.intersection, || nearest_hit(ray, object, settings, path.depth, rng));
+    match hit {
+        Some(hit) => shade_hit(ray, &hit, object, settings, path, rng),
+        None => timed(settings, |t| &mut t.shading, || escaped(ray, settings, &path, diffuse_normal)),
+    }
+}
+
+/// Nearest surface the ray hits, skipping partially opaque ones at random with stochastic alpha.
+fn nearest_hit<'a>(ray: &Ray, object: &'a Object, settings: &RenderSettings, depth: u32, rng: &mut Rng) -> Option<Hit<'a>> {
+    let range = t_range(ray, settings, depth);
+    if !settings.stochastic_alpha {
+        return object.hit_with_rng(ray, range, rng);
+    }
+    let mut start = Bound::Included(range.start);
+    loop {
+        let hit = object.hit_with_rng(ray, (start, Bound::Excluded(range.end)), rng)?;
+        if rng.f32() < surface_material(&hit, settings).opacity(&hit) {
+            return Some(hit);
+        }
+        start = Bound::Excluded(hit.t);
+    }
+}
+
+/// Light arriving along a ray which hits nothing. Rays scattered off diffuse surfaces receive the
+/// background's irradiance instead when it is available, which is far less noisy. Rays scattered
+/// where the environment was also sampled directly only count for their share of its light.
+fn escaped(ray: &Ray, settings: &RenderSettings, path: &Path, diffuse_normal: Option<&Vector3<f32>>) -> Color {
+    match (&settings.irradiance, diffuse_normal, &settings.environment_sampling, path.scatter_pdf) {
+        (Some(irradiance), Some(normal), _, _) => irradiance.sample(normal),
+        (_, _, Some(sampler), Some(scatter_pdf)) => {
+            power_heuristic(scatter_pdf, sampler.pdf(&ray.direction)) * settings.background.sample(&ray.direction)
+        }
+        _ => settings.background.sample(&ray.direction),
+    }
+}
+
+/// Weight of a sample drawn with the first of two strategies with the given densities, when both
+/// are used to estimate the same light.
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (pdf, other_pdf) = (pdf * pdf, other_pdf * other_pdf);
+    if pdf + other_pdf > 0.0 { pdf / (pdf + other_pdf) } else { 0.0 }
+}
+
+/// Light from the environment reaching a diffuse surface directly along a direction drawn from its
+/// distribution, weighted against the chance of scattering in that direction. `attenuation` is the
+/// albedo at the hit.
+fn sample_environment(sampler: &EnvironmentSampler, hit: &Hit, attenuation: Color, object: &Object, settings: &RenderSettings, time: f32, rng: &mut Rng) -> Color {
+    let (direction, pdf) = sampler.sample(rng);
+    let cosine = hit.normal.dot(&direction);
+    if cosine <= 0.0 || pdf <= 0.0 {
+        return Color::BLACK;
+    }
+    let shadow = Ray::new(hit.point, direction).with_kind(RayKind::Shadow).with_time(time);
+    if object.hit_with_rng(&shadow, surface_epsilon(&hit.point, settings.ray_epsilon)..f32::INFINITY, rng).is_some() {
+        return Color::BLACK;
+    }
+    // a Lambertian surface scatters with density cos/π, and reflects albedo·cos/π of the light.
+    let scatter_pdf = cosine / std::f32::consts::PI;
+    let weight = power_heuristic(pdf, scatter_pdf) * scatter_pdf / pdf;
+    attenuation * settings.background.sample(&direction) * weight
+}
+
+/// Light reaching a diffuse surface directly from a point drawn on one of the scene's lights, picked
+/// at random, weighted against the chance of scattering towards that point. `attenuation` is the
+/// albedo at the hit.
+fn sample_lights(hit: &Hit, attenuation: Color, object: &Object, settings: &RenderSettings, time: f32, rng: &mut Rng) -> Color {
+    let light = &settings.lights[rng.usize(..settings.lights.len())];
+    let Some(sample) = light.sample(&hit.point, rng) else {
         return Color::BLACK;
+    };
+    let cosine = hit.normal.dot(&sample.direction);
+    if cosine <= 0.0 {
+        return Color::BLACK;
+    }
+    // whatever the shadow ray hits first, looking a little past the point so as to find the light
+    // itself, is what lights the surface from there.
+    let shadow = Ray::new(hit.point, sample.direction).with_kind(RayKind::Shadow).with_time(time);
+    let range = surface_epsilon(&hit.point, settings.ray_epsilon)..sample.distance * 1.01;
+    let Some(light_hit) = object.hit_with_rng(&shadow, range, rng) else {
+        return Color::BLACK;
+    };
+    let pdf = lights_pdf(settings, &hit.point, &sample.direction, light_hit.t);
+    if pdf <= 0.0 {
+        return Color::BLACK;
+    }
+    let scatter_pdf = cosine / std::f32::consts::PI;
+    let weight = power_heuristic(pdf, scatter_pdf) * scatter_pdf / pdf;
+    attenuation * surface_material(&light_hit, settings).emitted(&light_hit) * weight
+}
+
+/// Density with which `sample_lights` picks the unit direction from the origin, for the surface
+/// `distance` along it.
+fn lights_pdf(settings: &RenderSettings, origin: &Point3<f32>, direction: &Vector3<f32>, distance: f32) -> f32 {
+    let sum: f32 = settings.lights.iter().map(|light| light.pdf(origin, direction, distance)).sum();
+    sum / settings.lights.len() as f32
+}
+
+/// Composites every surface along a ray front to back, weighted by their opacity, until the ray is
+/// fully blocked.
+fn render_transparent(ray: &Ray, object: &Object, settings: &RenderSettings, path: Path, diffuse_normal: Option<&Vector3<f32>>, rng: &mut Rng) -> Color {
+    let hits = timed(settings, |t| &mut t.intersection, || object.hit_all_with_rng(ray, t_range(ray, settings, path.depth), rng));
+    let mut color = Color::BLACK;
+    let mut transmittance = 1.0;
+    for hit in &hits {
+        let opacity = surface_material(hit, settings).opacity(hit);
+        if opacity > 0.0 {
+            color = color + transmittance * opacity * shade_hit(ray, hit, object, settings, path, rng);
+        }
+        transmittance *= 1.0 - opacity;
+        if transmittance < 0.001 {
+            return color;
+        }
+    }
+
+    color + transmittance * timed(settings, |t| &mut t.shading, || escaped(ray, settings, &path, diffuse_normal))
+}
+
+/// Light leaving a surface towards the ray that hit it, clamped once the path has bounced enough.
+fn shade_hit(ray: &Ray, hit: &Hit, object: &Object, settings: &RenderSettings, path: Path, rng: &mut Rng) -> Color {
+    let radiance = shade_surface(ray, hit, object, settings, path, rng);
+    match settings.indirect_clamp {
+        Some(clamp) if path.depth >= clamp.from_depth => clamp.apply(radiance),
+        _ => radiance,
     }
+}
 
-    if let Some(hit) = object.hit(ray, 0.001..) {
-        let (attenuation, scattered) = hit.material.scatter(ray, &hit);
-        return attenuation * render_ray(&scattered, object, bounces_left - 1);
+fn shade_surface(ray: &Ray, hit: &Hit, object: &Object, settings: &RenderSettings, path: Path, rng: &mut Rng) -> Color {
+    let material = surface_material(hit, settings);
+    if let Material::Matcap { texture } = material {
+        return sample_matcap(texture, &(path.view.inverse() * hit.normal));
+    }
+    let emitted = match path.scatter_pdf {
+        // reached by scattering where the lights were also sampled directly, so only counts for
+        // its share of their light.
+        Some(scatter_pdf) if !settings.lights.is_empty() => {
+            let length = ray.direction.magnitude();
+            let light_pdf = lights_pdf(settings, &ray.origin, &(ray.direction / length), hit.t * length);
+            material.emitted(hit) * power_heuristic(scatter_pdf, light_pdf)
+        }
+        _ => material.emitted(hit),
+    };
+    let Some(bounces) = path.bounces.take(material.scatter_kind()) else {
+        return emitted;
+    };
+    let Some((attenuation, scattered)) = timed(settings, |t| &mut t.shading, || material.scatter(ray, hit, rng)) else {
+        return emitted;
+    };
+    // volumes scatter diffusely, but have no surface normal.
+    let diffuse_normal = match (material, material.scatter_kind()) {
+        (Material::Isotropic { .. }, _) | (_, ScatterKind::Specular) => None,
+        (_, ScatterKind::Diffuse) => Some(&hit.normal),
+    };
+    // light from an environment image is sampled directly off Lambertian surfaces, unless the
+    // irradiance map already stands in for it.
+    let environment = match (material, &settings.environment_sampling, &settings.irradiance) {
+        (Material::Lambert { .. }, Some(sampler), None) => Some(sampler),
+        _ => None,
+    };
+    let lights = matches!(material, Material::Lambert { .. }) && !settings.lights.is_empty();
+    let mut direct = match environment {
+        Some(sampler) => timed(settings, |t| &mut t.shading, || sample_environment(sampler, hit, attenuation, object, settings, ray.time, rng)),
+        None => Color::BLACK,
+    };
+    if lights {
+        direct = direct + timed(settings, |t| &mut t.shading, || sample_lights(hit, attenuation, object, settings, ray.time, rng));
+    }
+    let next = Path {
+        bounces,
+        depth: path.depth + 1,
+        throughput: path.throughput * attenuation,
+        scatter_pdf: (environment.is_some() || lights)
+            .then(|| hit.normal.dot(&scattered.direction.normalize()).max(0.0) / std::f32::consts::PI),
+        ..path
+    };
+    let mut attenuation = attenuation;
+    match settings.russian_roulette {
+        Some(roulette) if path.depth >= roulette.from_depth => {
+            let survival = roulette.survival(next.throughput);
+            if rng.f32() >= survival {
+                return emitted + direct;
+            }
+            attenuation = attenuation * (1.0 / survival);
+        }
+        _ => {}
     }
+    let mut radiance = direct + attenuation * trace(&scattered, object, settings, next, diffuse_normal, rng);
+    if let (Some(contact), Some(normal)) = (settings.contact_shadows, diffuse_normal) {
+        radiance = radiance * timed(settings, |t| &mut t.shading, || contact.factor(&hit.point, normal, object, rng));
+    }
+    emitted + radiance
+}
+
+/// Distance along a primary ray to the first surface it hits.
+pub fn render_depth(ray: &Ray, object: &Object, settings: &RenderSettings) -> Option<f32> {
+    object.hit(ray, t_range(ray, settings, 0))
+        .map(|hit| hit.t * ray.direction.magnitude())
+}
+
+/// Renders the distance to the nearest surface through the center of every pixel, normalized so
+/// `near` maps to black and `far`, or no hit at all, to white. Suited to export as a heightmap or
+/// shadow map.
+pub fn render_depth_map(camera: &Camera, object: &Object, size: (u32, u32), near: f32, far: f32) -> Picture<Vec<u16>> {
+    let (width, height) = size;
+    let viewport = camera.viewport(width, height);
+    let settings = RenderSettings { near, far, ..Default::default() };
+
+    let pixels = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(x, y)| {
+            let u = (x as f32 + 0.5) / (viewport.image_width - 1.0);
+            let v = (y as f32 + 0.5) / (viewport.image_height - 1.0);
+            let ray = viewport.emit_ray(&point![u, v], 0, 1, &mut pixel_rng(point![x, y], settings.frame_seed()));
+            let depth = render_depth(&ray, object, &settings).unwrap_or(far);
+            (((depth - near) / (far - near)).clamp(0.0, 1.0) * u16::MAX as f32) as u16
+        })
+        .collect();
+    Picture::new(pixels, size)
+}
+
+/// Linear radiance of a frame, split by the kind of light path that carried it. The passes sum to
+/// the beauty render before gamma correction.
+pub struct LightPathAovs {
+    /// Paths whose first bounce off a surface was diffuse.
+    pub diffuse: Picture<Vec<Color>>,
+    /// Paths whose first bounce off a surface was a reflection or refraction.
+    pub specular: Picture<Vec<Color>>,
+    /// Light seen directly from the camera, from the background or emissive surfaces.
+    pub emission: Picture<Vec<Color>>,
+}
+
+/// Renders a frame as separate light path passes for compositing.
+pub fn render_aovs(camera: &Camera, object: &Object, size: (u32, u32), settings: &RenderSettings) -> LightPathAovs {
+    let samples = settings.sample_pattern();
+    let (width, height) = size;
+    let viewport = camera.viewport(width, height);
+    let bounces = Bounces {
+        diffuse: settings.max_diffuse_bounces,
+        specular: settings.max_specular_bounces,
+    };
 
-    let unit_direction = ray.direction.normalize();
-    let t = 0.5 * (unit_direction.y + 1.0);
-    (1.0 - t) * Color::WHITE + t * Color::new(0.5, 0.6, 1.0, 1.0)
+    let pixels: Vec<[Color; 3]> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(x, y)| {
+            let mut rng = pixel_rng(point![x, y], settings.frame_seed());
+            let offsets = samples.sample_offsets(&mut rng);
+            let mut passes = [Color::BLACK; 3];
+            for (sample, offset) in offsets.iter().enumerate() {
+                let u = (x as f32 + offset.x) / (viewport.image_width - 1.0);
+                let v = (y as f32 + offset.y) / (viewport.image_height - 1.0);
+                let ray = viewport.emit_ray(&point![u, v], sample, offsets.len(), &mut rng);
+                let (pass, color) = match object.hit_with_rng(&ray, t_range(&ray, settings, 0), &mut rng) {
+                    Some(hit) => {
+                        let pass = match surface_material(&hit, settings) {
+                            Material::DiffuseLight { .. } => 2,
+                            material if material.scatter_kind() == ScatterKind::Diffuse => 0,
+                            _ => 1,
+                        };
+                        (pass, shade_hit(&ray, &hit, object, settings, Path::new(bounces, 0, viewport.rotation), &mut rng))
+                    }
+                    None => (2, settings.background.sample(&ray.direction)),
+                };
+                passes[pass] = passes[pass] + color;
+            }
+            passes.map(|sum| {
+                let n = offsets.len() as f32;
+                Color::new(sum.r / n, sum.g / n, sum.b / n, 1.0)
+            })
+        })
+        .collect();
+
+    let pass = |i: usize| Picture::new(pixels.iter().map(|passes| passes[i]).collect(), size);
+    LightPathAovs { diffuse: pass(0), specular: pass(1), emission: pass(2) }
+}
+
+/// Visualized normal of the first surface hit by a primary ray, or black if it hits nothing.
+pub fn render_normal(ray: &Ray, object: &Object, settings: &RenderSettings, space: NormalSpace, viewport: &Viewport) -> Color {
+    match object.hit(ray, t_range(ray, settings, 0)) {
+        Some(hit) => match space {
+            NormalSpace::World => Color::visualize_normal(&hit.normal),
+            NormalSpace::View => Color::visualize_normal(&(viewport.rotation.inverse() * hit.normal)),
+        },
+        None => Color::BLACK,
+    }
 }
 
-const MAX_BOUNCES: u32 = 50;
+/// Base color of the first surface hit by a primary ray, or the sky if it hits nothing.
+pub fn render_albedo(ray: &Ray, object: &Object, settings: &RenderSettings) -> Color {
+    match object.hit(ray, t_range(ray, settings, 0)) {
+        Some(hit) => hit.material.base_albedo(&hit),
+        None => settings.background.sample(&ray.direction),
+    }
+}
 
 /// Produces the color of a single pixel using n randomly placed samples.
-pub fn render_pixel(p: Point2<u32>, viewport: &Viewport, object: &Object, samples: &impl SamplePattern) -> Color {
-    let samples = samples.sample_offsets();
-    let sum: Color = samples.iter()
-        .map(|offset| {
-            let u = (p.x as f32 + offset.x) / (viewport.image_width - 1.0);
-            let v = (p.y as f32 + offset.y) / (viewport.image_height - 1.0);
-            viewport.emit_ray(&point![u,v])
+pub fn render_pixel(p: Point2<u32>, viewport: &Viewport, object: &Object, samples: &dyn SamplePattern, settings: &RenderSettings) -> Color {
+    if let RenderMode::StandardError = settings.mode {
+        let (_, stats) = pixel_statistics(p, viewport, object, samples, settings);
+        return heat_color(standard_error(stats.variance(), stats.count as usize) / MAX_STANDARD_ERROR);
+    }
+    let mut rng = pixel_rng(p, settings.frame_seed());
+    let bounces = Bounces {
+        diffuse: settings.max_diffuse_bounces,
+        specular: settings.max_specular_bounces,
+    };
+    let samples = samples.sample_offsets(&mut rng);
+    let colors = samples.iter()
+        .enumerate()
+        .map(|(sample, offset)| {
+            let ray = pixel_ray(p, offset, sample, samples.len(), viewport, settings, &mut rng);
+            match settings.mode {
+                RenderMode::Albedo => render_albedo(&ray, object, settings),
+                RenderMode::Normal(space) => render_normal(&ray, object, settings, space, viewport),
+                RenderMode::BvhHeatmap => heat_color(traversal_cost(&ray, object, settings).total() as f32 / MAX_TRAVERSAL_COST),
+                RenderMode::Overdraw => heat_color(overdraw(&ray, object, settings) as f32 / MAX_OVERDRAW),
+                _ => render_camera_ray(&ray, viewport, object, settings, bounces, &mut rng),
+            }
+        });
+    let (sum, samples) = if settings.sample_trim > 0.0 {
+        trimmed_sum(colors.collect(), settings.sample_trim)
+    } else {
+        (colors.sum(), samples.len())
+    };
+    let samples = samples as f32;
+    let average = Color::new(sum.r / samples, sum.g / samples, sum.b / samples, 1.0);
+    let color = match settings.mode {
+        RenderMode::Normal(_) | RenderMode::BvhHeatmap | RenderMode::Overdraw => average,
+        RenderMode::Beauty => gamma_correct(settings.tone_map.apply(average), settings.gamma),
+        _ => gamma_correct(average, settings.gamma),
+    };
+    match &settings.focus_peaking {
+        Some(peaking) => peaking.apply(color, p, viewport, object, settings),
+        None => color,
+    }
+}
+
+/// Encodes linear light for a display with the given gamma.
+pub fn gamma_correct(color: Color, gamma: f32) -> Color {
+    let encode = |c: f32| if gamma == 2.0 {
+        // exact, where powf may be off by a bit.
+        c.sqrt()
+    } else {
+        c.powf(1.0 / gamma)
+    };
+    Color::new(encode(color.r), encode(color.g), encode(color.b), color.a)
+}
+
+/// Primary ray through the given offset within a pixel, for the `sample`th of `samples` samples
+/// taken in it.
+fn pixel_ray(p: Point2<u32>, offset: &Vector2<f32>, sample: usize, samples: usize, viewport: &Viewport, settings: &RenderSettings, rng: &mut Rng) -> Ray {
+    let u = (p.x as f32 + offset.x) / (viewport.image_width - 1.0);
+    let v = (p.y as f32 + offset.y) / (viewport.image_height - 1.0);
+    timed(settings, |t| &mut t.ray_generation, || viewport.emit_ray(&point![u, v], sample, samples, rng))
+}
+
+/// Renders a frame along with an estimate of the noise left in every pixel, as the sample variance
+/// of the luminance of its linear samples. Pixels match those of `render_pixel` in beauty mode,
+/// without trimming or focus peaking.
+pub fn render_with_variance(camera: &Camera, object: &Object, size: (u32, u32), settings: &RenderSettings) -> (Picture<Vec<Color>>, Picture<Vec<f32>>) {
+    let samples = settings.sample_pattern();
+    let (width, height) = size;
+    let viewport = camera.viewport(width, height);
+
+    let pixels: Vec<(Color, f32)> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(x, y)| {
+            let (average, stats) = pixel_statistics(point![x, y], &viewport, object, samples, settings);
+            (gamma_correct(settings.tone_map.apply(average), settings.gamma), stats.variance())
         })
-        .map(|ray| render_ray(&ray, object, MAX_BOUNCES))
-        .sum();
-    let samples = samples.len() as f32;
-    Color::new(
-        (sum.r / samples).sqrt(),
-        (sum.g / samples).sqrt(),
-        (sum.b / samples).sqrt(),
-        1.0,
+        .collect();
+
+    (
+        Picture::new(pixels.iter().map(|(color, _)| *color).collect(), size),
+        Picture::new(pixels.iter().map(|(_, variance)| *variance).collect(), size),
     )
 }
 
-fn render_work_pixels<I, P>(work: Work<I>, viewport: &Viewport, object: &Object, samples: &impl SamplePattern) -> Vec<P>
-    where I: Iterator<Item=(u32, u32)>,
-          P: PixelFormat {
-    let mut buffer = Vec::with_capacity(work.iter.size_hint().0);
-    let pixels = work.iter
-        .map(|(x, y)| render_pixel(point![x, y], viewport, object, samples))
-        .map(P::from);
-    buffer.extend(pixels);
-    buffer
+/// Running mean and variance of sample luminance, updated one sample at a time with Welford's
+/// algorithm.
+#[derive(Copy, Clone, Debug, Default)]
+struct LuminanceStats {
+    count: f32,
+    mean: f32,
+    squared_deviations: f32,
+}
+
+impl LuminanceStats {
+    fn push(&mut self, luminance: f32) {
+        self.count += 1.0;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.count;
+        self.squared_deviations += delta * (luminance - self.mean);
+    }
+
+    /// Combines the statistics of two disjoint sets of samples, as Chan et al. do.
+    fn merge(&mut self, other: &LuminanceStats) {
+        let count = self.count + other.count;
+        if count == 0.0 {
+            return;
+        }
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count / count;
+        self.squared_deviations += other.squared_deviations + delta * delta * self.count * other.count / count;
+        self.count = count;
+    }
+
+    fn variance(&self) -> f32 {
+        if self.count > 1.0 { self.squared_deviations / (self.count - 1.0) } else { 0.0 }
+    }
 }
 
-struct Work<I> {
-    iter: I,
+/// Linear average of a pixel's samples, along with the statistics of their luminance.
+fn pixel_statistics(p: Point2<u32>, viewport: &Viewport, object: &Object, samples: &dyn SamplePattern, settings: &RenderSettings) -> (Color, LuminanceStats) {
+    let mut rng = pixel_rng(p, settings.frame_seed());
+    let offsets = samples.sample_offsets(&mut rng);
+    let bounces = Bounces {
+        diffuse: settings.max_diffuse_bounces,
+        specular: settings.max_specular_bounces,
+    };
+    let mut sum = Color::BLACK;
+    let mut stats = LuminanceStats::default();
+    for (sample, offset) in offsets.iter().enumerate() {
+        let ray = pixel_ray(p, offset, sample, offsets.len(), viewport, settings, &mut rng);
+        let color = render_camera_ray(&ray, viewport, object, settings, bounces, &mut rng);
+        sum = sum + color;
+        stats.push(color.luminance());
+    }
+    let count = stats.count;
+    (Color::new(sum.r / count, sum.g / count, sum.b / count, 1.0), stats)
 }
 
-const LINES_PER_WORK: u32 = 50;
+/// Produces the color of a single pixel from randomly placed samples, taking more until the 95%
+/// confidence interval of its mean luminance is narrower than `tolerance` either side of the mean,
+/// or `max_samples` have been taken. Returns the color along with the number of samples taken, so
+/// noisy pixels such as those along edges get more samples while flat ones stop early.
+pub fn render_pixel_adaptive(
+    p: Point2<u32>,
+    viewport: &Viewport,
+    object: &Object,
+    min_samples: u32,
+    max_samples: u32,
+    tolerance: f32,
+    settings: &RenderSettings,
+) -> (Color, u32) {
+    /// Standard errors either side of the mean covering 95% of a normal distribution.
+    const CONFIDENCE_95: f32 = 1.96;
 
-pub fn render_frame_async<P: PixelFormat + Copy + Send>(frame: &Mutex<Frame<P>>, camera: &Camera, object: &Object, samples: &impl SamplePattern) {
-    let (width, height) = {
-        let frame = frame.lock().expect("frame lock");
-        (frame.width(), frame.height())
+    let mut rng = pixel_rng(p, settings.frame_seed());
+    let bounces = Bounces {
+        diffuse: settings.max_diffuse_bounces,
+        specular: settings.max_specular_bounces,
     };
-    let pixels = width * height;
+    let mut sum = Color::BLACK;
+    let mut stats = LuminanceStats::default();
+    let mut taken = 0;
+    while taken < max_samples.max(1) {
+        let offset = vector![rng.f32(), rng.f32()];
+        // how many samples will be taken is not known up front, so times are not stratified.
+        let ray = pixel_ray(p, &offset, 0, 1, viewport, settings, &mut rng);
+        let color = render_camera_ray(&ray, viewport, object, settings, bounces, &mut rng);
+        sum = sum + color;
+        stats.push(color.luminance());
+        taken += 1;
+        if taken >= min_samples.max(2) && CONFIDENCE_95 * standard_error(stats.variance(), taken as usize) < tolerance {
+            break;
+        }
+    }
+    let count = taken as f32;
+    let average = Color::new(sum.r / count, sum.g / count, sum.b / count, 1.0);
+    (gamma_correct(settings.tone_map.apply(average), settings.gamma), taken)
+}
+
+/// Limits within which `render_pixel_adaptive` samples every pixel of a frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    pub min_samples: u32,
+    pub max_samples: u32,
+    /// Half-width of the confidence interval of a pixel's mean luminance at which it stops.
+    pub tolerance: f32,
+}
+
+impl AdaptiveSampling {
+    fn render_pixel(&self, p: Point2<u32>, viewport: &Viewport, object: &Object, settings: &RenderSettings) -> (Color, u32) {
+        render_pixel_adaptive(p, viewport, object, self.min_samples, self.max_samples, self.tolerance, settings)
+    }
+}
+
+/// Standard error of a mean taken over `samples` samples with the given variance. Falls as more
+/// samples are taken, by half for four times as many.
+pub fn standard_error(variance: f32, samples: usize) -> f32 {
+    (variance / samples as f32).sqrt()
+}
+
+/// Displays the variance from `render_with_variance` the way `RenderMode::StandardError` does,
+/// given the number of samples each pixel took.
+pub fn standard_error_heatmap(variance: &Picture<Vec<f32>>, samples: usize) -> Picture<Vec<Color>> {
+    let view = variance.view();
+    let pixels = (0..variance.height())
+        .flat_map(|y| (0..variance.width()).map(move |x| (x, y)))
+        .map(|(x, y)| heat_color(standard_error(*view.pixel(x, y), samples) / MAX_STANDARD_ERROR))
+        .collect();
+    Picture::new(pixels, (variance.width(), variance.height()))
+}
+
+/// Sum of the samples left after discarding the given fraction of them, brightest first, along with
+/// how many are left. At least one sample is always kept.
+fn trimmed_sum(mut colors: Vec<Color>, trim: f32) -> (Color, usize) {
+    colors.sort_by_key(|color| FloatOrd(color.luminance()));
+    let discarded = ((colors.len() as f32 * trim) as usize).min(colors.len() - 1);
+    colors.truncate(colors.len() - discarded);
+    let kept = colors.len();
+    (colors.into_iter().sum(), kept)
+}
+
+/// Rectangular region of the frame rendered as a single unit of work.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// The tile in the given column and row of a frame's tile grid.
+    pub fn at(column: u32, row: u32, width: u32, height: u32) -> Self {
+        let x = column * TILE_SIZE;
+        let y = row * TILE_SIZE;
+        Tile {
+            x,
+            y,
+            width: TILE_SIZE.min(width - x),
+            height: TILE_SIZE.min(height - y),
+        }
+    }
+
+    pub fn center(&self) -> Point2<f32> {
+        point![self.x as f32 + self.width as f32 / 2.0, self.y as f32 + self.height as f32 / 2.0]
+    }
+}
+
+const TILE_SIZE: u32 = 32;
+
+/// Index of the nearest of the objects hit by the primary ray through the center of every pixel, as
+/// placed by `render_pixel` with a single sample, for finding which parts of the frame depend on
+/// which objects.
+pub fn render_object_ids(camera: &Camera, objects: &[Object], size: (u32, u32), settings: &RenderSettings) -> Picture<Vec<Option<usize>>> {
+    let (width, height) = size;
     let viewport = camera.viewport(width, height);
+    let pixels = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(x, y)| {
+            let p = point![x, y];
+            let ray = pixel_ray(p, &SINGLE_SAMPLE_PATTERN[0], 0, 1, &viewport, settings, &mut pixel_rng(p, settings.frame_seed()));
+            objects.iter()
+                .enumerate()
+                .filter_map(|(id, object)| object.hit(&ray, t_range(&ray, settings, 0)).map(|hit| (id, hit.t)))
+                .min_by_key(|(_, t)| FloatOrd(*t))
+                .map(|(id, _)| id)
+        })
+        .collect();
+    Picture::new(pixels, size)
+}
 
-    let chunk_len = width * LINES_PER_WORK;
-    let chunks = pixels / chunk_len;
-    let remainder = pixels % chunk_len;
-
-    (0..chunks)
-        .map(|i| (i * chunk_len..i * chunk_len + chunk_len))
-        .chain(once(pixels - remainder..pixels))
-        .par_bridge()
-        .for_each(|chunk| {
-            let index = chunk.start as usize;
-            let work = Work {
-                iter: chunk.clone().map(|i| (i % width, i / width)),
-            };
-            trace!(target: "app", "Rendering chunk: {:?}", chunk);
-            let buffer = render_work_pixels(work, &viewport, object, samples);
+/// Tiles, in rendering order, seeing any of the changed objects directly according to an object
+/// ID buffer of the previous frame, and so needing to be rendered again after only their materials
+/// changed. Only primary visibility is covered: tiles which see the objects solely through
+/// reflections or refractions, or in the light they bounce onto other surfaces, are not marked.
+pub fn dirty_tiles(object_ids: &Picture<&[Option<usize>]>, changed: &[usize]) -> Vec<Tile> {
+    scheduled_tiles(object_ids.width(), object_ids.height())
+        .into_iter()
+        .filter(|tile| {
+            (tile.y..tile.y + tile.height)
+                .flat_map(|y| (tile.x..tile.x + tile.width).map(move |x| (x, y)))
+                .any(|(x, y)| object_ids.pixel(x, y).is_some_and(|id| changed.contains(&id)))
+        })
+        .collect()
+}
 
-            {
+/// Number of tile columns and rows covering a frame.
+pub fn tile_grid(width: u32, height: u32) -> (u32, u32) {
+    (width.div_ceil(TILE_SIZE), height.div_ceil(TILE_SIZE))
+}
+
+/// Splits a frame into tiles of at most `TILE_SIZE` pixels square, row by row.
+pub fn tiles(width: u32, height: u32) -> impl Iterator<Item=Tile> {
+    let (columns, rows) = tile_grid(width, height);
+    (0..rows).flat_map(move |row| (0..columns).map(move |column| Tile::at(column, row, width, height)))
+}
+
+/// Orders the cells of a grid along a Morton (Z-order) curve, so neighbouring tiles are rendered
+/// close together in time and the frame fills in evenly rather than from top to bottom.
+pub struct MortonScheduler {
+    columns: u32,
+    rows: u32,
+    code: u64,
+    end: u64,
+}
+
+impl MortonScheduler {
+    pub fn new(columns: u32, rows: u32) -> Self {
+        let side = columns.max(rows).next_power_of_two() as u64;
+        MortonScheduler { columns, rows, code: 0, end: side * side }
+    }
+}
+
+/// Every tile of a frame, in the order they are scheduled for rendering.
+pub fn scheduled_tiles(width: u32, height: u32) -> Vec<Tile> {
+    let (columns, rows) = tile_grid(width, height);
+    MortonScheduler::new(columns, rows)
+        .map(|(column, row)| Tile::at(column, row, width, height))
+        .collect()
+}
+
+/// Gathers every other bit of a Morton code into a single coordinate.
+fn compact_bits(code: u64) -> u32 {
+    let mut code = code & 0x5555555555555555;
+    code = (code | (code >> 1)) & 0x3333333333333333;
+    code = (code | (code >> 2)) & 0x0f0f0f0f0f0f0f0f;
+    code = (code | (code >> 4)) & 0x00ff00ff00ff00ff;
+    code = (code | (code >> 8)) & 0x0000ffff0000ffff;
+    code = (code | (code >> 16)) & 0x00000000ffffffff;
+    code as u32
+}
+
+impl Iterator for MortonScheduler {
+    type Item = (u32, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.code < self.end {
+            let column = compact_bits(self.code);
+            let row = compact_bits(self.code >> 1);
+            self.code += 1;
+            if column < self.columns && row < self.rows {
+                return Some((column, row));
+            }
+        }
+        None
+    }
+}
+
+/// How much effort is spent on a tile.
+pub struct TileQuality<'a> {
+    pub samples: &'a dyn SamplePattern,
+    /// Side length of the square pixel blocks sharing a single rendered color.
+    pub scale: u32,
+}
+
+/// Spends fewer samples and resolution on tiles further away from a focus point.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Foveation {
+    /// Point of highest detail, in pixels.
+    pub focus: Point2<f32>,
+    /// Distance from the focus, relative to the frame's half-diagonal, within which tiles are
+    /// rendered at full quality.
+    pub inner_radius: f32,
+    /// Relative distance beyond which tiles are rendered at the lowest quality.
+    pub outer_radius: f32,
+}
+
+impl Foveation {
+    pub fn centered(width: u32, height: u32) -> Self {
+        Foveation {
+            focus: point![width as f32 / 2.0, height as f32 / 2.0],
+            inner_radius: 0.25,
+            outer_radius: 0.9,
+        }
+    }
+
+    pub fn tile_quality(&self, tile: &Tile, width: u32, height: u32) -> TileQuality<'static> {
+        let half_diagonal = vector![width as f32, height as f32].magnitude() / 2.0;
+        let distance = (tile.center() - self.focus).magnitude() / half_diagonal;
+        let falloff = (distance - self.inner_radius) / (self.outer_radius - self.inner_radius);
+
+        let (samples, scale): (&'static dyn SamplePattern, u32) = if falloff <= 0.0 {
+            (&MULTISAMPLE_8X_PATTERN, 1)
+        } else if falloff < 1.0 / 3.0 {
+            (&MULTISAMPLE_4X_PATTERN, 1)
+        } else if falloff < 2.0 / 3.0 {
+            (&MULTISAMPLE_2X_PATTERN, 2)
+        } else {
+            (&SINGLE_SAMPLE_PATTERN, 4)
+        };
+        TileQuality { samples, scale }
+    }
+}
+
+/// What is written to the frame for each pixel.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum RenderMode {
+    #[default]
+    Beauty,
+    /// Samples taken per pixel as a grayscale value, where white is the most a pixel may take:
+    /// `AdaptiveSampling::max_samples` when sampling adaptively, or `MAX_SAMPLE_COUNT`.
+    SampleCount,
+    /// Base color of the surfaces seen, as used by denoisers to preserve texture detail.
+    Albedo,
+    /// Surface normals mapped from `-1..1` to `0..1` per axis.
+    Normal(NormalSpace),
+    /// Intersection tests made to find what each primary ray hits, from blue for none to red for
+    /// `MAX_TRAVERSAL_COST` or more.
+    BvhHeatmap,
+    /// Standard error of each pixel's mean luminance, from blue for none to red for
+    /// `MAX_STANDARD_ERROR` or more, showing where the image is still noisy.
+    StandardError,
+    /// Surfaces each primary ray passes through, as composited with transparency, from blue for
+    /// none to red for `MAX_OVERDRAW` or more.
+    Overdraw,
+}
+
+impl RenderMode {
+    /// The mode after this one, wrapping around to `Beauty` after the last.
+    pub fn next(self) -> RenderMode {
+        match self {
+            RenderMode::Beauty => RenderMode::SampleCount,
+            RenderMode::SampleCount => RenderMode::Albedo,
+            RenderMode::Albedo => RenderMode::Normal(NormalSpace::World),
+            RenderMode::Normal(NormalSpace::World) => RenderMode::Normal(NormalSpace::View),
+            RenderMode::Normal(NormalSpace::View) => RenderMode::BvhHeatmap,
+            RenderMode::BvhHeatmap => RenderMode::StandardError,
+            RenderMode::StandardError => RenderMode::Overdraw,
+            RenderMode::Overdraw => RenderMode::Beauty,
+        }
+    }
+}
+
+/// Coordinate system normals are visualized in.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum NormalSpace {
+    #[default]
+    World,
+    /// Relative to the camera, with +Z pointing towards the viewer.
+    View,
+}
+
+/// Highlights the parts of the image that are in focus, to assist in setting the focus distance.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FocusPeaking {
+    /// Largest circle of confusion, in pixels, considered in focus.
+    pub max_blur: f32,
+    pub color: Color,
+}
+
+impl Default for FocusPeaking {
+    fn default() -> Self {
+        FocusPeaking { max_blur: 0.5, color: Color::new(1.0, 0.0, 1.0, 1.0) }
+    }
+}
+
+impl FocusPeaking {
+    /// Tints the color of a pixel if the surface seen through its center is in focus.
+    fn apply(&self, color: Color, p: Point2<u32>, viewport: &Viewport, object: &Object, settings: &RenderSettings) -> Color {
+        let u = (p.x as f32 + 0.5) / (viewport.image_width - 1.0);
+        let v = (p.y as f32 + 0.5) / (viewport.image_height - 1.0);
+        let ray = viewport.emit_ray(&point![u, v], 0, 1, &mut pixel_rng(p, settings.frame_seed()));
+        match render_depth(&ray, object, settings) {
+            Some(depth) if viewport.circle_of_confusion(depth) <= self.max_blur => 0.5 * color + 0.5 * self.color,
+            _ => color,
+        }
+    }
+}
+
+/// Intersection tests displayed as red by `RenderMode::BvhHeatmap`.
+pub const MAX_TRAVERSAL_COST: f32 = 200.0;
+
+/// Surfaces displayed as red by `RenderMode::Overdraw`.
+pub const MAX_OVERDRAW: f32 = 8.0;
+
+/// Number of surfaces along a primary ray, which are all shaded when rendering with transparency.
+pub fn overdraw(ray: &Ray, object: &Object, settings: &RenderSettings) -> usize {
+    object.hit_all(ray, t_range(ray, settings, 0)).len()
+}
+
+/// Standard error of the mean luminance displayed as red by `RenderMode::StandardError`.
+pub const MAX_STANDARD_ERROR: f32 = 0.05;
+
+/// Work done to find the surface a ray hits.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TraversalCost {
+    pub node_visits: u64,
+    pub primitive_tests: u64,
+}
+
+impl TraversalCost {
+    pub fn total(&self) -> u64 {
+        self.node_visits + self.primitive_tests
+    }
+}
+
+/// Nodes and primitives the ray is tested against while looking for the nearest surface it hits.
+pub fn traversal_cost(ray: &Ray, object: &Object, settings: &RenderSettings) -> TraversalCost {
+    let counts = || COUNTERS.with(|counters| (counters.node_visits.get(), counters.primitive_tests.get()));
+    let before = counts();
+    object.hit(ray, t_range(ray, settings, 0));
+    let after = counts();
+    TraversalCost {
+        node_visits: after.0 - before.0,
+        primitive_tests: after.1 - before.1,
+    }
+}
+
+/// Maps `0..1` through blue, green and yellow to red.
+fn heat_color(value: f32) -> Color {
+    const RAMP: [Color; 4] = [
+        Color::new(0.0, 0.0, 1.0, 1.0),
+        Color::new(0.0, 1.0, 0.0, 1.0),
+        Color::new(1.0, 1.0, 0.0, 1.0),
+        Color::new(1.0, 0.0, 0.0, 1.0),
+    ];
+    let position = value.clamp(0.0, 1.0) * (RAMP.len() - 1) as f32;
+    let index = (position as usize).min(RAMP.len() - 2);
+    let fraction = position - index as f32;
+    RAMP[index] * (1.0 - fraction) + RAMP[index + 1] * fraction
+}
+
+/// Sample count displayed as white by `RenderMode::SampleCount` without adaptive sampling.
+pub const MAX_SAMPLE_COUNT: usize = MULTISAMPLE_8X_PATTERN.len();
+
+fn sample_count_color(samples: f32, max_samples: f32) -> Color {
+    let value = samples / max_samples;
+    Color::new(value, value, value, 1.0)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderSettings {
+    pub mode: RenderMode,
+    /// Samples taken in every pixel, unless lowered by foveation or edge detection.
+    pub samples: SamplePatternKind,
+    /// Varies quality across the frame instead of using the given sample pattern everywhere.
+    pub foveation: Option<Foveation>,
+    /// Multisamples only pixels along edges, taking precedence over foveation.
+    pub edge_detection: Option<EdgeDetection>,
+    /// Takes as many samples in each pixel as its noise calls for in place of the sample pattern,
+    /// in beauty and sample count mode. Ignored with edge detection.
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    /// Maximum number of bounces off diffuse surfaces along a single path.
+    pub max_diffuse_bounces: u32,
+    /// Maximum number of reflections and refractions along a single path.
+    pub max_specular_bounces: u32,
+    pub indirect_clamp: Option<IndirectClamp>,
+    /// Applied to the light arriving at each pixel in beauty mode.
+    pub tone_map: ToneMap,
+    /// Of the display the image is encoded for, where 1 keeps colors linear.
+    pub gamma: f32,
+    /// Distance from the camera before which primary rays ignore geometry, measured from where
+    /// they start past the camera's own `near`.
+    pub near: f32,
+    /// Distance from the camera beyond which primary rays ignore geometry.
+    pub far: f32,
+    /// Seed from which every pixel's random numbers are derived.
+    pub seed: u64,
+    /// Index of the frame being rendered in a sequence.
+    pub frame_index: u64,
+    /// Whether noise changes from frame to frame, rather than staying fixed for a static camera.
+    pub animate_noise: bool,
+    /// Measures the time spent in each stage of rendering, at a small cost.
+    pub profile: bool,
+    /// Shades every surface with `Material::clay()` instead of its own material.
+    pub clay: bool,
+    pub contact_shadows: Option<ContactShadows>,
+    /// Ends dim paths early. Leaves the image unbiased, but changes its noise, so should be off for
+    /// renders compared against a reference.
+    pub russian_roulette: Option<RussianRoulette>,
+    /// Wall-clock time a frame may take. Tiles not started by the time it runs out are left as they
+    /// were, and accumulation stops adding passes.
+    pub time_budget: Option<Duration>,
+    /// Blends partially opaque surfaces with whatever lies behind them, at the cost of finding
+    /// every intersection along each ray.
+    pub transparency: bool,
+    /// Lets each sample pass through partially opaque surfaces with a probability of their
+    /// transparency, so averaging samples gives smooth cutout edges. Ignored with `transparency`.
+    pub stochastic_alpha: bool,
+    pub background: Background,
+    /// Distance rays leaving a surface skip to avoid hitting it again, relative to how far the
+    /// surface is from the world origin.
+    pub ray_epsilon: f32,
+    pub focus_peaking: Option<FocusPeaking>,
+    /// Trades accuracy for speed while navigating, rendering dielectrics with
+    /// `Material::PREVIEW_GLASS`.
+    pub preview: bool,
+    /// Irradiance of `background`, lighting diffuse surfaces in place of the background itself.
+    pub irradiance: Option<Arc<IrradianceMap>>,
+    /// Distribution of the light of `background`, an environment image, from which Lambertian
+    /// surfaces sample it directly. Ignored when `irradiance` is set.
+    pub environment_sampling: Option<Arc<EnvironmentSampler>>,
+    /// Lights of the scene, as collected by `Object::lights`, which Lambertian surfaces sample
+    /// directly.
+    pub lights: Vec<Light>,
+    /// Fraction of each pixel's samples to discard, brightest first, before averaging the rest.
+    /// Removes fireflies without clamping to a fixed brightness.
+    pub sample_trim: f32,
+}
+
+impl RenderSettings {
+    /// Seed for the current frame, advanced by the frame index when noise is animated.
+    pub fn frame_seed(&self) -> u64 {
+        if self.animate_noise {
+            mix(self.seed ^ mix(self.frame_index))
+        } else {
+            self.seed
+        }
+    }
+
+    /// Pattern named by `samples`.
+    pub fn sample_pattern(&self) -> &'static dyn SamplePattern {
+        pattern(self.samples)
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            mode: RenderMode::Beauty,
+            samples: SamplePatternKind::Multisample8x,
+            foveation: None,
+            edge_detection: None,
+            adaptive_sampling: None,
+            max_diffuse_bounces: 50,
+            max_specular_bounces: 50,
+            indirect_clamp: None,
+            tone_map: ToneMap::default(),
+            gamma: 2.0,
+            near: 0.0,
+            far: f32::INFINITY,
+            seed: 0,
+            frame_index: 0,
+            animate_noise: false,
+            profile: false,
+            clay: false,
+            contact_shadows: None,
+            russian_roulette: None,
+            time_budget: None,
+            transparency: false,
+            stochastic_alpha: false,
+            background: Background::default(),
+            ray_epsilon: DEFAULT_RAY_EPSILON,
+            focus_peaking: None,
+            preview: false,
+            irradiance: None,
+            environment_sampling: None,
+            lights: Vec::new(),
+            sample_trim: 0.0,
+        }
+    }
+}
+
+/// Color written for a pixel in the current render mode, where the pixel's color is shared by a
+/// block of `scale` by `scale` pixels.
+fn shade_pixel(p: Point2<u32>, viewport: &Viewport, object: &Object, samples: &dyn SamplePattern, scale: u32, settings: &RenderSettings) -> Color {
+    let block = (scale * scale) as f32;
+    match (settings.mode, &settings.adaptive_sampling) {
+        (RenderMode::Beauty, Some(adaptive)) => adaptive.render_pixel(p, viewport, object, settings).0,
+        (RenderMode::SampleCount, Some(adaptive)) => {
+            let (_, taken) = adaptive.render_pixel(p, viewport, object, settings);
+            sample_count_color(taken as f32 / block, adaptive.max_samples as f32)
+        }
+        (RenderMode::SampleCount, None) => sample_count_color(samples.sample_count() as f32 / block, MAX_SAMPLE_COUNT as f32),
+        _ => render_pixel(p, viewport, object, samples, settings),
+    }
+}
+
+fn render_tile<P: PixelFormat>(tile: &Tile, quality: &TileQuality, viewport: &Viewport, object: &Object, settings: &RenderSettings) -> Vec<P> {
+    let scale = quality.scale;
+    let blocks_x = tile.width.div_ceil(scale);
+    let blocks_y = tile.height.div_ceil(scale);
+    let blocks: Vec<Color> = (0..blocks_y)
+        .flat_map(|by| (0..blocks_x).map(move |bx| (bx, by)))
+        .map(|(bx, by)| {
+            let x = (tile.x + bx * scale + scale / 2).min(tile.x + tile.width - 1);
+            let y = (tile.y + by * scale + scale / 2).min(tile.y + tile.height - 1);
+            shade_pixel(point![x, y], viewport, object, quality.samples, scale, settings)
+        })
+        .collect();
+
+    (0..tile.height)
+        .flat_map(|y| (0..tile.width).map(move |x| (x, y)))
+        .map(|(x, y)| blocks[(y / scale * blocks_x + x / scale) as usize])
+        .map(P::from)
+        .collect()
+}
+
+/// Restricts multisampling to pixels along edges, where it makes a visible difference.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EdgeDetection {
+    /// Luminance difference to a neighbouring pixel above which a pixel is considered an edge.
+    pub threshold: f32,
+}
+
+impl EdgeDetection {
+    /// Flags every pixel of a row-major image whose luminance differs from a horizontal or vertical
+    /// neighbour by more than the threshold.
+    pub fn edges(&self, colors: &[Color], width: u32, height: u32) -> Vec<bool> {
+        let luminance = |x: u32, y: u32| colors[(y * width + x) as usize].luminance();
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let center = luminance(x, y);
+                [
+                    (x > 0).then(|| (x - 1, y)),
+                    (x + 1 < width).then(|| (x + 1, y)),
+                    (y > 0).then(|| (x, y - 1)),
+                    (y + 1 < height).then(|| (x, y + 1)),
+                ]
+                    .into_iter()
+                    .flatten()
+                    .any(|(nx, ny)| (luminance(nx, ny) - center).abs() > self.threshold)
+            })
+            .collect()
+    }
+}
+
+/// Renders every pixel with a single sample, then re-renders the pixels detected as edges using the
+/// full sample pattern.
+fn render_edge_aware<P: PixelFormat + Send>(
+    detection: &EdgeDetection,
+    width: u32,
+    height: u32,
+    viewport: &Viewport,
+    object: &Object,
+    samples: &dyn SamplePattern,
+    settings: &RenderSettings,
+) -> Vec<P> {
+    let coordinates: Vec<_> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| point![x, y]))
+        .collect();
+
+    let preview: Vec<Color> = coordinates.par_iter()
+        .map(|&p| render_pixel(p, viewport, object, &SINGLE_SAMPLE_PATTERN, settings))
+        .collect();
+    let edges = detection.edges(&preview, width, height);
+    trace!(target: "app", "Multisampling {} edge pixels", edges.iter().filter(|&&edge| edge).count());
+
+    coordinates.into_par_iter()
+        .zip(preview)
+        .zip(edges)
+        .map(|((p, color), edge)| match (settings.mode, edge) {
+            (RenderMode::SampleCount, true) => sample_count_color(samples.sample_count() as f32, MAX_SAMPLE_COUNT as f32),
+            (RenderMode::SampleCount, false) => sample_count_color(1.0, MAX_SAMPLE_COUNT as f32),
+            (_, true) => render_pixel(p, viewport, object, samples, settings),
+            (_, false) => color,
+        })
+        .map(P::from)
+        .collect()
+}
+
+/// Renders directly into a caller-owned, row-major buffer of the given size, without allocating
+/// per unit of work. Produces the same pixels as `render_frame_async` without foveation or edge
+/// detection.
+pub fn render_into<P: PixelFormat + Send>(
+    out: &mut [P],
+    size: (u32, u32),
+    camera: &Camera,
+    object: &Object,
+    settings: &RenderSettings,
+) {
+    let (width, height) = size;
+    let samples = settings.sample_pattern();
+    assert_eq!(out.len(), width as usize * height as usize, "output buffer length");
+    let viewport = camera.viewport(width, height);
+
+    out.par_chunks_mut(width as usize)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let p = point![x as u32, y as u32];
+                *pixel = P::from(shade_pixel(p, &viewport, object, samples, 1, settings));
+            }
+        });
+}
+
+pub fn render_frame_async<P: PixelFormat + Copy + Send>(
+    frame: &Mutex<Frame<P>>,
+    camera: &Camera,
+    object: &Object,
+    settings: &RenderSettings,
+) -> RenderStats {
+    let (width, height) = {
+        let frame = frame.lock().expect("frame lock");
+        (frame.width(), frame.height())
+    };
+    let viewport = camera.viewport(width, height);
+    // discard anything left over from rendering outside of a frame.
+    collect_stats();
+
+    if let Some(detection) = &settings.edge_detection {
+        let pixels = render_edge_aware(detection, width, height, &viewport, object, settings.sample_pattern(), settings);
+        timed(settings, |t| &mut t.upload, || {
+            let mut frame = frame.lock().expect("frame submission lock");
+            frame.picture_mut().buffer_mut().copy_from_slice(&pixels);
+        });
+        return finish_stats(settings);
+    }
+    render_tiles_async(frame, &scheduled_tiles(width, height), camera, object, settings)
+}
+
+/// Renders only the given tiles of the frame, leaving the rest of it as it was. Edge detection is
+/// not applied.
+pub fn render_tiles_async<P: PixelFormat + Copy + Send>(
+    frame: &Mutex<Frame<P>>,
+    tiles: &[Tile],
+    camera: &Camera,
+    object: &Object,
+    settings: &RenderSettings,
+) -> RenderStats {
+    let (width, height) = {
+        let frame = frame.lock().expect("frame lock");
+        (frame.width(), frame.height())
+    };
+    let viewport = camera.viewport(width, height);
+    collect_stats();
+    let finished = AtomicUsize::new(0);
+    let start = Instant::now();
+
+    tiles.par_iter()
+        .for_each(|tile| {
+            if settings.time_budget.is_some_and(|budget| start.elapsed() >= budget) {
+                return;
+            }
+            let quality = match &settings.foveation {
+                Some(foveation) => foveation.tile_quality(tile, width, height),
+                None => TileQuality { samples: settings.sample_pattern(), scale: 1 },
+            };
+            trace!(target: "app", "Rendering tile: {:?}", tile);
+            let buffer: Vec<P> = render_tile(tile, &quality, &viewport, object, settings);
+
+            timed(settings, |t| &mut t.upload, || {
                 let mut frame = frame.lock().expect("frame submission lock");
                 let mut picture = frame.picture_mut();
-                let slice = picture.buffer_mut().get_mut(index..index + buffer.len()).unwrap();
-                slice.copy_from_slice(&buffer);
-            }
+                for (row, pixels) in buffer.chunks(tile.width as usize).enumerate() {
+                    picture.slice_mut(tile.x, tile.y + row as u32, pixels.len()).copy_from_slice(pixels);
+                }
+            });
+            let finished = finished.fetch_add(1, Ordering::Relaxed) + 1;
+            trace!(target: "app", "Finished {}/{} tiles", finished, tiles.len());
+        });
+
+    finish_stats(settings)
+}
+
+/// Linear light summed over every pass rendered by `accumulate_frame_async` since the last reset,
+/// so that a still view keeps converging.
+#[derive(Clone, Debug, Default)]
+pub struct Accumulation {
+    sum: Vec<Color>,
+    /// Of the luminance of every sample taken in each pixel, for `RenderMode::StandardError`.
+    luminance: Vec<LuminanceStats>,
+    size: (u32, u32),
+    sample_count: usize,
+}
+
+impl Accumulation {
+    /// Samples summed into every pixel.
+    pub fn sample_count(&self) -> usize {
+        self.sample_count
+    }
+
+    /// Discards every pass, as needed whenever the view changes.
+    pub fn reset(&mut self) {
+        self.sum.iter_mut().for_each(|sum| *sum = Color::BLACK);
+        self.luminance.iter_mut().for_each(|stats| *stats = LuminanceStats::default());
+        self.sample_count = 0;
+    }
+
+    /// Linear average of every sample taken in each pixel.
+    pub fn average(&self) -> Picture<Vec<Color>> {
+        let count = self.sample_count.max(1) as f32;
+        let pixels = self.sum.iter()
+            .map(|sum| Color::new(sum.r / count, sum.g / count, sum.b / count, 1.0))
+            .collect();
+        Picture::new(pixels, self.size)
+    }
+}
+
+/// Adds a fresh pass of samples to the accumulation and writes the average of all of them to the
+/// frame. Every pass uses different random numbers, so the frame converges as passes are added.
+/// Always renders beauty samples, without foveation, edge detection, trimming or focus peaking, and
+/// shows them as such unless in `RenderMode::StandardError`, where the frame shows the standard
+/// error of the mean luminance of every sample accumulated so far.
+pub fn accumulate_frame_async<P: PixelFormat + Copy + Send>(
+    frame: &Mutex<Frame<P>>,
+    accumulation: &mut Accumulation,
+    camera: &Camera,
+    object: &Object,
+    settings: &RenderSettings,
+) -> RenderStats {
+    let (width, height) = {
+        let frame = frame.lock().expect("frame lock");
+        (frame.width(), frame.height())
+    };
+    if accumulation.size != (width, height) {
+        *accumulation = Accumulation {
+            sum: vec![Color::BLACK; width as usize * height as usize],
+            luminance: vec![LuminanceStats::default(); width as usize * height as usize],
+            size: (width, height),
+            sample_count: 0,
+        };
+    }
+    let viewport = camera.viewport(width, height);
+    collect_stats();
+
+    let samples = settings.sample_pattern();
+    let pass = RenderSettings {
+        seed: mix(settings.frame_seed() ^ mix(accumulation.sample_count as u64)),
+        animate_noise: false,
+        ..settings.clone()
+    };
+    accumulation.sum.par_iter_mut()
+        .zip(accumulation.luminance.par_iter_mut())
+        .enumerate()
+        .for_each(|(index, (sum, luminance))| {
+            let p = point![(index % width as usize) as u32, (index / width as usize) as u32];
+            let (average, stats) = pixel_statistics(p, &viewport, object, samples, &pass);
+            *sum = *sum + average * stats.count;
+            luminance.merge(&stats);
         });
+    accumulation.sample_count += samples.sample_count();
+
+    let count = accumulation.sample_count as f32;
+    let pixels: Vec<P> = accumulation.sum.par_iter()
+        .zip(accumulation.luminance.par_iter())
+        .map(|(sum, luminance)| match settings.mode {
+            RenderMode::StandardError => heat_color(standard_error(luminance.variance(), luminance.count as usize) / MAX_STANDARD_ERROR),
+            _ => {
+                let average = Color::new(sum.r / count, sum.g / count, sum.b / count, 1.0);
+                gamma_correct(settings.tone_map.apply(average), settings.gamma)
+            }
+        })
+        .map(P::from)
+        .collect();
+    timed(settings, |t| &mut t.upload, || {
+        let mut frame = frame.lock().expect("frame submission lock");
+        frame.picture_mut().buffer_mut().copy_from_slice(&pixels);
+    });
+    finish_stats(settings)
+}
+
+/// Adds passes to the accumulation until it holds `max_samples` samples per pixel or the time budget
+/// runs out, leaving the frame with whatever has converged by then. At least one pass is rendered,
+/// and a pass is always finished once started, so the budget may be overrun by up to one pass.
+pub fn accumulate_frame_until_async<P: PixelFormat + Copy + Send>(
+    frame: &Mutex<Frame<P>>,
+    accumulation: &mut Accumulation,
+    camera: &Camera,
+    object: &Object,
+    settings: &RenderSettings,
+    max_samples: usize,
+) -> RenderStats {
+    let start = Instant::now();
+    let mut stats = RenderStats::default();
+    loop {
+        stats = stats + accumulate_frame_async(frame, accumulation, camera, object, settings);
+        let out_of_time = settings.time_budget.is_some_and(|budget| start.elapsed() >= budget);
+        if out_of_time || accumulation.sample_count() >= max_samples {
+            return stats;
+        }
+    }
+}
+
+fn finish_stats(settings: &RenderSettings) -> RenderStats {
+    let stats = collect_stats();
+    if settings.profile {
+        debug!(target: "app", "Render stats: {:?}", stats);
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use nalgebra::Unit;
+
+    use crate::camera::{CameraBuilder, Projection};
+    use crate::picture::RGBA8;
+    use crate::scene::{random_scene, RandomSceneOptions};
+    use crate::texture::Checker;
+
+    use super::*;
+
+    #[test]
+    fn tilted_plane_gives_linear_depth_ramp() {
+        let camera = CameraBuilder::new(point![0.0, 0.0, 10.0])
+            .projection(Projection::Orthographic { height: 2.0 })
+            .build();
+        // z = -y, so depth grows by one unit for every unit up the view.
+        let plane = Object::plane(Point3::origin(), Unit::new_normalize(vector![0.0, 1.0, 1.0]), Material::lambert(Color::WHITE));
+        let depth = render_depth_map(&camera, &plane, (4, 8), 8.0, 12.0);
+        let depth = depth.view();
+
+        let column: Vec<f32> = (0..8).map(|y| *depth.pixel(0, y) as f32).collect();
+        let step = column[1] - column[0];
+        assert!(step > 0.0);
+        for pair in column.windows(2) {
+            assert!((pair[1] - pair[0] - step).abs() <= 2.0, "uneven ramp {:?}", column);
+        }
+        for y in 0..8 {
+            for x in 1..4 {
+                assert_eq!(depth.pixel(x, y), depth.pixel(0, y));
+            }
+        }
+    }
+
+    #[test]
+    fn off_screen_edit_dirties_no_tiles() {
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0]).build();
+        let objects = [
+            Object::sphere(Point3::origin(), 1.0, Material::lambert(Color::WHITE)),
+            // behind the camera.
+            Object::sphere(point![0.0, 0.0, 10.0], 1.0, Material::lambert(Color::WHITE)),
+        ];
+        let ids = render_object_ids(&camera, &objects, (64, 48), &RenderSettings::default());
+
+        assert!(dirty_tiles(&ids.view(), &[1]).is_empty());
+        assert!(!dirty_tiles(&ids.view(), &[0]).is_empty());
+    }
+
+    #[test]
+    fn object_ids_match_rendered_pixel_centers() {
+        // only the pixel in the top right corner sees the sphere through its center.
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0]).build();
+        let size = (8, 8);
+        let viewport = camera.viewport(size.0, size.1);
+        let settings = RenderSettings::default();
+        let corner = pixel_ray(point![7, 7], &SINGLE_SAMPLE_PATTERN[0], 0, 1, &viewport, &settings, &mut Rng::with_seed(0));
+        let target = corner.at(5.0 / corner.direction.magnitude());
+        let objects = [Object::sphere(target, 0.01, Material::lambert(Color::WHITE))];
+
+        let ids = render_object_ids(&camera, &objects, size, &settings);
+        assert_eq!(*ids.view().pixel(7, 7), Some(0));
+    }
+
+    #[test]
+    fn geometry_before_near_plane_is_not_rendered() {
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0]).build();
+        let objects = [
+            Object::sphere(point![0.0, 0.0, 4.0], 0.5, Material::lambert(Color::WHITE)),
+            Object::sphere(Point3::origin(), 1.0, Material::lambert(Color::WHITE)),
+        ];
+        let clipped = RenderSettings { near: 2.0, ..Default::default() };
+
+        let ids = render_object_ids(&camera, &objects, (8, 8), &RenderSettings::default());
+        assert_eq!(*ids.view().pixel(4, 4), Some(0));
+        let ids = render_object_ids(&camera, &objects, (8, 8), &clipped);
+        assert_eq!(*ids.view().pixel(4, 4), Some(1));
+    }
+
+    #[test]
+    fn lambert_under_white_sky_converges_to_albedo() {
+        let plane = Object::plane(Point3::origin(), Vector3::y_axis(), Material::lambert(Color::new(0.8, 0.8, 0.8, 1.0)));
+        let settings = RenderSettings { background: Background::SolidColor(Color::WHITE), ..Default::default() };
+        let bounces = Bounces { diffuse: 4, specular: 0 };
+        let ray = Ray::new(point![0.0, 1.0, 0.0], vector![0.3, -1.0, 0.2]);
+        let mut rng = Rng::with_seed(5);
+        let mut stats = LuminanceStats::default();
+        for _ in 0..10000 {
+            stats.push(render_ray(&ray, &plane, &settings, bounces, 0, &mut rng).luminance());
+        }
+        assert!((stats.mean - 0.8).abs() < 0.01, "{}", stats.mean);
+    }
+
+    #[test]
+    fn one_sphere_and_ray_count_one_intersection_test() {
+        let sphere = Object::sphere(Point3::origin(), 1.0, Material::lambert(Color::WHITE));
+        take_stats();
+        sphere.hit(&Ray::new(point![0.0, 0.0, 5.0], -Vector3::z()), 0.0..);
+        let stats = take_stats();
+        assert_eq!((stats.primitive_tests, stats.node_visits, stats.hits), (1, 0, 1));
+    }
+
+    /// Bytes of a small render of the random scene, laid out by a generator of its own.
+    fn render_random_scene(settings: &RenderSettings) -> Vec<u8> {
+        let scene = random_scene(&RandomSceneOptions::default(), &mut Rng::with_seed(1));
+        let camera = CameraBuilder::new(point![13.0, 2.0, 3.0]).fov_deg(20.0).build();
+        let mut pixels = vec![RGBA8::default(); 16 * 12];
+        render_into(&mut pixels, (16, 12), &camera, &scene, settings);
+        bytemuck::cast_slice(&pixels).to_vec()
+    }
+
+    #[test]
+    fn render_is_independent_of_thread_count() {
+        let render = |threads| rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(|| render_random_scene(&RenderSettings::default()));
+
+        let single = render(1);
+        assert_eq!(render(2), single);
+        assert_eq!(render(8), single);
+    }
+
+    #[test]
+    fn same_seed_renders_identically() {
+        let seeded = |seed| RenderSettings { seed, ..Default::default() };
+        assert_eq!(render_random_scene(&seeded(7)), render_random_scene(&seeded(7)));
+        assert_ne!(render_random_scene(&seeded(7)), render_random_scene(&seeded(8)));
+    }
+
+    #[test]
+    fn foveation_gives_central_tiles_more_samples_than_corners() {
+        let (width, height) = (TILE_SIZE * 8, TILE_SIZE * 6);
+        let foveation = Foveation::centered(width, height);
+        let samples = |column, row| foveation.tile_quality(&Tile::at(column, row, width, height), width, height).samples.sample_count();
+
+        let center = samples(4, 3);
+        for corner in [samples(0, 0), samples(7, 0), samples(0, 5), samples(7, 5)] {
+            assert!(center > corner, "{} and {}", center, corner);
+        }
+    }
+
+    #[test]
+    fn glass_chain_takes_more_specular_than_diffuse_bounces() {
+        let chain = Object::list((0..3)
+            .map(|i| Object::sphere(point![0.0, 0.0, -3.0 * i as f32], 1.0, Material::dielectric(1.5)))
+            .collect());
+        let settings = RenderSettings { background: Background::SolidColor(Color::WHITE), ..Default::default() };
+        let ray = Ray::new(point![0.0, 0.0, 5.0], -Vector3::z());
+        let mean = |bounces| {
+            let mut rng = Rng::with_seed(2);
+            (0..1000).map(|_| render_ray(&ray, &chain, &settings, bounces, 0, &mut rng).luminance()).sum::<f32>() / 1000.0
+        };
+
+        // passing straight through takes six refractions.
+        assert!(mean(Bounces { diffuse: 2, specular: 16 }) > 0.7);
+        assert!(mean(Bounces { diffuse: 2, specular: 2 }) < 0.2);
+    }
+
+    #[test]
+    fn indirect_clamp_leaves_direct_light_untouched() {
+        let light = Color::new(100.0, 100.0, 100.0, 1.0);
+        let room = Object::list(vec![
+            Object::plane(Point3::origin(), Vector3::y_axis(), Material::lambert(Color::new(0.5, 0.5, 0.5, 1.0))),
+            Object::plane(point![0.0, 1.0, 0.0], -Vector3::y_axis(), Material::diffuse_light(light)),
+        ]);
+        let unclamped = RenderSettings::default();
+        let clamped = RenderSettings { indirect_clamp: Some(IndirectClamp::new(1.0)), ..Default::default() };
+        let bounces = Bounces { diffuse: 4, specular: 4 };
+        let render = |direction, settings| {
+            render_ray(&Ray::new(point![0.0, 0.5, 0.0], direction), &room, settings, bounces, 0, &mut Rng::with_seed(0)).luminance()
+        };
+
+        assert!((render(Vector3::y(), &clamped) - 100.0).abs() < 1e-3);
+        assert!((render(-Vector3::y(), &unclamped) - 50.0).abs() < 1e-3);
+        assert!((render(-Vector3::y(), &clamped) - 0.5).abs() < 1e-3);
+    }
+
+    /// Mean and variance of the luminance of many diffuse bounces off a white plane under the
+    /// background.
+    fn plane_statistics(settings: &RenderSettings, samples: u32) -> (f32, f32) {
+        let plane = Object::plane(Point3::origin(), Vector3::y_axis(), Material::lambert(Color::new(0.5, 0.5, 0.5, 1.0)));
+        let bounces = Bounces { diffuse: 1, specular: 0 };
+        let ray = Ray::new(point![0.0, 1.0, 0.0], vector![0.3, -1.0, 0.2]);
+        let mut rng = Rng::with_seed(11);
+        let mut stats = LuminanceStats::default();
+        for _ in 0..samples {
+            stats.push(render_ray(&ray, &plane, settings, bounces, 0, &mut rng).luminance());
+        }
+        (stats.mean, stats.variance())
+    }
+
+    #[test]
+    fn environment_sampling_reduces_noise_without_bias() {
+        let (width, height) = (16, 8);
+        let pixels = (0..height)
+            .flat_map(|row| (0..width).map(move |column| (column, row)))
+            .map(|texel| if texel == (3, 2) { Color::new(40.0, 40.0, 40.0, 1.0) } else { Color::new(0.2, 0.2, 0.2, 1.0) })
+            .collect();
+        let background = Background::Environment(Arc::new(Picture::new(pixels, (width, height))));
+        let uniform = RenderSettings { background: background.clone(), ..Default::default() };
+        let sampled = RenderSettings {
+            environment_sampling: EnvironmentSampler::new(&background).map(Arc::new),
+            ..uniform.clone()
+        };
+
+        // irradiance of the dim sky, plus that of the bright texel spanning polar angles of 45° to
+        // 67.5° and a sixteenth of the way around.
+        let (from, to) = (PI / 4.0, 3.0 * PI / 8.0);
+        let texel_irradiance = 2.0 * PI / 16.0 * (to.sin().powi(2) - from.sin().powi(2)) / 2.0;
+        let expected = 0.5 / PI * (0.2 * PI + (40.0 - 0.2) * texel_irradiance);
+
+        let (_, uniform_variance) = plane_statistics(&uniform, 40000);
+        let (sampled_mean, sampled_variance) = plane_statistics(&sampled, 40000);
+        assert!((sampled_mean - expected).abs() < 0.02 * expected, "{} instead of {}", sampled_mean, expected);
+        assert!(sampled_variance < uniform_variance / 4.0, "{} and {}", sampled_variance, uniform_variance);
+    }
+
+    fn luminance(picture: &Picture<&[RGBA8]>, x: u32, y: u32) -> f32 {
+        Color::from(*picture.pixel(x, y)).luminance()
+    }
+
+    #[test]
+    fn foveated_center_reads_brighter_in_sample_count_mode() {
+        let size = (128, 128);
+        let frame = Mutex::new(Frame::<RGBA8>::from_buffer(vec![0; 128 * 128 * 4], size));
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0]).build();
+        let settings = RenderSettings {
+            mode: RenderMode::SampleCount,
+            foveation: Some(Foveation::centered(size.0, size.1)),
+            ..Default::default()
+        };
+        render_frame_async(&frame, &camera, &Object::list(vec![]), &settings);
+
+        let frame = frame.lock().unwrap();
+        let picture = frame.picture();
+        assert!(luminance(&picture, 64, 64) > luminance(&picture, 0, 0));
+    }
+
+    #[test]
+    fn adaptive_sample_count_is_brighter_where_more_samples_were_taken() {
+        let size = (16, 16);
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0]).fov_deg(30.0).build();
+        // diffuse bounces make the sphere noisy, while the sky is nearly flat within each pixel.
+        let sphere = Object::sphere(Point3::origin(), 1.0, Material::lambert(Color::new(0.5, 0.5, 0.5, 1.0)));
+        let settings = RenderSettings {
+            mode: RenderMode::SampleCount,
+            adaptive_sampling: Some(AdaptiveSampling { min_samples: 4, max_samples: 32, tolerance: 0.01 }),
+            ..Default::default()
+        };
+        let mut out = vec![RGBA8::default(); 16 * 16];
+        render_into(&mut out, size, &camera, &sphere, &settings);
+
+        let picture = Picture::new(&out[..], size);
+        assert!(luminance(&picture, 8, 8) > luminance(&picture, 0, 0));
+        assert!((luminance(&picture, 0, 0) - 4.0 / 32.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn pixel_samples_span_the_shutter_evenly() {
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0]).shutter(1.0, 3.0).build();
+        let viewport = camera.viewport(4, 4);
+        let settings = RenderSettings::default();
+        let mut rng = Rng::with_seed(7);
+
+        let mut times: Vec<f32> = MULTISAMPLE_8X_PATTERN.iter()
+            .enumerate()
+            .map(|(sample, offset)| pixel_ray(point![1, 2], offset, sample, 8, &viewport, &settings, &mut rng).time)
+            .collect();
+        times.sort_by_key(|time| FloatOrd(*time));
+        for (stratum, time) in times.iter().enumerate() {
+            let from = 1.0 + 2.0 * stratum as f32 / 8.0;
+            assert!((from..=from + 2.0 / 8.0).contains(time), "{:?}", times);
+        }
+    }
+
+    #[test]
+    fn morton_scheduler_visits_every_tile_once_in_z_order() {
+        let order: Vec<_> = MortonScheduler::new(5, 3).collect();
+        let mut sorted = order.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), 15);
+        assert_eq!(order.len(), 15);
+        assert!(sorted.iter().all(|&(column, row)| column < 5 && row < 3));
+
+        assert_eq!(order[..6], [(0, 0), (1, 0), (0, 1), (1, 1), (2, 0), (3, 0)]);
+    }
+
+    #[test]
+    fn near_clip_removes_foreground_but_keeps_background() {
+        let objects = Object::list(vec![
+            Object::sphere(point![0.0, 0.0, 3.0], 0.5, Material::lambert(Color::WHITE)),
+            Object::plane(Point3::origin(), Vector3::z_axis(), Material::lambert(Color::WHITE)),
+        ]);
+        let ray = Ray::new(point![0.0, 0.0, 5.0], -Vector3::z());
+        let depth = |near| render_depth(&ray, &objects, &RenderSettings { near, ..Default::default() });
+
+        assert!((depth(0.0).unwrap() - 1.5).abs() < 1e-4);
+        assert!((depth(3.0).unwrap() - 5.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn animated_noise_changes_between_frames() {
+        let frame = |frame_index, animate_noise| RenderSettings { frame_index, animate_noise, ..Default::default() };
+        assert_eq!(render_random_scene(&frame(0, false)), render_random_scene(&frame(1, false)));
+        assert_ne!(render_random_scene(&frame(0, true)), render_random_scene(&frame(1, true)));
+    }
+
+    #[test]
+    fn edge_detection_multisamples_only_along_the_edge() {
+        let size = (16, 16);
+        let frame = Mutex::new(Frame::<RGBA8>::from_buffer(vec![0; 16 * 16 * 4], size));
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0])
+            .projection(Projection::Orthographic { height: 2.0 })
+            .build();
+        // lit on the left half of the view only.
+        let light = Object::rect_xy(point![-5.0, -5.0], point![0.0, 5.0], 0.0, Material::diffuse_light(Color::WHITE));
+        let settings = RenderSettings {
+            mode: RenderMode::SampleCount,
+            edge_detection: Some(EdgeDetection { threshold: 0.1 }),
+            background: Background::SolidColor(Color::BLACK),
+            ..Default::default()
+        };
+        render_frame_async(&frame, &camera, &light, &settings);
+
+        let frame = frame.lock().unwrap();
+        let picture = frame.picture();
+        let single = luminance(&picture, 0, 0);
+        for y in 0..16 {
+            for x in 0..16 {
+                let multisampled = luminance(&picture, x, y) > single;
+                assert_eq!(multisampled, x == 7 || x == 8, "pixel {} {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn view_space_normal_facing_camera_is_the_same_from_any_side() {
+        let sphere = Object::sphere(Point3::origin(), 1.0, Material::lambert(Color::WHITE));
+        let facing = Color::visualize_normal(&Vector3::z());
+        for position in [point![0.0, 0.0, 5.0], point![5.0, 3.0, -2.0], point![-4.0, -1.0, 1.0]] {
+            let viewport = CameraBuilder::new(position).build().viewport(8, 8);
+            let ray = viewport.emit_ray(&point![0.5, 0.5], 0, 1, &mut Rng::with_seed(0));
+            let normal = render_normal(&ray, &sphere, &RenderSettings::default(), NormalSpace::View, &viewport);
+            assert!((normal.r - facing.r).abs() < 1e-3 && (normal.g - facing.g).abs() < 1e-3 && (normal.b - facing.b).abs() < 1e-3, "{:?}", normal);
+        }
+    }
+
+    #[test]
+    fn each_pattern_kind_has_its_sample_count() {
+        let counts = SamplePatternKind::ALL.map(|kind| pattern(kind).sample_count());
+        assert_eq!(counts, [1, 2, 4, 8, 16]);
+        for kind in SamplePatternKind::ALL {
+            assert_eq!(pattern(kind).sample_offsets(&mut Rng::with_seed(0)).len(), pattern(kind).sample_count());
+        }
+    }
+
+    #[test]
+    fn stage_timings_add_up_to_roughly_the_render_time() {
+        let frame = Mutex::new(Frame::<RGBA8>::from_buffer(vec![0; 32 * 32 * 4], (32, 32)));
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0]).build();
+        let sphere = Object::sphere(Point3::origin(), 1.0, Material::lambert(Color::WHITE));
+        let settings = RenderSettings { profile: true, ..Default::default() };
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let start = Instant::now();
+        let stats = pool.install(|| render_frame_async(&frame, &camera, &sphere, &settings));
+        let elapsed = start.elapsed();
+
+        let timings = stats.timings;
+        assert!(timings.intersection > Duration::ZERO && timings.shading > Duration::ZERO);
+        assert!(timings.total() <= elapsed, "{:?} of {:?}", timings.total(), elapsed);
+        assert!(timings.total() >= elapsed / 4, "{:?} of {:?}", timings.total(), elapsed);
+    }
+
+    #[test]
+    fn render_into_matches_render_frame_async() {
+        let scene = random_scene(&RandomSceneOptions::default(), &mut Rng::with_seed(1));
+        let camera = CameraBuilder::new(point![13.0, 2.0, 3.0]).fov_deg(20.0).build();
+        let settings = RenderSettings::default();
+
+        let mut pixels = vec![RGBA8::default(); 16 * 12];
+        render_into(&mut pixels, (16, 12), &camera, &scene, &settings);
+        let frame = Mutex::new(Frame::<RGBA8>::from_buffer(vec![0; 16 * 12 * 4], (16, 12)));
+        render_frame_async(&frame, &camera, &scene, &settings);
+
+        assert_eq!(bytemuck::cast_slice::<RGBA8, u8>(&pixels), frame.lock().unwrap().bytes());
+    }
+
+    #[test]
+    fn clay_mode_ignores_material_color() {
+        let settings = RenderSettings { clay: true, background: Background::SolidColor(Color::WHITE), ..Default::default() };
+        let ray = Ray::new(point![0.0, 1.0, 0.0], vector![0.3, -1.0, 0.2]);
+        let render = |albedo| {
+            let plane = Object::plane(Point3::origin(), Vector3::y_axis(), Material::lambert(albedo));
+            render_ray(&ray, &plane, &settings, Bounces { diffuse: 2, specular: 2 }, 0, &mut Rng::with_seed(4))
+        };
+
+        let red = render(Color::new(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(red, render(Color::new(0.0, 0.0, 1.0, 1.0)));
+        assert!(red.r == red.g && red.g == red.b);
+    }
+
+    #[test]
+    fn contact_shadows_darken_points_near_other_surfaces() {
+        let wall = Object::plane(Point3::origin(), Vector3::x_axis(), Material::lambert(Color::WHITE));
+        let contact = ContactShadows { radius: 1.0, strength: 1.0, samples: 64 };
+        let mut rng = Rng::with_seed(6);
+        let up = Vector3::y();
+
+        let near = contact.factor(&point![0.05, 0.0, 0.0], &up, &wall, &mut rng);
+        let exposed = contact.factor(&point![5.0, 0.0, 0.0], &up, &wall, &mut rng);
+        assert_eq!(exposed, 1.0);
+        assert!(near < 0.9, "{}", near);
+    }
+
+    #[test]
+    fn light_path_aovs_sum_to_the_beauty_render() {
+        let scene = Object::list(vec![
+            Object::sphere(point![-1.0, 0.0, 0.0], 0.8, Material::metal(Color::new(0.9, 0.8, 0.7, 1.0), 0.1)),
+            Object::sphere(point![1.0, 0.0, 0.0], 0.8, Material::lambert(Color::new(0.2, 0.6, 0.3, 1.0))),
+            Object::plane(point![0.0, -0.8, 0.0], Vector3::y_axis(), Material::lambert(Color::new(0.5, 0.5, 0.5, 1.0))),
+        ]);
+        let camera = CameraBuilder::new(point![0.0, 1.0, 6.0]).build();
+        let settings = RenderSettings::default();
+
+        let aovs = render_aovs(&camera, &scene, (12, 8), &settings);
+        let (beauty, _) = render_with_variance(&camera, &scene, (12, 8), &settings);
+        for y in 0..8 {
+            for x in 0..12 {
+                let sum = *aovs.diffuse.view().pixel(x, y) + *aovs.specular.view().pixel(x, y) + *aovs.emission.view().pixel(x, y);
+                // the beauty render is encoded for display.
+                let sum = gamma_correct(settings.tone_map.apply(sum), settings.gamma);
+                let expected = *beauty.view().pixel(x, y);
+                assert!((sum.luminance() - expected.luminance()).abs() < 1e-3, "pixel {} {}: {:?} and {:?}", x, y, sum, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn ray_offset_scales_with_distance_from_origin() {
+        let settings = RenderSettings::default();
+        let mut rng = Rng::with_seed(8);
+
+        // rays bouncing off a large surface far from the origin.
+        let far = Object::plane(point![20000.0, 0.0, 0.0], Unit::new_normalize(vector![1.0, 2.0, 3.0]), Material::lambert(Color::WHITE));
+        let acne = (0..1000)
+            .filter(|_| {
+                let incoming = Ray::new(point![20010.0, 10.0 * rng.f32(), 10.0 * rng.f32()], vector![-1.0, -0.3, -0.5]);
+                let hit = far.hit(&incoming, 0.0..).expect("far plane");
+                let ray = Ray::new(hit.point, random_cosine_direction(&mut rng, &hit.normal));
+                far.hit(&ray, t_range(&ray, &settings, 1)).is_some()
+            })
+            .count();
+        assert_eq!(acne, 0);
+
+        // a surface a thousandth of a unit away from one near the origin is still found.
+        let gap = Object::plane(point![0.0, 0.001, 0.0], Vector3::y_axis(), Material::lambert(Color::WHITE));
+        let ray = Ray::new(point![0.1, 0.0, 0.1], Vector3::y());
+        assert!(gap.hit(&ray, t_range(&ray, &settings, 1)).is_some());
+    }
+
+    #[test]
+    fn scheduled_tiles_cover_the_frame_once() {
+        let (width, height) = (TILE_SIZE * 3 + 5, TILE_SIZE * 2 + 1);
+        let tiles = scheduled_tiles(width, height);
+        let mut covered = vec![0; (width * height) as usize];
+        for tile in &tiles {
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    covered[(y * width + x) as usize] += 1;
+                }
+            }
+        }
+
+        assert!(covered.iter().all(|&count| count == 1));
+        assert_eq!(tiles, scheduled_tiles(width, height));
+    }
+
+    #[test]
+    fn preview_glass_transmits_no_light() {
+        let red = Color::new(1.0, 0.0, 0.0, 1.0);
+        let scene = Object::list(vec![
+            Object::sphere(Point3::origin(), 1.0, Material::dielectric(1.5)),
+            Object::rect_xy(point![-10.0, -10.0], point![10.0, 10.0], -3.0, Material::diffuse_light(red)),
+        ]);
+        let ray = Ray::new(point![0.0, 0.0, 5.0], -Vector3::z());
+        let render = |preview| {
+            let settings = RenderSettings { preview, background: Background::SolidColor(Color::BLACK), ..Default::default() };
+            let mut rng = Rng::with_seed(2);
+            (0..64).map(|_| render_ray(&ray, &scene, &settings, Bounces { diffuse: 4, specular: 8 }, 0, &mut rng).r).sum::<f32>()
+        };
+
+        // only light refracted through the sphere reaches the light behind it.
+        assert!(render(false) > 0.0);
+        assert_eq!(render(true), 0.0);
+    }
+
+    #[test]
+    fn cycling_modes_visits_each_once_and_wraps() {
+        let mut modes = vec![RenderMode::Beauty];
+        loop {
+            let next = modes.last().unwrap().next();
+            if next == RenderMode::Beauty {
+                break;
+            }
+            assert!(!modes.contains(&next), "{:?} visited twice", next);
+            modes.push(next);
+        }
+        assert_eq!(modes.len(), 8);
+        assert_eq!(RenderMode::Overdraw.next(), RenderMode::Beauty);
+    }
+
+    #[test]
+    fn trimmed_mean_excludes_a_firefly() {
+        let mut samples = vec![Color::new(0.5, 0.5, 0.5, 1.0); 31];
+        samples.insert(7, Color::new(1000.0, 1000.0, 1000.0, 1.0));
+
+        let (sum, kept) = trimmed_sum(samples, 0.05);
+        assert_eq!(kept, 31);
+        assert!((sum.r / kept as f32 - 0.5).abs() < 1e-5);
+        assert_eq!(trimmed_sum(vec![Color::WHITE], 0.9).1, 1);
+    }
+
+    #[test]
+    fn bvh_tests_far_fewer_primitives_than_a_flat_list() {
+        fn leaves(object: &Object, into: &mut Vec<Object>) {
+            match object {
+                Object::Bvh { left, right, .. } => {
+                    leaves(left, into);
+                    leaves(right, into);
+                }
+                leaf => into.push(leaf.clone()),
+            }
+        }
+        let bvh = random_scene(&RandomSceneOptions::default(), &mut Rng::with_seed(1));
+        let mut objects = Vec::new();
+        leaves(&bvh, &mut objects);
+        assert!(objects.len() > 400);
+        let flat = Object::list(objects);
+
+        let viewport = CameraBuilder::new(point![13.0, 2.0, 3.0]).fov_deg(20.0).build().viewport(16, 12);
+        let settings = RenderSettings::default();
+        let mut rng = Rng::with_seed(0);
+        let (mut bvh_tests, mut flat_tests) = (0, 0);
+        for (x, y) in (0..12).flat_map(|y| (0..16).map(move |x| (x, y))) {
+            let ray = viewport.emit_ray(&point![(x as f32 + 0.5) / 16.0, (y as f32 + 0.5) / 12.0], 0, 1, &mut rng);
+            assert_eq!(bvh.hit(&ray, 1e-3..).map(|hit| hit.t), flat.hit(&ray, 1e-3..).map(|hit| hit.t));
+            bvh_tests += traversal_cost(&ray, &bvh, &settings).primitive_tests;
+            flat_tests += traversal_cost(&ray, &flat, &settings).primitive_tests;
+        }
+        assert!(bvh_tests * 20 < flat_tests, "{} primitive tests through the BVH, {} flat", bvh_tests, flat_tests);
+    }
+
+    #[test]
+    fn hash_rng_depends_only_on_its_coordinates() {
+        let value = hash_rng(1.5, -2.0, 0.25);
+        assert_eq!(value, hash_rng(1.5, -2.0, 0.25));
+        assert_eq!(hash_rng(0.0, 1.0, 2.0), hash_rng(-0.0, 1.0, 2.0));
+        assert_ne!(value, hash_rng(1.5, -2.0, 0.26));
+        assert_ne!(value, hash_rng(-2.0, 1.5, 0.25));
+
+        let values: Vec<_> = (0..1000).map(|i| hash_rng(i as f32, 0.0, 0.0)).collect();
+        assert!(values.iter().all(|value| (0.0..1.0).contains(value)));
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        assert!((mean - 0.5).abs() < 0.05, "{}", mean);
+    }
+
+    #[test]
+    fn dense_cluster_costs_more_node_visits_than_empty_space() {
+        let mut rng = Rng::with_seed(5);
+        let cluster = (0..200)
+            .map(|_| Object::sphere(point![rng.f32(), rng.f32(), rng.f32()], 0.05, Material::lambert(Color::WHITE)));
+        let scene = Object::bvh(cluster.chain([Object::sphere(point![10.0, 0.0, 0.0], 0.5, Material::lambert(Color::WHITE))]).collect());
+        let settings = RenderSettings::default();
+
+        let dense = traversal_cost(&Ray::new(point![0.5, 0.5, -5.0], Vector3::z()), &scene, &settings);
+        let empty = traversal_cost(&Ray::new(point![5.0, 5.0, -5.0], Vector3::z()), &scene, &settings);
+        assert!(dense.node_visits > empty.node_visits + 5, "{:?} against {:?}", dense, empty);
+        assert!(dense.primitive_tests > empty.primitive_tests);
+    }
+
+    #[test]
+    fn checkered_ground_renders_both_colors() {
+        let (light, dark) = (Color::new(0.8, 0.8, 0.8, 1.0), Color::new(0.2, 0.2, 0.2, 1.0));
+        let ground = Object::plane(point![0.0, -0.5, 0.0], Vector3::y_axis(), Material::textured(Checker::colors(light, dark, PI)));
+        let settings = RenderSettings { background: Background::SolidColor(Color::WHITE), ..Default::default() };
+        // with a white sky every bounce off the open ground returns its albedo.
+        let render = |x: f32, z: f32| {
+            let ray = Ray::new(point![x, 2.0, z], -Vector3::y());
+            render_ray(&ray, &ground, &settings, Bounces { diffuse: 2, specular: 2 }, 0, &mut Rng::with_seed(3))
+        };
+
+        assert_eq!(render(0.5, 0.5), dark);
+        assert_eq!(render(1.5, 0.5), light);
+        assert_eq!(render(1.5, 1.5), dark);
+    }
+
+    #[test]
+    fn half_transparent_surface_lets_half_the_rays_through() {
+        let sheet = Object::plane(Point3::origin(), Vector3::z_axis(), Material::lambert(Color::new(1.0, 1.0, 1.0, 0.5)));
+        let ray = Ray::new(point![0.0, 0.0, 1.0], -Vector3::z());
+        let blocked = |stochastic_alpha| {
+            let settings = RenderSettings { stochastic_alpha, ..Default::default() };
+            let mut rng = Rng::with_seed(8);
+            (0..10_000).filter(|_| nearest_hit(&ray, &sheet, &settings, 0, &mut rng).is_some()).count()
+        };
+
+        let transmitted = 10_000 - blocked(true);
+        assert!((4_800..5_200).contains(&transmitted), "{} of 10000 rays transmitted", transmitted);
+        assert_eq!(blocked(false), 10_000);
+    }
+
+    #[test]
+    fn variance_is_high_only_across_edges() {
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0]).fov_deg(40.0).build();
+        // black on the left, with its edge crossing a single column, against a white sky.
+        let card = Object::rect_xy(point![-10.0, -10.0], point![0.1, 10.0], 0.0, Material::lambert(Color::BLACK));
+        let settings = RenderSettings { background: Background::SolidColor(Color::WHITE), ..Default::default() };
+
+        let (_, variance) = render_with_variance(&camera, &card, (8, 8), &settings);
+        let row: Vec<f32> = (0..8).map(|x| *variance.view().pixel(x, 3)).collect();
+        assert_eq!(row.iter().filter(|&&variance| variance > 0.1).count(), 1, "{:?}", row);
+        assert_eq!(row.iter().filter(|&&variance| variance < 1e-6).count(), 7, "{:?}", row);
+    }
+
+    #[test]
+    fn tone_maps_keep_black_and_saturate_bright_values() {
+        let gray = |c: f32| Color::new(c, c, c, 1.0);
+        for tone_map in [ToneMap::Clamp, ToneMap::Reinhard, ToneMap::ReinhardExtended { white: 4.0 }, ToneMap::Aces] {
+            assert_eq!(tone_map.apply(Color::BLACK), Color::BLACK, "{:?}", tone_map);
+
+            let mut previous = 0.0;
+            for c in [0.1, 0.5, 1.0, 2.0, 10.0, 1000.0] {
+                let mapped = tone_map.apply(gray(c)).r;
+                assert!(mapped >= previous && mapped <= 1.0, "{:?} maps {} to {}", tone_map, c, mapped);
+                previous = mapped;
+            }
+            assert!(previous > 0.99, "{:?} maps 1000 to {}", tone_map, previous);
+        }
+        assert_eq!(ToneMap::Reinhard.apply(gray(1.0)).r, 0.5);
+        assert_eq!(ToneMap::ReinhardExtended { white: 4.0 }.apply(gray(4.0)).r, 1.0);
+    }
+
+    #[test]
+    fn gamma_two_is_the_square_root_and_one_is_linear() {
+        let gray = Color::new(0.5, 0.5, 0.5, 1.0);
+        let sqrt = 0.5f32.sqrt();
+        assert_eq!(gamma_correct(gray, 2.0), Color::new(sqrt, sqrt, sqrt, 1.0));
+        assert_eq!(gamma_correct(gray, 1.0), gray);
+        assert!((gamma_correct(gray, 2.2).r - 0.5f32.powf(1.0 / 2.2)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn single_bounce_darkens_a_reflective_scene() {
+        let scene = Object::list(vec![
+            Object::plane(Point3::origin(), Vector3::y_axis(), Material::metal(Color::new(0.9, 0.9, 0.9, 1.0), 0.0)),
+            Object::sphere(point![0.0, 1.0, 0.0], 1.0, Material::metal(Color::new(0.9, 0.9, 0.9, 1.0), 0.0)),
+        ]);
+        let camera = CameraBuilder::new(point![0.0, 1.0, 3.0]).look_at(point![0.0, 1.0, 0.0]).build();
+        let brightness = |bounces| {
+            let settings = RenderSettings { max_diffuse_bounces: bounces, max_specular_bounces: bounces, ..Default::default() };
+            let mut pixels = vec![RGBA8::default(); 16 * 12];
+            render_into(&mut pixels, (16, 12), &camera, &scene, &settings);
+            pixels.into_iter().map(|pixel| Color::from(pixel).luminance()).sum::<f32>()
+        };
+
+        // the mirrored ground seen in the mirror ball needs a second bounce to reach the sky.
+        let (single, full) = (brightness(1), brightness(50));
+        assert!(single < 0.9 * full, "{} with a single bounce against {}", single, full);
+    }
+
+    #[test]
+    fn error_map_cools_as_samples_accumulate() {
+        let mut rng = Rng::with_seed(12);
+        let mut stats = LuminanceStats::default();
+        let mut errors = Vec::new();
+        for taken in 1..=256 {
+            // a pixel half covered by a bright surface.
+            stats.push(if rng.bool() { 1.0 } else { 0.0 });
+            if [4, 16, 64, 256].contains(&taken) {
+                errors.push(standard_error(stats.variance(), taken));
+            }
+        }
+        assert!(errors.windows(2).all(|pair| pair[1] < pair[0]), "{:?}", errors);
+        assert!((errors[3] - 0.5 / 16.0).abs() < 0.005, "{:?}", errors);
+
+        // the heatmap runs from red, for errors at the maximum shown, to blue.
+        let variance = Picture::new(vec![stats.variance()], (1, 1));
+        let heat = |samples| *standard_error_heatmap(&variance, samples).view().pixel(0, 0);
+        assert_eq!(heat(4), Color::new(1.0, 0.0, 0.0, 1.0));
+        assert!(heat(256).r > 0.5 && heat(256).b == 0.0, "{:?}", heat(256));
+        assert!(heat(16384).r == 0.0 && heat(16384).b > 0.5, "{:?}", heat(16384));
+    }
+
+    #[test]
+    fn russian_roulette_keeps_the_mean() {
+        let gray = Material::lambert(Color::new(0.5, 0.5, 0.5, 1.0));
+        let scene = Object::list(vec![
+            Object::plane(Point3::origin(), Vector3::y_axis(), gray.clone()),
+            Object::sphere(point![0.0, 1.0, 0.0], 1.0, gray),
+        ]);
+        let ray = Ray::new(point![1.2, 3.0, 0.5], vector![0.0, -1.0, 0.0]);
+        let mean = |russian_roulette| {
+            let settings = RenderSettings { russian_roulette, background: Background::SolidColor(Color::WHITE), ..Default::default() };
+            let mut rng = Rng::with_seed(13);
+            let samples = 20_000;
+            let sum: f32 = (0..samples)
+                .map(|_| render_ray(&ray, &scene, &settings, Bounces { diffuse: 50, specular: 50 }, 0, &mut rng).r)
+                .sum();
+            sum / samples as f32
+        };
+
+        let (with, without) = (mean(Some(RussianRoulette { from_depth: 1 })), mean(None));
+        assert!((with - without).abs() < 0.02 * without, "{} with roulette against {}", with, without);
+    }
+
+    #[test]
+    fn adaptive_sampling_spends_samples_on_edges() {
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0]).fov_deg(40.0).build();
+        let viewport = camera.viewport(8, 8);
+        // the card's edge crosses the fourth column, as in `variance_is_high_only_across_edges`.
+        let card = Object::rect_xy(point![-10.0, -10.0], point![0.1, 10.0], 0.0, Material::lambert(Color::BLACK));
+        let settings = RenderSettings { background: Background::SolidColor(Color::WHITE), ..Default::default() };
+        let samples = |x| render_pixel_adaptive(point![x, 3], &viewport, &card, 4, 256, 0.05, &settings).1;
+
+        assert_eq!(samples(0), 4);
+        assert_eq!(samples(7), 4);
+        assert!(samples(3) > 64, "{} samples across the edge", samples(3));
+    }
+
+    #[test]
+    fn stratified_offsets_cover_every_stratum_once() {
+        let pattern = StratifiedPattern::new(4);
+        let mut rng = Rng::with_seed(15);
+        let offsets = pattern.sample_offsets(&mut rng).into_owned();
+        assert_eq!(offsets.len(), pattern.sample_count());
+
+        let mut strata: Vec<_> = offsets.iter()
+            .inspect(|offset| assert!((0.0..1.0).contains(&offset.x) && (0.0..1.0).contains(&offset.y), "{}", offset))
+            .map(|offset| ((offset.x * 4.0) as u32, (offset.y * 4.0) as u32))
+            .collect();
+        strata.sort();
+        strata.dedup();
+        assert_eq!(strata.len(), 16);
+        assert_ne!(pattern.sample_offsets(&mut rng).into_owned(), offsets);
+    }
+
+    #[test]
+    fn three_stacked_quads_overdraw_three_times() {
+        let glassy = Material::lambert(Color::new(1.0, 1.0, 1.0, 0.3));
+        let quads = Object::list((0..3)
+            .map(|i| Object::rect_xy(point![-1.0, -1.0], point![1.0, 1.0], -(i as f32), glassy.clone()))
+            .collect());
+        let settings = RenderSettings { transparency: true, mode: RenderMode::Overdraw, ..Default::default() };
+
+        assert_eq!(overdraw(&Ray::new(point![0.0, 0.0, 5.0], -Vector3::z()), &quads, &settings), 3);
+        assert_eq!(overdraw(&Ray::new(point![3.0, 0.0, 5.0], -Vector3::z()), &quads, &settings), 0);
+    }
+
+    #[test]
+    fn time_budget_stops_accumulation_early() {
+        let scene = random_scene(&RandomSceneOptions::default(), &mut Rng::with_seed(1));
+        let camera = CameraBuilder::new(point![13.0, 2.0, 3.0]).fov_deg(20.0).build();
+        let frame = Mutex::new(Frame::<RGBA8>::from_buffer(vec![0; 32 * 24 * 4], (32, 24)));
+        let mut accumulation = Accumulation::default();
+        let budget = Duration::from_millis(50);
+        let settings = RenderSettings { time_budget: Some(budget), ..Default::default() };
+
+        let start = Instant::now();
+        accumulate_frame_until_async(&frame, &mut accumulation, &camera, &scene, &settings, usize::MAX);
+        let elapsed = start.elapsed();
+
+        assert!(elapsed >= budget);
+        // without the budget it would never stop; a single pass takes well under the slack.
+        assert!(elapsed < budget + Duration::from_secs(5), "stopped after {:?}", elapsed);
+        assert!(accumulation.sample_count() > 0);
+        let frame = frame.lock().unwrap();
+        assert!(frame.bytes().iter().any(|&byte| byte != 0));
+    }
+
+    #[test]
+    fn clay_mode_keeps_emitters_lit() {
+        let scene = Object::list(vec![
+            Object::plane(Point3::origin(), Vector3::y_axis(), Material::lambert(Color::WHITE)),
+            Object::rect_xz(point![-2.0, -2.0], point![2.0, 2.0], 3.0, Material::diffuse_light(Color::new(4.0, 4.0, 4.0, 1.0))),
+        ]);
+        let render = |ray: &Ray| {
+            let settings = RenderSettings { clay: true, background: Background::SolidColor(Color::BLACK), ..Default::default() };
+            let mut rng = Rng::with_seed(16);
+            (0..64).map(|_| render_ray(ray, &scene, &settings, Bounces { diffuse: 4, specular: 4 }, 0, &mut rng).r).sum::<f32>()
+        };
+
+        // the light itself, and the ground lit by nothing else.
+        assert!(render(&Ray::new(point![0.0, 1.0, 0.0], Vector3::y())) > 0.0);
+        assert!(render(&Ray::new(point![0.5, 2.0, 0.5], -Vector3::y())) > 0.0);
+    }
+
+
+    #[test]
+    fn matcap_is_looked_up_in_the_camera_view_space() {
+        let texels = (0..9).map(|i| Color::new(i as f32 / 10.0, 0.0, 0.0, 1.0)).collect();
+        let texture = Arc::new(Picture::new(texels, (3, 3)));
+        let wall = Object::plane(Point3::origin(), Unit::new_normalize(Vector3::z()), Material::matcap(texture));
+        let camera = CameraBuilder::new(point![0.0, 0.0, 5.0]).look_at(Point3::origin()).fov_deg(90.0).build();
+        let viewport = camera.viewport(8, 8);
+        let settings = RenderSettings { gamma: 1.0, ..Default::default() };
+
+        // the wall faces the camera straight on, even where the rays hitting it are oblique.
+        for p in [point![0, 0], point![7, 0], point![3, 4], point![7, 7]] {
+            let color = render_pixel(p, &viewport, &wall, &SINGLE_SAMPLE_PATTERN, &settings);
+            assert!((color.r - 0.4).abs() < 1e-3, "{:?} sampled {}", p, color.r);
+        }
+    }
+
+
+    #[test]
+    fn camera_near_plane_sees_out_of_enclosing_objects() {
+        let objects = [
+            Object::sphere(point![0.0, 0.0, 5.0], 1.0, Material::lambert(Color::WHITE)),
+            Object::sphere(Point3::origin(), 1.0, Material::lambert(Color::WHITE)),
+        ];
+        let settings = RenderSettings::default();
+
+        let inside = CameraBuilder::new(point![0.0, 0.0, 5.0]).build();
+        assert_eq!(*render_object_ids(&inside, &objects, (8, 8), &settings).view().pixel(4, 4), Some(0));
+        let outside = CameraBuilder::new(point![0.0, 0.0, 5.0]).near(1.5).build();
+        assert_eq!(*render_object_ids(&outside, &objects, (8, 8), &settings).view().pixel(4, 4), Some(1));
+    }
+
+
+    #[test]
+    fn lambert_samplers_share_the_cosine_distribution() {
+        let normal = vector![0.0, 0.6, 0.8];
+        let mut rng = Rng::with_seed(11);
+        type Sampler = fn(&mut Rng, &Vector3<f32>) -> Vector3<f32>;
+        for sampler in [random_cosine_direction as Sampler, random_lambert_direction] {
+            let cosines: Vec<f32> = (0..20000).map(|_| sampler(&mut rng, &normal).normalize().dot(&normal)).collect();
+            assert!(cosines.iter().all(|&cosine| cosine >= -1e-5));
+            // the mean cosine of a cosine weighted hemisphere is 2 / 3.
+            let mean = cosines.iter().sum::<f32>() / cosines.len() as f32;
+            assert!((mean - 2.0 / 3.0).abs() < 0.01, "{}", mean);
+        }
+    }
+
+
+    #[test]
+    fn merged_luminance_stats_match_pushing_every_sample() {
+        let samples = [0.1, 0.9, 0.4, 0.0, 2.0, 0.3, 0.7];
+        let mut all = LuminanceStats::default();
+        samples.iter().for_each(|&sample| all.push(sample));
+        let (mut first, mut second) = (LuminanceStats::default(), LuminanceStats::default());
+        samples[..3].iter().for_each(|&sample| first.push(sample));
+        samples[3..].iter().for_each(|&sample| second.push(sample));
+        first.merge(&second);
+
+        assert_eq!(first.count, all.count);
+        assert!((first.mean - all.mean).abs() < 1e-6);
+        assert!((first.variance() - all.variance()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn accumulated_error_map_cools_with_every_pass() {
+        let scene = random_scene(&RandomSceneOptions::default(), &mut Rng::with_seed(1));
+        let camera = CameraBuilder::new(point![13.0, 2.0, 3.0]).fov_deg(20.0).build();
+        let frame = Mutex::new(Frame::<RGBA8>::from_buffer(vec![0; 16 * 12 * 4], (16, 12)));
+        let mut accumulation = Accumulation::default();
+        let settings = RenderSettings { mode: RenderMode::StandardError, samples: SamplePatternKind::Stratified4x4, ..Default::default() };
+
+        // red for the largest errors, fading through yellow and green to blue.
+        let heat = |frame: &Mutex<Frame<RGBA8>>| {
+            let frame = frame.lock().unwrap();
+            frame.bytes().chunks(4).map(|pixel| pixel[0] as f32 + pixel[1] as f32 - pixel[2] as f32).sum::<f32>()
+        };
+        let mut heats = Vec::new();
+        for _ in 0..4 {
+            accumulate_frame_async(&frame, &mut accumulation, &camera, &scene, &settings);
+            heats.push(heat(&frame));
+        }
+        assert_eq!(accumulation.sample_count(), 64);
+        assert!(heats.windows(2).all(|pair| pair[1] < pair[0]), "{:?}", heats);
+    }
+
+
+    #[test]
+    fn sampling_area_lights_reduces_noise_without_bias() {
+        let scene = Object::list(vec![
+            Object::plane(Point3::origin(), Vector3::y_axis(), Material::lambert(Color::WHITE)),
+            // a small light facing down onto the plane.
+            Object::area_light(point![-0.25, 2.0, -0.25], vector![0.5, 0.0, 0.0], vector![0.0, 0.0, 0.5], Color::WHITE, 20.0),
+        ]);
+        let statistics = |settings: &RenderSettings| {
+            let ray = Ray::new(point![0.5, 1.0, 0.0], vector![0.0, -1.0, 0.1]);
+            let mut rng = Rng::with_seed(8);
+            let mut stats = LuminanceStats::default();
+            for _ in 0..20000 {
+                stats.push(render_ray(&ray, &scene, settings, Bounces { diffuse: 2, specular: 2 }, 0, &mut rng).luminance());
+            }
+            (stats.mean, stats.variance())
+        };
+        let background = Background::SolidColor(Color::BLACK);
+        let bsdf = statistics(&RenderSettings { background: background.clone(), ..Default::default() });
+        let lights = statistics(&RenderSettings { background, lights: scene.lights(), ..Default::default() });
+
+        assert_eq!(scene.lights().len(), 1);
+        assert!((lights.0 - bsdf.0).abs() < 0.05 * bsdf.0, "{:?} {:?}", lights, bsdf);
+        assert!(lights.1 < bsdf.1 / 10.0, "{:?} {:?}", lights, bsdf);
+    }
+
+
+    #[test]
+    fn sphere_lights_cast_soft_shadows() {
+        // fraction of the light reaching points across the edge of the shadow of a half plane.
+        let lit_fractions = |radius: f32| {
+            let light = Object::sphere(point![0.0, 4.0, 0.0], radius, Material::diffuse_light(Color::WHITE));
+            let floor = Object::plane(Point3::origin(), Vector3::y_axis(), Material::lambert(Color::WHITE));
+            let blocker = Object::rect_xz(point![-10.0, -10.0], point![0.0, 10.0], 2.0, Material::lambert(Color::BLACK));
+            let open = Object::list(vec![floor.clone(), light.clone()]);
+            let shadowed = Object::list(vec![floor, light, blocker]);
+            let brightness = |scene: &Object, x: f32| {
+                let settings = RenderSettings { background: Background::SolidColor(Color::BLACK), lights: scene.lights(), ..Default::default() };
+                let ray = Ray::new(point![x, 1.0, 0.0], -Vector3::y());
+                let mut rng = Rng::with_seed(6);
+                (0..500).map(|_| render_ray(&ray, scene, &settings, Bounces { diffuse: 1, specular: 0 }, 0, &mut rng).luminance()).sum::<f32>()
+            };
+            [-0.7, -0.5, -0.3, -0.1, 0.1, 0.3, 0.5, 0.7].map(|x| brightness(&shadowed, x) / brightness(&open, x))
+        };
+        let in_penumbra = |fractions: [f32; 8]| fractions.iter().filter(|&&fraction| (0.05..0.95).contains(&fraction)).count();
+
+        // a light all but a point leaves the shadow's edge sharp, while a wide one blurs it.
+        let point = lit_fractions(0.01);
+        let sphere = lit_fractions(1.0);
+        assert_eq!(in_penumbra(point), 0, "{:?}", point);
+        assert!(in_penumbra(sphere) >= 6, "{:?}", sphere);
+        assert!(sphere.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", sphere);
+    }
 }
@@ -0,0 +1,378 @@
+use std::f32::consts::PI;
+use std::iter::once;
+use std::ops::{Range, RangeBounds};
+
+use nalgebra::{point, Similarity3, Vector3};
+
+use crate::camera::{Camera, CameraDirection, Projection};
+use crate::material::Material;
+use crate::object::Object;
+use crate::picture::Color;
+use crate::ray::{Hit, Ray};
+use crate::render::{Bounces, render_ray, RenderSettings, Rng};
+use crate::texture::Checker;
+
+/// Geometry together with the camera viewing it.
+#[derive(Clone, Debug)]
+pub struct Scene {
+    pub world: Object,
+    pub camera: Camera,
+}
+
+impl Scene {
+    /// The nearest surface hit by the ray within the range.
+    pub fn raycast(&self, ray: &Ray, range: impl RangeBounds<f32> + Clone) -> Option<Hit<'_>> {
+        self.world.hit(ray, range)
+    }
+
+    /// Every surface hit by the ray within the range, ordered by distance.
+    pub fn raycast_all(&self, ray: &Ray, range: impl RangeBounds<f32> + Clone) -> Vec<Hit<'_>> {
+        self.world.hit_all(ray, range)
+    }
+
+    /// Linear light arriving along a single ray, as a single sample of the path tracer computes it,
+    /// bouncing at most `max_bounces` times off each kind of surface. The same seed always gives
+    /// the same result.
+    pub fn trace(&self, ray: &Ray, max_bounces: u32, seed: u64) -> Color {
+        let settings = RenderSettings {
+            max_diffuse_bounces: max_bounces,
+            max_specular_bounces: max_bounces,
+            lights: self.world.lights(),
+            ..Default::default()
+        };
+        let bounces = Bounces { diffuse: max_bounces, specular: max_bounces };
+        render_ray(ray, &self.world, &settings, bounces, 0, &mut Rng::with_seed(seed))
+    }
+}
+
+/// Places an object, expected to sit around the origin, on a ground checkered in unit squares and
+/// frames it with a camera, for quickly previewing a material.
+pub fn test_scene(object: Object) -> Scene {
+    // the checker's sines vanish at y = 0, so the ground sits a hair below it.
+    let ground = Object::plane(
+        point![0.0, -1e-3, 0.0],
+        Vector3::y_axis(),
+        Material::textured(Checker::colors(Color::new(0.8, 0.8, 0.8, 1.0), Color::new(0.3, 0.3, 0.3, 1.0), PI)),
+    );
+
+    let position = point![0.0, 1.5, 6.0];
+    let look_at = point![0.0, 0.5, 0.0];
+    let camera = Camera::new(
+        position,
+        CameraDirection::LookAt { look_at, up: Vector3::y_axis() },
+        30.0,
+        0.0,
+        (position - look_at).magnitude(),
+    );
+
+    Scene {
+        world: Object::list(vec![ground, object]),
+        camera,
+    }
+}
+
+/// Relative likelihood of each material being picked for the small spheres of the random scene.
+#[derive(Clone, Debug)]
+pub struct RandomSceneOptions {
+    pub diffuse: f32,
+    pub metal: f32,
+    pub glass: f32,
+}
+
+impl Default for RandomSceneOptions {
+    fn default() -> Self {
+        RandomSceneOptions {
+            diffuse: 0.8,
+            metal: 0.15,
+            glass: 0.05,
+        }
+    }
+}
+
+/// Final scene of Ray Tracing in One Weekend: a field of small spheres of random materials around
+/// three large ones, laid out by `rng`.
+pub fn random_scene(options: &RandomSceneOptions, rng: &mut Rng) -> Object {
+    let random_in = |rng: &mut Rng, range: Range<f32>| rng.f32() * (range.end - range.start) + range.start;
+    let total = options.diffuse + options.metal + options.glass;
+    let mut spheres = Vec::new();
+    for (a, b) in (-11..11).flat_map(|a| (-11..11).map(move |b| (a, b))) {
+        let center = point![a as f32 + 0.9 * rng.f32(), 0.2, b as f32 + 0.9 * rng.f32()];
+        if (center - point![4.0, 0.2, 0.0]).magnitude() <= 0.9 {
+            continue;
+        }
+        let material = rng.f32() * total;
+        let material = if material < options.diffuse {
+            // diffuse
+            let color = Color::new(
+                rng.f32() * rng.f32(),
+                rng.f32() * rng.f32(),
+                rng.f32() * rng.f32(),
+                1.0,
+            );
+            Material::lambert(color)
+        } else if material < options.diffuse + options.metal {
+            // metal
+            let albedo = Color::new(
+                random_in(rng, 0.5..1.0),
+                random_in(rng, 0.5..1.0),
+                random_in(rng, 0.5..1.0),
+                1.0,
+            );
+            let fuzz = random_in(rng, 0.0..0.5);
+            Material::metal(albedo, fuzz)
+        } else {
+            // glass
+            Material::dielectric(1.5)
+        };
+        spheres.push(Object::sphere(center, 0.2, material));
+    }
+    let ground = Object::sphere(
+        point![0.0, -1000.0, 0.0],
+        1000.0,
+        Material::lambert(Color::new(0.5, 0.5, 0.5, 1.0)),
+    );
+    Object::bvh(
+        once(ground)
+            .chain(spheres)
+            .chain([
+                Object::sphere(
+                    point![0.0, 1.0, 0.0],
+                    1.0,
+                    Material::dielectric(1.5),
+                ),
+                Object::sphere(
+                    point![-4.0, 1.0, 0.0],
+                    1.0,
+                    Material::lambert(Color::new(0.4, 0.2, 0.1, 1.0)),
+                ),
+                Object::sphere(
+                    point![4.0, 1.0, 0.0],
+                    1.0,
+                    Material::metal(Color::new(0.7, 0.6, 0.5, 1.0), 0.0),
+                ),
+            ])
+            .collect()
+    )
+}
+
+/// Named node of a `SceneGraph`, positioned relative to its parent.
+#[derive(Clone, Debug)]
+pub struct SceneNode {
+    pub name: String,
+    pub object: Option<Object>,
+    pub transform: Similarity3<f32>,
+    pub children: Vec<SceneNode>,
+}
+
+impl SceneNode {
+    pub fn new(name: impl Into<String>) -> Self {
+        SceneNode {
+            name: name.into(),
+            object: None,
+            transform: Similarity3::identity(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_object(mut self, object: Object) -> Self {
+        self.object = Some(object);
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Similarity3<f32>) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_child(mut self, child: SceneNode) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// Depth-first search for the first node with the given name, including this one.
+    pub fn find(&self, name: &str) -> Option<&SceneNode> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find(name))
+    }
+
+    fn flatten_into(&self, parent: &Similarity3<f32>, objects: &mut Vec<Object>) {
+        let world = parent * self.transform;
+        if let Some(object) = &self.object {
+            objects.push(Object::transform(world, object.clone()));
+        }
+        for child in &self.children {
+            child.flatten_into(&world, objects);
+        }
+    }
+}
+
+/// Hierarchy of named nodes for building scenes programmatically.
+#[derive(Clone, Debug)]
+pub struct SceneGraph {
+    pub root: SceneNode,
+}
+
+impl SceneGraph {
+    pub fn new(root: SceneNode) -> Self {
+        SceneGraph { root }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&SceneNode> {
+        self.root.find(name)
+    }
+
+    /// Produces the object tree consumed by the renderer, with every node's geometry placed in world
+    /// space.
+    pub fn flatten(&self) -> Object {
+        let mut objects = Vec::new();
+        self.root.flatten_into(&Similarity3::identity(), &mut objects);
+        Object::list(objects)
+    }
+}
+
+/// Removes the parts of an object which would appear less than `min_size` pixels across when
+/// viewed through the camera at the given image height, as they cost intersection tests while
+/// contributing little to the image. Lists are culled element by element, while anything else is
+/// kept or removed as a whole. Unbounded objects are always kept.
+pub fn cull_small_objects(object: &Object, camera: &Camera, image_height: u32, min_size: f32) -> Object {
+    cull(object, camera, image_height as f32, min_size).unwrap_or(Object::list(Vec::new()))
+}
+
+fn cull(object: &Object, camera: &Camera, image_height: f32, min_size: f32) -> Option<Object> {
+    if let Object::List(list) = object {
+        let kept = list.iter()
+            .filter_map(|object| cull(object, camera, image_height, min_size))
+            .collect::<Vec<_>>();
+        return (!kept.is_empty()).then(|| Object::list(kept));
+    }
+
+    let Some(bounds) = object.bounding_box() else {
+        return Some(object.clone());
+    };
+    let diameter = bounds.size().magnitude();
+    let projected = match camera.projection {
+        Projection::Perspective => {
+            let distance = (bounds.center() - camera.position).magnitude() - diameter / 2.0;
+            if distance <= 0.0 {
+                return Some(object.clone());
+            }
+            diameter / distance * image_height / (2.0 * (camera.fov_deg.to_radians() / 2.0).tan())
+        }
+        Projection::Orthographic { height } => diameter * image_height / height,
+    };
+    (projected >= min_size).then(|| object.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Point3, Translation3, UnitQuaternion};
+
+    use crate::camera::CameraBuilder;
+    use crate::object::Sphere;
+
+    use super::*;
+
+    fn small_spheres<'a>(object: &'a Object, spheres: &mut Vec<&'a Sphere>) {
+        match object {
+            Object::Sphere(sphere) if sphere.radius == 0.2 => spheres.push(sphere),
+            Object::List(list) => list.iter().for_each(|object| small_spheres(object, spheres)),
+            Object::Bvh { left, right, .. } => {
+                small_spheres(left, spheres);
+                small_spheres(right, spheres);
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_scene_holds_object_and_ground() {
+        let scene = test_scene(Object::sphere(point![0.0, 0.5, 0.0], 0.5, Material::dielectric(1.5)));
+        let Object::List(list) = &scene.world else { panic!("not a list") };
+        assert_eq!(list.len(), 2);
+        assert!(matches!(&list[1], Object::Sphere(sphere) if sphere.radius == 0.5));
+    }
+
+    #[test]
+    fn test_scene_ground_is_checkered() {
+        let scene = test_scene(Object::list(Vec::new()));
+        let albedo = |x: f32, z: f32| {
+            let hit = scene.raycast(&Ray::new(point![x, 1.0, z], -Vector3::y()), 0.0..).expect("ground");
+            hit.material.base_albedo(&hit)
+        };
+
+        assert_ne!(albedo(0.5, 0.5), albedo(1.5, 0.5));
+        assert_eq!(albedo(0.5, 0.5), albedo(1.5, 1.5));
+    }
+
+    #[test]
+    fn certain_glass_gives_only_dielectric_spheres() {
+        let options = RandomSceneOptions { diffuse: 0.0, metal: 0.0, glass: 1.0 };
+        let scene = random_scene(&options, &mut Rng::with_seed(3));
+        let mut spheres = Vec::new();
+        small_spheres(&scene, &mut spheres);
+
+        assert!(spheres.len() > 400);
+        assert!(spheres.iter().all(|sphere| matches!(sphere.material, Material::Dielectric { .. })));
+    }
+
+    #[test]
+    fn raycast_all_finds_both_sides_of_a_sphere_in_order() {
+        let scene = test_scene(Object::sphere(point![0.0, 0.5, 0.0], 0.5, Material::dielectric(1.5)));
+        let hits = scene.raycast_all(&Ray::new(point![-5.0, 0.5, 0.0], Vector3::x()), 0.0..);
+
+        let points: Vec<_> = hits.iter().map(|hit| hit.point.x).collect();
+        assert_eq!(points.len(), 2);
+        assert!((points[0] + 0.5).abs() < 1e-4 && (points[1] - 0.5).abs() < 1e-4, "{:?}", points);
+    }
+
+    #[test]
+    fn nested_translations_compose() {
+        let translation = |x, y, z| Similarity3::from_parts(Translation3::new(x, y, z), UnitQuaternion::identity(), 1.0);
+        let graph = SceneGraph::new(SceneNode::new("root")
+            .with_transform(translation(1.0, 0.0, 0.0))
+            .with_child(SceneNode::new("leaf")
+                .with_transform(translation(0.0, 2.0, 0.0))
+                .with_object(Object::sphere(Point3::origin(), 0.5, Material::lambert(Color::WHITE)))));
+        let world = graph.flatten();
+
+        let hit = world.hit(&Ray::new(point![1.0, 2.0, 5.0], -Vector3::z()), 0.0..).expect("leaf sphere");
+        assert!((hit.point - point![1.0, 2.0, 0.5]).magnitude() < 1e-4, "{}", hit.point);
+        assert!(world.hit(&Ray::new(point![0.0, 0.0, 5.0], -Vector3::z()), 0.0..).is_none());
+    }
+
+    #[test]
+    fn tiny_distant_sphere_is_culled() {
+        let camera = CameraBuilder::new(Point3::new(0.0, 0.0, 5.0)).look_at(Point3::origin()).build();
+        let scene = Object::list(vec![
+            Object::sphere(Point3::new(0.0, 0.0, -1000.0), 0.01, Material::lambert(Color::WHITE)),
+            Object::sphere(Point3::origin(), 1.0, Material::lambert(Color::WHITE)),
+        ]);
+
+        let culled = cull_small_objects(&scene, &camera, 480, 1.0);
+        let Object::List(kept) = culled else { panic!("not a list") };
+        assert_eq!(kept.len(), 1);
+        assert!(matches!(&kept[0], Object::Sphere(sphere) if sphere.radius == 1.0));
+    }
+
+    #[test]
+    fn ray_at_a_red_sphere_is_tinted_by_its_albedo() {
+        let albedo = Color::new(0.8, 0.1, 0.1, 1.0);
+        let scene = Scene {
+            world: Object::sphere(Point3::origin(), 1.0, Material::lambert(albedo)),
+            camera: CameraBuilder::new(Point3::new(0.0, 0.0, 5.0)).build(),
+        };
+        let ray = Ray::new(Point3::new(0.0, 5.0, 0.0), -Vector3::y());
+
+        assert_eq!(scene.trace(&ray, 8, 3), scene.trace(&ray, 8, 3));
+        let samples = 256;
+        let sum: Color = (0..samples).map(|seed| scene.trace(&ray, 8, seed)).sum();
+        let mean = sum * (1.0 / samples as f32);
+        // the sky is at most white, and the top of the sphere sees most of it.
+        for (channel, limit) in [(mean.r, albedo.r), (mean.g, albedo.g), (mean.b, albedo.b)] {
+            assert!(channel <= limit + 1e-4, "{:?}", mean);
+        }
+        assert!(mean.r > 0.4 && mean.r > 4.0 * mean.g.max(mean.b), "{:?}", mean);
+    }
+}
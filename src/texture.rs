@@ -0,0 +1,234 @@
+use std::fmt::Debug;
+use std::path::Path;
+
+use nalgebra::{Point3, Vector3};
+
+use crate::picture::{Color, Picture, RGBA8};
+
+/// Color varying across a surface.
+pub trait Texture: Debug + Send + Sync {
+    /// Color at the given point, with `u` and `v` its coordinates across the surface.
+    fn value(&self, u: f32, v: f32, point: &Point3<f32>) -> Color;
+}
+
+/// The same color everywhere.
+#[derive(Copy, Clone, Debug)]
+pub struct SolidColor(pub Color);
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f32, _v: f32, _point: &Point3<f32>) -> Color {
+        self.0
+    }
+}
+
+/// Alternates between two textures in a 3D checkerboard pattern, with cells `PI / scale` wide.
+#[derive(Debug)]
+pub struct Checker {
+    pub even: Box<dyn Texture>,
+    pub odd: Box<dyn Texture>,
+    pub scale: f32,
+}
+
+impl Checker {
+    pub fn new(even: impl Texture + 'static, odd: impl Texture + 'static, scale: f32) -> Self {
+        Checker { even: Box::new(even), odd: Box::new(odd), scale }
+    }
+
+    /// Checkerboard of two solid colors.
+    pub fn colors(even: Color, odd: Color, scale: f32) -> Self {
+        Checker::new(SolidColor(even), SolidColor(odd), scale)
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f32, v: f32, point: &Point3<f32>) -> Color {
+        let sines = (self.scale * point.x).sin() * (self.scale * point.y).sin() * (self.scale * point.z).sin();
+        if sines < 0.0 {
+            self.odd.value(u, v, point)
+        } else {
+            self.even.value(u, v, point)
+        }
+    }
+}
+
+/// Shown where a texture has nothing to sample.
+const MISSING_TEXTURE: Color = Color::new(1.0, 0.0, 1.0, 1.0);
+
+/// Picture stretched over the surface, with `u` running left to right and `v` bottom to top.
+#[derive(Debug)]
+pub struct ImageTexture {
+    /// Rows of the picture, starting from the top.
+    picture: Picture<Vec<RGBA8>>,
+}
+
+impl ImageTexture {
+    pub fn new(picture: Picture<Vec<RGBA8>>) -> Self {
+        ImageTexture { picture }
+    }
+
+    /// Decodes an image file, such as a PNG or JPEG.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.into_rgba8();
+        let size = image.dimensions();
+        let pixels = image.pixels()
+            .map(|pixel| RGBA8::new(pixel[0], pixel[1], pixel[2], pixel[3]))
+            .collect();
+        Ok(ImageTexture::new(Picture::new(pixels, size)))
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _point: &Point3<f32>) -> Color {
+        let picture = self.picture.view();
+        let (width, height) = (picture.width(), picture.height());
+        if width == 0 || height == 0 {
+            return MISSING_TEXTURE;
+        }
+        let x = ((u.clamp(0.0, 1.0) * width as f32) as u32).min(width - 1);
+        let y = (((1.0 - v.clamp(0.0, 1.0)) * height as f32) as u32).min(height - 1);
+        (*picture.pixel(x, y)).into()
+    }
+}
+
+const PERLIN_POINTS: usize = 256;
+/// Seed of the random lattice, so noise looks the same every run.
+const PERLIN_SEED: u64 = 0x5eed;
+
+/// Gradient noise on a lattice of random unit vectors.
+#[derive(Clone, Debug)]
+pub struct Perlin {
+    gradients: Vec<Vector3<f32>>,
+    permutations: [Vec<usize>; 3],
+}
+
+impl Perlin {
+    pub fn new() -> Self {
+        let mut rng = fastrand::Rng::with_seed(PERLIN_SEED);
+        let gradients = (0..PERLIN_POINTS)
+            .map(|_| {
+                let vector = Vector3::from_fn(|_, _| rng.f32() * 2.0 - 1.0);
+                vector.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::x)
+            })
+            .collect();
+        let mut permutation = || {
+            let mut indices: Vec<usize> = (0..PERLIN_POINTS).collect();
+            rng.shuffle(&mut indices);
+            indices
+        };
+        let permutations = [permutation(), permutation(), permutation()];
+        Perlin { gradients, permutations }
+    }
+
+    /// Smoothly varying value in roughly `-1..1`.
+    pub fn noise(&self, point: &Point3<f32>) -> f32 {
+        let cell = point.map(f32::floor);
+        let fraction = point - cell;
+        // lattice indices wrap around, including below zero.
+        let index = |axis: usize, offset: usize| {
+            self.permutations[axis][(cell[axis] as i32 + offset as i32) as usize & (PERLIN_POINTS - 1)]
+        };
+        let mut corners = [[[Vector3::zeros(); 2]; 2]; 2];
+        for (di, plane) in corners.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    *corner = self.gradients[index(0, di) ^ index(1, dj) ^ index(2, dk)];
+                }
+            }
+        }
+
+        // hermite smoothing hides the lattice.
+        let smooth = fraction.map(|f| f * f * (3.0 - 2.0 * f));
+        let mut sum = 0.0;
+        for (i, plane) in corners.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, gradient) in row.iter().enumerate() {
+                    let corner = Vector3::new(i as f32, j as f32, k as f32);
+                    let weight = corner.zip_map(&smooth, |c, s| c * s + (1.0 - c) * (1.0 - s));
+                    sum += weight.product() * gradient.dot(&(fraction - corner));
+                }
+            }
+        }
+        sum
+    }
+
+    /// Sum of `depth` octaves of noise, each at twice the frequency and half the weight of the
+    /// previous one.
+    pub fn turbulence(&self, point: &Point3<f32>, depth: u32) -> f32 {
+        let mut sum = 0.0;
+        let mut point = *point;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            sum += weight * self.noise(&point);
+            weight *= 0.5;
+            point *= 2.0;
+        }
+        sum.abs()
+    }
+}
+
+impl Default for Perlin {
+    fn default() -> Self {
+        Perlin::new()
+    }
+}
+
+/// Octaves of noise summed by `NoiseTexture`.
+const TURBULENCE_DEPTH: u32 = 7;
+
+/// Grayscale marble-like veins running along the z axis, closer together with a larger scale.
+#[derive(Clone, Debug)]
+pub struct NoiseTexture {
+    pub scale: f32,
+    perlin: Perlin,
+}
+
+impl NoiseTexture {
+    pub fn new(scale: f32) -> Self {
+        NoiseTexture { scale, perlin: Perlin::new() }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f32, _v: f32, point: &Point3<f32>) -> Color {
+        let value = 0.5 * (1.0 + (self.scale * point.z + 10.0 * self.perlin.turbulence(point, TURBULENCE_DEPTH)).sin());
+        Color::new(value, value, value, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_corners_sample_their_pixels() {
+        let pixels = vec![
+            RGBA8::new(255, 0, 0, 255),
+            RGBA8::new(0, 255, 0, 255),
+            RGBA8::new(0, 0, 255, 255),
+            RGBA8::new(255, 255, 255, 255),
+        ];
+        let texture = ImageTexture::new(Picture::new(pixels.clone(), (2, 2)));
+        let sample = |u, v| texture.value(u, v, &Point3::origin());
+
+        assert_eq!(sample(0.0, 1.0), pixels[0].into());
+        assert_eq!(sample(1.0, 1.0), pixels[1].into());
+        assert_eq!(sample(0.0, 0.0), pixels[2].into());
+        assert_eq!(sample(1.0, 0.0), pixels[3].into());
+        assert_eq!(sample(-3.0, 7.0), pixels[0].into());
+        assert_eq!(ImageTexture::new(Picture::new(Vec::new(), (0, 0))).value(0.5, 0.5, &Point3::origin()), MISSING_TEXTURE);
+    }
+
+    #[test]
+    fn noise_is_deterministic_and_within_unit_range() {
+        let texture = NoiseTexture::new(4.0);
+        let other = NoiseTexture::new(4.0);
+        let mut rng = fastrand::Rng::with_seed(9);
+        for _ in 0..1000 {
+            let point = Point3::from(Vector3::from_fn(|_, _| rng.f32() * 20.0 - 10.0));
+            let value = texture.value(0.0, 0.0, &point);
+            assert_eq!(value, texture.value(0.0, 0.0, &point));
+            assert_eq!(value, other.value(0.0, 0.0, &point));
+            assert!((0.0..=1.0).contains(&value.r) && value.r == value.g && value.g == value.b, "{:?}", value);
+        }
+    }
+}
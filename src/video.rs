@@ -0,0 +1,95 @@
+//! Encoding of rendered frames into a video by piping them through an `ffmpeg` process, which must
+//! be installed and on the `PATH`.
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+use crate::picture::{Picture, RGBA8};
+
+/// Video being written one frame at a time. The container and codec are chosen by `ffmpeg` from
+/// the output path's extension, such as `.mp4` or `.webm`.
+pub struct VideoEncoder {
+    process: Child,
+    input: ChildStdin,
+    size: (u32, u32),
+    frames: usize,
+}
+
+impl VideoEncoder {
+    /// Starts encoding a video of frames of the given size, shown at `frame_rate` frames per second.
+    pub fn new(path: impl AsRef<Path>, size: (u32, u32), frame_rate: f32) -> io::Result<Self> {
+        let (width, height) = size;
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgba"])
+            .args(["-s", &format!("{}x{}", width, height)])
+            .args(["-r", &frame_rate.to_string()])
+            .args(["-i", "-", "-pix_fmt", "yuv420p"])
+            .arg(path.as_ref());
+        VideoEncoder::spawn(command, size)
+    }
+
+    /// Starts the process the frames are piped into, as rows of raw RGBA pixels from the top.
+    fn spawn(mut command: Command, size: (u32, u32)) -> io::Result<Self> {
+        let mut process = command.stdin(Stdio::piped()).spawn()?;
+        let input = process.stdin.take().expect("piped stdin");
+        Ok(VideoEncoder { process, input, size, frames: 0 })
+    }
+
+    /// Appends a frame, which must be of the size given when the encoder was created.
+    pub fn push_frame(&mut self, frame: &Picture<&[RGBA8]>) -> io::Result<()> {
+        assert_eq!((frame.width(), frame.height()), self.size, "video frame size");
+        // pictures start at the bottom row, videos at the top.
+        for y in (0..frame.height()).rev() {
+            let row: Vec<RGBA8> = (0..frame.width()).map(|x| *frame.pixel(x, y)).collect();
+            self.input.write_all(bytemuck::cast_slice(&row))?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Number of frames pushed so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Waits for the video to be fully written.
+    pub fn finish(self) -> io::Result<()> {
+        let VideoEncoder { mut process, input, .. } = self;
+        drop(input);
+        let status = process.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("ffmpeg exited with {}", status)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoder_receives_every_frame_top_row_first() {
+        let path = std::env::temp_dir().join(format!("ray-tracing-{}-video.rgba", std::process::id()));
+        // stands in for ffmpeg, keeping the raw input.
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!("cat > '{}'", path.display()));
+        let mut encoder = VideoEncoder::spawn(command, (4, 2)).unwrap();
+
+        for frame in 0..3u8 {
+            let pixels: Vec<_> = (0..8).map(|i| RGBA8::new(frame, i, 0, 255)).collect();
+            encoder.push_frame(&Picture::new(&pixels[..], (4, 2))).unwrap();
+        }
+        assert_eq!(encoder.frames(), 3);
+        encoder.finish().unwrap();
+
+        let raw = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(raw.len(), 3 * 4 * 2 * 4);
+        // the first pixel written is the top left one, the fifth of the bottom-first picture.
+        assert_eq!(raw[..4], [0, 4, 0, 255]);
+        assert_eq!(raw[2 * 32..2 * 32 + 4], [2, 4, 0, 255]);
+    }
+}